@@ -0,0 +1,330 @@
+//! Rules deciding which apps' clips are captured.
+//!
+//! A rule matches the foreground window's process name, its title, or
+//! either, by substring, glob (`*` and `?`) or regex, ignoring case. Ignore
+//! rules drop matching clips. In allowlist mode only clips matching an allow
+//! rule are captured, and ignore rules still apply on top of that.
+//!
+//! The listener keeps the active profile's rules compiled, and compiles them
+//! again when they are saved or another profile becomes active, so changes
+//! apply from the next clip.
+
+use crate::clipboard::ForegroundWindow;
+use crate::database::Database;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Settings key holding the JSON-encoded rules.
+pub const RULES_SETTING_KEY: &str = "app_rules";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    /// Everything not ignored.
+    #[default]
+    All,
+    /// Only apps matching an allow rule.
+    Allowlist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternKind {
+    Substring,
+    Glob,
+    Regex,
+}
+
+/// Which part of the foreground window a rule looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchField {
+    Process,
+    Title,
+    Either,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleList {
+    Ignore,
+    Allow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppRule {
+    pub pattern: String,
+    pub kind: PatternKind,
+    pub field: MatchField,
+    pub list: RuleList,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppRules {
+    pub mode: CaptureMode,
+    pub rules: Vec<AppRule>,
+}
+
+impl AppRules {
+    /// Load the rules from settings; none are set by default.
+    pub fn load(db: &Database) -> Self {
+        Self::read(db).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Self::default()
+        })
+    }
+
+    /// Load and compile the rules for the listener. Rules that cannot be read
+    /// may have been an allowlist, so they capture nothing rather than
+    /// falling back to capturing everything.
+    pub fn load_compiled(db: &Database) -> CompiledRules {
+        match Self::read(db) {
+            Ok(rules) => rules.compile(),
+            Err(e) => {
+                eprintln!("{}, capturing nothing", e);
+                CompiledRules { mode: CaptureMode::Allowlist, ..CompiledRules::default() }
+            }
+        }
+    }
+
+    fn read(db: &Database) -> Result<Self, String> {
+        match db.get_setting(RULES_SETTING_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).map_err(|e| format!("Invalid app rules: {}", e)),
+            Ok(None) => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read app rules: {}", e)),
+        }
+    }
+
+    /// Store the rules, refusing any whose pattern does not compile.
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        for rule in &self.rules {
+            if rule.pattern.trim().is_empty() {
+                return Err("Pattern is empty".to_string());
+            }
+            Matcher::new(rule)?;
+        }
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        db.set_setting(RULES_SETTING_KEY, &json).map_err(|e| e.to_string())
+    }
+
+    /// Apps ignored the way the old ignored apps list did it: substring
+    /// ignore rules on the process name or window title.
+    pub fn ignored_apps(&self) -> Vec<String> {
+        self.rules.iter().filter(|rule| is_ignored_app(rule)).map(|rule| rule.pattern.clone()).collect()
+    }
+
+    pub fn add_ignored_app(&mut self, app_name: &str) {
+        if !self.rules.iter().any(|rule| is_ignored_app(rule) && rule.pattern == app_name) {
+            self.rules.push(AppRule {
+                pattern: app_name.to_string(),
+                kind: PatternKind::Substring,
+                field: MatchField::Either,
+                list: RuleList::Ignore,
+            });
+        }
+    }
+
+    pub fn remove_ignored_app(&mut self, app_name: &str) {
+        self.rules.retain(|rule| !(is_ignored_app(rule) && rule.pattern == app_name));
+    }
+
+    /// Compile the patterns for the listener. `save` refuses invalid ones, so
+    /// a rule that does not compile was edited by hand; it is skipped.
+    pub fn compile(&self) -> CompiledRules {
+        let mut compiled = CompiledRules { mode: self.mode, ..CompiledRules::default() };
+        for rule in &self.rules {
+            let matcher = match Matcher::new(rule) {
+                Ok(matcher) => matcher,
+                Err(e) => {
+                    eprintln!("Skipping app rule: {}", e);
+                    continue;
+                }
+            };
+            match rule.list {
+                RuleList::Allow => compiled.allow.push(matcher),
+                RuleList::Ignore => compiled.ignore.push(matcher),
+            }
+        }
+        compiled
+    }
+}
+
+fn is_ignored_app(rule: &AppRule) -> bool {
+    rule.kind == PatternKind::Substring && rule.field == MatchField::Either && rule.list == RuleList::Ignore
+}
+
+/// Rules ready to be checked against every clip.
+#[derive(Debug, Default)]
+pub struct CompiledRules {
+    mode: CaptureMode,
+    allow: Vec<Matcher>,
+    ignore: Vec<Matcher>,
+}
+
+impl CompiledRules {
+    /// Whether clips from `window` are captured.
+    pub fn allows(&self, window: &ForegroundWindow) -> bool {
+        if self.mode == CaptureMode::Allowlist && !self.allow.iter().any(|matcher| matcher.matches(window)) {
+            return false;
+        }
+        !self.ignore.iter().any(|matcher| matcher.matches(window))
+    }
+}
+
+/// A rule's pattern, compiled.
+#[derive(Debug)]
+struct Matcher {
+    regex: Regex,
+    field: MatchField,
+}
+
+impl Matcher {
+    fn new(rule: &AppRule) -> Result<Self, String> {
+        let pattern = match rule.kind {
+            PatternKind::Substring => regex::escape(&rule.pattern),
+            PatternKind::Glob => glob_to_regex(&rule.pattern),
+            PatternKind::Regex => rule.pattern.clone(),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern '{}': {}", rule.pattern, e))?;
+        Ok(Self { regex, field: rule.field })
+    }
+
+    fn matches(&self, window: &ForegroundWindow) -> bool {
+        let is_match = |value: &Option<String>| value.as_deref().is_some_and(|value| self.regex.is_match(value));
+        match self.field {
+            MatchField::Process => is_match(&window.process),
+            MatchField::Title => is_match(&window.title),
+            MatchField::Either => is_match(&window.process) || is_match(&window.title),
+        }
+    }
+}
+
+/// Globs match the whole name, so `firefox*` does not match
+/// `my-firefox-helper`.
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(process: Option<&str>, title: Option<&str>) -> ForegroundWindow {
+        ForegroundWindow { process: process.map(str::to_string), title: title.map(str::to_string) }
+    }
+
+    fn rule(pattern: &str, kind: PatternKind, field: MatchField, list: RuleList) -> AppRule {
+        AppRule { pattern: pattern.to_string(), kind, field, list }
+    }
+
+    #[test]
+    fn globs_match_the_whole_name() {
+        assert_eq!(glob_to_regex("firefox*"), "^firefox.*$");
+        assert_eq!(glob_to_regex("a?.exe"), r"^a.\.exe$");
+        assert_eq!(glob_to_regex("(x)+"), r"^\(x\)\+$");
+
+        let matcher = Matcher::new(&rule("code*", PatternKind::Glob, MatchField::Process, RuleList::Allow)).unwrap();
+        assert!(matcher.matches(&window(Some("Code.exe"), None)));
+        assert!(!matcher.matches(&window(Some("vscode"), None)));
+    }
+
+    #[test]
+    fn ignore_rules_match_any_case_by_field() {
+        let rules = AppRules {
+            mode: CaptureMode::All,
+            rules: vec![
+                rule("keepass", PatternKind::Substring, MatchField::Either, RuleList::Ignore),
+                rule("*private browsing*", PatternKind::Glob, MatchField::Title, RuleList::Ignore),
+                rule(r"^1password(\.exe)?$", PatternKind::Regex, MatchField::Process, RuleList::Ignore),
+            ],
+        }
+        .compile();
+
+        assert!(rules.allows(&window(None, None)));
+        assert!(!rules.allows(&window(Some("KeePassXC"), None)));
+        assert!(!rules.allows(&window(Some("explorer"), Some("Passwords.kdbx - KEEPASS"))));
+        assert!(!rules.allows(&window(Some("firefox"), Some("Mozilla Firefox — Private Browsing"))));
+        assert!(rules.allows(&window(Some("firefox"), Some("Mozilla Firefox"))));
+        assert!(rules.allows(&window(Some("private browsing"), None)));
+        assert!(!rules.allows(&window(Some("1Password.exe"), None)));
+        assert!(rules.allows(&window(Some("my-1password-helper"), None)));
+        assert!(rules.allows(&window(Some("notes"), Some("1password"))));
+    }
+
+    #[test]
+    fn allowlist_captures_only_allowed_apps() {
+        let mut rules = AppRules {
+            mode: CaptureMode::Allowlist,
+            rules: vec![rule("*private*", PatternKind::Glob, MatchField::Title, RuleList::Ignore)],
+        };
+        assert!(!rules.compile().allows(&window(Some("code"), None)));
+
+        rules.rules.push(rule("code*", PatternKind::Glob, MatchField::Process, RuleList::Allow));
+        let compiled = rules.compile();
+        assert!(compiled.allows(&window(Some("Code.exe"), Some("main.rs"))));
+        assert!(!compiled.allows(&window(Some("firefox"), None)));
+        assert!(!compiled.allows(&window(None, None)));
+        assert!(!compiled.allows(&window(Some("code"), Some("Private notes"))));
+    }
+
+    #[test]
+    fn ignored_apps_edit_only_their_own_rules() {
+        let mut rules = AppRules {
+            mode: CaptureMode::All,
+            rules: vec![rule("keepass*", PatternKind::Glob, MatchField::Process, RuleList::Ignore)],
+        };
+        rules.add_ignored_app("Bitwarden");
+        rules.add_ignored_app("Bitwarden");
+        rules.add_ignored_app("keepass*");
+        assert_eq!(rules.ignored_apps(), vec!["Bitwarden", "keepass*"]);
+        assert!(!rules.compile().allows(&window(Some("bitwarden.exe"), None)));
+
+        rules.remove_ignored_app("keepass*");
+        rules.remove_ignored_app("Bitwarden");
+        assert!(rules.ignored_apps().is_empty());
+        assert_eq!(rules.rules, vec![rule("keepass*", PatternKind::Glob, MatchField::Process, RuleList::Ignore)]);
+    }
+
+    #[test]
+    fn invalid_rules_are_refused_on_save_and_skipped_on_load() {
+        let dir = std::env::temp_dir().join(format!("clipstream-app-rules-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(&dir.join("clipboard.db")).unwrap();
+
+        let mut rules = AppRules {
+            mode: CaptureMode::All,
+            rules: vec![rule("keepass", PatternKind::Substring, MatchField::Either, RuleList::Ignore)],
+        };
+        rules.save(&db).unwrap();
+        assert_eq!(AppRules::load(&db).rules, rules.rules);
+
+        rules.rules.push(rule("(", PatternKind::Regex, MatchField::Title, RuleList::Ignore));
+        assert!(rules.save(&db).is_err());
+        rules.rules[1] = rule("  ", PatternKind::Substring, MatchField::Title, RuleList::Ignore);
+        assert!(rules.save(&db).is_err());
+
+        rules.rules[1] = rule("(", PatternKind::Regex, MatchField::Title, RuleList::Ignore);
+        let compiled = rules.compile();
+        assert!(!compiled.allows(&window(Some("keepassxc"), None)));
+        assert!(compiled.allows(&window(None, Some("("))));
+
+        db.set_setting(RULES_SETTING_KEY, "{\"mode\": \"allowlist\", \"rules\": 1}").unwrap();
+        assert!(AppRules::load(&db).rules.is_empty());
+        assert!(!AppRules::load_compiled(&db).allows(&window(Some("code"), Some("main.rs"))));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,246 @@
+//! Portable history archive read and written by the `export_history` and
+//! `import_history` commands and the `export` / `import` CLI subcommands.
+//!
+//! An archive is a single UTF-8 JSON document:
+//!
+//! ```json
+//! {
+//!   "format": "clipstream-archive",
+//!   "version": 1,
+//!   "exported_at": "2024-05-01 12:00:00",
+//!   "entries": [
+//!     {
+//!       "content": "hello world",
+//!       "content_type": "code",
+//!       "language": "rust",
+//!       "source_app": "firefox",
+//!       "created_at": "2024-04-30 09:15:00",
+//!       "is_pinned": true,
+//!       "tags": ["work"]
+//!     },
+//!     {
+//!       "content": "[Image 640x480]",
+//!       "content_type": "image",
+//!       "source_app": null,
+//!       "created_at": "2024-04-30 10:02:11",
+//!       "is_pinned": false,
+//!       "tags": [],
+//!       "image": "iVBORw0KGgo..."
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Timestamps are UTC as `YYYY-MM-DD HH:MM:SS`; RFC 3339 is also accepted on
+//! import. `image` is the base64 PNG of an image entry and is omitted for
+//! everything else. Entries are written oldest first. Archives are plaintext
+//! even when the history is encrypted.
+
+use crate::database::Database;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub const ARCHIVE_FORMAT: &str = "clipstream-archive";
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Archive {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub content: String,
+    pub content_type: String,
+    /// Detected language of `code` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub source_app: Option<String>,
+    pub created_at: String,
+    #[serde(default)]
+    pub is_pinned: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Base64 encoded PNG, only for image entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Which entries to export. Dates are local `YYYY-MM-DD` (or `today` /
+/// `yesterday`) with the same meaning as the `after:` and `before:` search
+/// filters; `app` matches the source app case-insensitively as a substring.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportFilter {
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub app: Option<String>,
+    pub pinned_only: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Entries already in the history, matched by content hash.
+    pub duplicates: usize,
+}
+
+/// Write the entries selected by `filter` to `path`. Returns how many were written.
+pub fn export_to(db: &Database, path: &Path, filter: &ExportFilter) -> Result<usize, String> {
+    let entries = db.export_entries(filter)?;
+    let count = entries.len();
+    let archive = Archive {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now().format(TIMESTAMP_FORMAT).to_string(),
+        entries,
+    };
+
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &archive).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/// Add the entries of the archive at `path`, skipping ones already present.
+pub fn import_from(db: &Database, path: &Path) -> Result<ImportSummary, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let archive: Archive = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Not a ClipStream archive: {}", e))?;
+
+    if archive.format != ARCHIVE_FORMAT {
+        return Err(format!("Not a ClipStream archive: unknown format '{}'", archive.format));
+    }
+    if archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "Archive version {} is newer than the latest supported version {}. Please update ClipStream.",
+            archive.version, ARCHIVE_VERSION
+        ));
+    }
+
+    let mut entries = archive.entries;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.created_at = normalize_timestamp(&entry.created_at)
+            .ok_or_else(|| format!("Entry {} has an invalid created_at '{}'", i + 1, entry.created_at))?;
+        if (entry.content_type == "image") != entry.image.is_some() {
+            return Err(format!("Entry {} has content type '{}' but image data does not match", i + 1, entry.content_type));
+        }
+    }
+
+    db.import_entries(&entries)
+}
+
+/// Convert an archive timestamp to the form SQLite stores.
+fn normalize_timestamp(value: &str) -> Option<String> {
+    if let Ok(dt) = NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT) {
+        return Some(dt.format(TIMESTAMP_FORMAT).to_string());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc).format(TIMESTAMP_FORMAT).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use std::path::PathBuf;
+
+    fn png() -> Vec<u8> {
+        let mut png = Vec::new();
+        image::GrayImage::from_fn(8, 4, |x, y| image::Luma([(x * 30 + y) as u8]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    fn write_archive(dir: &Path, version: u32, entries: serde_json::Value) -> PathBuf {
+        let path = dir.join(format!("handmade-{}.json", version));
+        let archive = serde_json::json!({
+            "format": ARCHIVE_FORMAT,
+            "version": version,
+            "exported_at": "2024-05-01 12:00:00",
+            "entries": entries,
+        });
+        std::fs::write(&path, archive.to_string()).unwrap();
+        path
+    }
+
+    fn exported(db: &Database) -> serde_json::Value {
+        serde_json::to_value(db.export_entries(&ExportFilter::default()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn archives_round_trip_and_skip_duplicates() {
+        let dir = std::env::temp_dir().join(format!("clipstream-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let source = Database::open(&dir.join("source.db")).unwrap();
+        let target = Database::open(&dir.join("target.db")).unwrap();
+
+        let code = source.insert("fn main() {\n    println!(\"hi\");\n}", Some("code"), None).unwrap();
+        source.set_pinned(code, true).unwrap();
+        let tag = source.create_tag("Work").unwrap();
+        source.tag_entries(tag.id, &[code]).unwrap();
+        let image = png();
+        source.insert("[Image 8x4]", None, Some(&image)).unwrap();
+        source.insert("plain note", None, None).unwrap();
+
+        let path = dir.join("history.json");
+        assert_eq!(export_to(&source, &path, &ExportFilter::default()).unwrap(), 3);
+        let summary = import_from(&target, &path).unwrap();
+        assert_eq!((summary.imported, summary.duplicates), (3, 0));
+        assert_eq!(exported(&target), exported(&source));
+
+        let entries = target.export_entries(&ExportFilter::default()).unwrap();
+        assert_eq!(entries[0].tags, vec!["Work"]);
+        assert!(entries[0].is_pinned);
+        assert_eq!(entries[1].image.as_deref().map(|data| STANDARD.decode(data).unwrap()), Some(image));
+
+        let again = import_from(&target, &path).unwrap();
+        assert_eq!((again.imported, again.duplicates), (0, 3));
+        let pinned_only = ExportFilter { pinned_only: true, ..ExportFilter::default() };
+        assert_eq!(export_to(&source, &path, &pinned_only).unwrap(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn imports_check_version_timestamps_and_images() {
+        let dir = std::env::temp_dir().join(format!("clipstream-archive-checks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(&dir.join("clipboard.db")).unwrap();
+        let entry = |created_at: &str, content_type: &str, image: Option<&str>| serde_json::json!({
+            "content": "hello",
+            "content_type": content_type,
+            "created_at": created_at,
+            "image": image,
+        });
+
+        let rfc3339 = write_archive(&dir, 1, serde_json::json!([entry("2024-04-30T11:15:00+02:00", "text", None)]));
+        assert_eq!(import_from(&db, &rfc3339).unwrap().imported, 1);
+        assert_eq!(db.export_entries(&ExportFilter::default()).unwrap()[0].created_at, "2024-04-30 09:15:00");
+
+        let newer = write_archive(&dir, ARCHIVE_VERSION + 1, serde_json::json!([]));
+        assert!(import_from(&db, &newer).unwrap_err().contains("newer than the latest supported version"));
+
+        let invalid = write_archive(&dir, 1, serde_json::json!([entry("yesterday", "text", None)]));
+        assert!(import_from(&db, &invalid).unwrap_err().contains("invalid created_at"));
+        let missing = write_archive(&dir, 1, serde_json::json!([entry("2024-04-30 09:15:00", "image", None)]));
+        assert!(import_from(&db, &missing).unwrap_err().contains("image data does not match"));
+        let stray = write_archive(&dir, 1, serde_json::json!([entry("2024-04-30 09:15:00", "text", Some("AAAA"))]));
+        assert!(import_from(&db, &stray).unwrap_err().contains("image data does not match"));
+        assert_eq!(db.export_entries(&ExportFilter::default()).unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::database::Database;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Settings key holding the JSON-encoded backup policy.
+pub const POLICY_SETTING_KEY: &str = "backup_policy";
+
+/// Snapshots live in this directory next to the database file.
+const BACKUP_DIR: &str = "backups";
+const FILE_PREFIX: &str = "clipboard-";
+const FILE_SUFFIX: &str = ".db";
+const NAME_TIME_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+
+/// How often the worker checks whether a snapshot is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// When and how many snapshots of the database to keep.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPolicy {
+    pub enabled: bool,
+    pub interval_hours: u64,
+    /// Number of snapshots kept; older ones are deleted after each backup.
+    pub keep: usize,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+impl BackupPolicy {
+    /// Load the policy from settings, falling back to the defaults.
+    pub fn load(db: &Database) -> Self {
+        match db.get_setting(POLICY_SETTING_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Invalid backup policy, using defaults: {}", e);
+                Self::default()
+            }),
+            Ok(None) => Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read backup policy: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        db.set_setting(POLICY_SETTING_KEY, &json).map_err(|e| e.to_string())
+    }
+}
+
+/// A snapshot in the backups directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub name: String,
+    /// UTC, `YYYY-MM-DD HH:MM:SS` like entry timestamps.
+    pub created_at: String,
+    pub size: u64,
+}
+
+pub fn backup_dir(db: &Database) -> PathBuf {
+    db.path()
+        .parent()
+        .map(|parent| parent.join(BACKUP_DIR))
+        .unwrap_or_else(|| PathBuf::from(BACKUP_DIR))
+}
+
+/// Take a snapshot now, then delete all but the newest `keep`.
+pub fn create_backup(db: &Database, keep: usize) -> Result<BackupInfo, String> {
+    let info = snapshot(db)?;
+    rotate(db, keep)?;
+    Ok(info)
+}
+
+fn snapshot(db: &Database) -> Result<BackupInfo, String> {
+    let dir = backup_dir(db);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let now = Utc::now().naive_utc();
+    let name = format!("{}{}{}", FILE_PREFIX, now.format(NAME_TIME_FORMAT), FILE_SUFFIX);
+    let path = dir.join(&name);
+
+    // Write under a temporary name so a crash never leaves a half snapshot
+    // that looks complete
+    let partial = dir.join(format!("{}.partial", name));
+    db.backup_to(&partial).map_err(|e| e.to_string())?;
+    fs::rename(&partial, &path).map_err(|e| e.to_string())?;
+
+    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    Ok(BackupInfo {
+        name,
+        created_at: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+        size,
+    })
+}
+
+/// Snapshots in the backups directory, newest first.
+pub fn list_backups(db: &Database) -> Result<Vec<BackupInfo>, String> {
+    let dir = backup_dir(db);
+    let read_dir = match fs::read_dir(&dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut backups: Vec<BackupInfo> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let created_at = parse_name(&name)?;
+            Some(BackupInfo {
+                created_at: created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                name,
+            })
+        })
+        .collect();
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+/// Restore the snapshot `name` over the live database. The current state is
+/// snapshotted first so a restore can itself be undone.
+pub fn restore_backup(db: &Database, name: &str) -> Result<(), String> {
+    // Only names from `list_backups` are accepted, never arbitrary paths
+    if parse_name(name).is_none() || name.contains(['/', '\\']) {
+        return Err(format!("Unknown backup '{}'", name));
+    }
+    let path = backup_dir(db).join(name);
+    if !path.is_file() {
+        return Err(format!("Unknown backup '{}'", name));
+    }
+
+    snapshot(db)?;
+    db.restore_from(&path)?;
+    rotate(db, BackupPolicy::load(db).keep)
+}
+
+fn rotate(db: &Database, keep: usize) -> Result<(), String> {
+    let dir = backup_dir(db);
+    for old in list_backups(db)?.into_iter().skip(keep.max(1)) {
+        if let Err(e) = fs::remove_file(dir.join(&old.name)) {
+            eprintln!("Failed to remove old backup {}: {}", old.name, e);
+        }
+    }
+    Ok(())
+}
+
+fn parse_name(name: &str) -> Option<NaiveDateTime> {
+    let stamp = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    NaiveDateTime::parse_from_str(stamp, NAME_TIME_FORMAT).ok()
+}
+
+/// Take a snapshot whenever the newest one is older than `interval_hours`.
+/// Checking against the newest file rather than a timer means restarts do
+/// not reset the schedule. The policy is re-read on every check, and `db`
+/// is called each time so backups follow the active profile.
+pub fn start_worker<F>(db: F)
+where
+    F: Fn() -> Arc<Database> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        let db = db();
+        let policy = BackupPolicy::load(&db);
+
+        if policy.enabled {
+            let due = match list_backups(&db) {
+                Ok(backups) => backups.first().and_then(|b| parse_name(&b.name)).is_none_or(|newest| {
+                    let age = Utc::now().naive_utc() - newest;
+                    age.num_seconds() >= (policy.interval_hours.max(1) * 3600) as i64
+                }),
+                Err(e) => {
+                    eprintln!("Failed to list backups: {}", e);
+                    false
+                }
+            };
+            if due {
+                if let Err(e) = create_backup(&db, policy.keep) {
+                    eprintln!("Backup error: {}", e);
+                }
+            }
+        }
+        drop(db);
+
+        thread::sleep(CHECK_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> (PathBuf, Database) {
+        let dir = std::env::temp_dir().join(format!("clipstream-backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let db = Database::open(&dir.join("clipboard.db")).unwrap();
+        (dir, db)
+    }
+
+    /// Snapshot names have millisecond precision.
+    fn backup(db: &Database, keep: usize) -> BackupInfo {
+        thread::sleep(Duration::from_millis(5));
+        create_backup(db, keep).unwrap()
+    }
+
+    fn names(db: &Database) -> Vec<String> {
+        list_backups(db).unwrap().into_iter().map(|b| b.name).collect()
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_snapshots() {
+        let (dir, db) = temp_db("rotate");
+        let taken: Vec<String> = (0..4).map(|_| backup(&db, 3).name).collect();
+        assert_eq!(names(&db), vec![taken[3].clone(), taken[2].clone(), taken[1].clone()]);
+
+        // Other files in the directory are left alone
+        fs::write(backup_dir(&db).join("notes.txt"), "mine").unwrap();
+        backup(&db, 0);
+        assert_eq!(names(&db).len(), 1, "the newest snapshot is always kept");
+        assert!(backup_dir(&db).join("notes.txt").exists());
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restoring_takes_a_snapshot_first() {
+        let (dir, db) = temp_db("restore");
+        let before = db.insert("before", None, None).unwrap();
+        let snapshot = backup(&db, 7);
+        let after = db.insert("after", None, None).unwrap();
+
+        thread::sleep(Duration::from_millis(5));
+        restore_backup(&db, &snapshot.name).unwrap();
+        assert!(db.get_by_id(before).unwrap().is_some());
+        assert!(db.get_by_id(after).unwrap().is_none());
+
+        // The state just replaced can be restored in turn
+        let backups = names(&db);
+        assert_eq!(backups.len(), 2);
+        restore_backup(&db, &backups[0]).unwrap();
+        assert_eq!(db.get_by_id(after).unwrap().unwrap().content, "after");
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_snapshots_in_the_backups_directory_are_restored() {
+        let (dir, db) = temp_db("names");
+        let id = db.insert("kept", None, None).unwrap();
+        let outside = "clipboard-20240101-120000.000.db";
+        fs::copy(db.path(), dir.join(outside)).unwrap();
+        fs::create_dir_all(backup_dir(&db)).unwrap();
+        fs::write(backup_dir(&db).join("clipboard-20240101-130000.000.db"), "not a database").unwrap();
+
+        for name in [
+            format!("../{}", outside),
+            format!("..\\{}", outside),
+            format!("{}/../../{}", outside, outside),
+            "../clipboard.db".to_string(),
+            "clipboard-20991231-000000.000.db".to_string(),
+            String::new(),
+        ] {
+            assert_eq!(restore_backup(&db, &name).unwrap_err(), format!("Unknown backup '{}'", name));
+        }
+        assert!(restore_backup(&db, "clipboard-20240101-130000.000.db").is_err());
+        assert_eq!(db.get_by_id(id).unwrap().unwrap().content, "kept");
+
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
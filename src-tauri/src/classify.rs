@@ -0,0 +1,553 @@
+//! Content classification for captured text.
+//!
+//! Short single-line values are matched against exact formats first (URLs,
+//! email addresses, UUIDs, colors, phone numbers, numbers, file paths).
+//! Longer text is checked for structured formats that can be recognised
+//! reliably (JSON, markup, markdown, shell commands), then scored against
+//! per-language patterns to tell code from prose. Anything else is text.
+
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    Text,
+    Url,
+    Email,
+    Path,
+    Color,
+    Phone,
+    Uuid,
+    Number,
+    Json,
+    Yaml,
+    Xml,
+    Html,
+    Markdown,
+    Shell,
+    Code,
+    Image,
+}
+
+impl ContentType {
+    pub const ALL: [ContentType; 16] = [
+        ContentType::Text,
+        ContentType::Url,
+        ContentType::Email,
+        ContentType::Path,
+        ContentType::Color,
+        ContentType::Phone,
+        ContentType::Uuid,
+        ContentType::Number,
+        ContentType::Json,
+        ContentType::Yaml,
+        ContentType::Xml,
+        ContentType::Html,
+        ContentType::Markdown,
+        ContentType::Shell,
+        ContentType::Code,
+        ContentType::Image,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Url => "url",
+            ContentType::Email => "email",
+            ContentType::Path => "path",
+            ContentType::Color => "color",
+            ContentType::Phone => "phone",
+            ContentType::Uuid => "uuid",
+            ContentType::Number => "number",
+            ContentType::Json => "json",
+            ContentType::Yaml => "yaml",
+            ContentType::Xml => "xml",
+            ContentType::Html => "html",
+            ContentType::Markdown => "markdown",
+            ContentType::Shell => "shell",
+            ContentType::Code => "code",
+            ContentType::Image => "image",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+}
+
+/// Programming language of a code entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Go,
+    Java,
+    CSharp,
+    C,
+    Cpp,
+    Ruby,
+    Php,
+    Sql,
+    Css,
+}
+
+impl Language {
+    pub const ALL: [Language; 13] = [
+        Language::Rust,
+        Language::Python,
+        Language::JavaScript,
+        Language::TypeScript,
+        Language::Go,
+        Language::Java,
+        Language::CSharp,
+        Language::C,
+        Language::Cpp,
+        Language::Ruby,
+        Language::Php,
+        Language::Sql,
+        Language::Css,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+            Language::TypeScript => "typescript",
+            Language::Go => "go",
+            Language::Java => "java",
+            Language::CSharp => "csharp",
+            Language::C => "c",
+            Language::Cpp => "cpp",
+            Language::Ruby => "ruby",
+            Language::Php => "php",
+            Language::Sql => "sql",
+            Language::Css => "css",
+        }
+    }
+
+    /// The stored name, or a common alias such as `js` or `c++`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = match name {
+            "rs" => "rust",
+            "py" => "python",
+            "js" => "javascript",
+            "ts" => "typescript",
+            "golang" => "go",
+            "cs" | "c#" => "csharp",
+            "c++" => "cpp",
+            "rb" => "ruby",
+            other => other,
+        };
+        Self::ALL.into_iter().find(|l| l.as_str() == name)
+    }
+}
+
+/// What a piece of text is, and for code the likely language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Classification {
+    pub content_type: ContentType,
+    pub language: Option<Language>,
+}
+
+impl Classification {
+    pub fn of(content_type: ContentType) -> Self {
+        Self { content_type, language: None }
+    }
+}
+
+/// Classify captured text.
+pub fn classify(text: &str) -> Classification {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Classification::of(ContentType::Text);
+    }
+    if !trimmed.contains('\n') {
+        if let Some(content_type) = classify_value(trimmed) {
+            return Classification::of(content_type);
+        }
+    }
+    if is_json(trimmed) {
+        return Classification::of(ContentType::Json);
+    }
+    if let Some(markup) = classify_markup(trimmed) {
+        return Classification::of(markup);
+    }
+    if is_markdown(trimmed) {
+        return Classification::of(ContentType::Markdown);
+    }
+    if is_shell(trimmed) {
+        return Classification::of(ContentType::Shell);
+    }
+    if let Some(language) = detect_language(trimmed) {
+        return Classification {
+            content_type: ContentType::Code,
+            language: Some(language),
+        };
+    }
+    if is_yaml(trimmed) {
+        return Classification::of(ContentType::Yaml);
+    }
+    Classification::of(ContentType::Text)
+}
+
+fn regex(pattern: &str) -> Regex {
+    Regex::new(pattern).unwrap()
+}
+
+fn regex_ci(pattern: &str) -> Regex {
+    RegexBuilder::new(pattern).case_insensitive(true).build().unwrap()
+}
+
+// ============================================================================
+// Single values
+// ============================================================================
+
+static URL: Lazy<Regex> = Lazy::new(|| regex_ci(r"^(?:(?:https?|ftp|file)://|www\.)\S+$"));
+static EMAIL: Lazy<Regex> = Lazy::new(|| regex_ci(r"^(?:mailto:)?[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}$"));
+static UUID: Lazy<Regex> = Lazy::new(|| regex(r"^\{?[0-9a-fA-F]{8}-(?:[0-9a-fA-F]{4}-){3}[0-9a-fA-F]{12}\}?$"));
+static COLOR: Lazy<Regex> = Lazy::new(|| {
+    regex_ci(r"^(?:#(?:[0-9a-f]{3,4}|[0-9a-f]{6}|[0-9a-f]{8})|(?:rgba?|hsla?)\(\s*[\d.]+%?\s*(?:,\s*[\d.]+%?\s*){2,3}\))$")
+});
+static PHONE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"^(?:\+\d{1,3}[\s.-]?)?(?:\(\d{1,4}\)[\s.-]?)?\d{2,4}(?:[\s.-]\d{2,4}){1,4}$")
+});
+static ISO_DATE: Lazy<Regex> = Lazy::new(|| regex(r"^\d{4}-\d{2}-\d{2}$"));
+static NUMBER: Lazy<Regex> = Lazy::new(|| {
+    regex_ci(r"^(?:[-+]?(?:\d{1,3}(?:,\d{3})+|\d+)(?:\.\d+)?(?:e[-+]?\d+)?%?|[-+]?\.\d+|0x[0-9a-f]+|0b[01]+)$")
+});
+static UNIX_PATH: Lazy<Regex> = Lazy::new(|| regex(r"^(?:~|\.{1,2})?(?:/[\w.@+~-][^/]*)+/?$"));
+static WINDOWS_PATH: Lazy<Regex> = Lazy::new(|| regex(r"^(?:[A-Za-z]:[\\/]|\\\\[\w.$-]+\\)[^<>|?*]*$"));
+
+fn classify_value(value: &str) -> Option<ContentType> {
+    if URL.is_match(value) {
+        Some(ContentType::Url)
+    } else if EMAIL.is_match(value) {
+        Some(ContentType::Email)
+    } else if UUID.is_match(value) {
+        Some(ContentType::Uuid)
+    } else if COLOR.is_match(value) {
+        Some(ContentType::Color)
+    } else if NUMBER.is_match(value) {
+        Some(ContentType::Number)
+    } else if is_phone(value) {
+        Some(ContentType::Phone)
+    } else if UNIX_PATH.is_match(value) || WINDOWS_PATH.is_match(value) {
+        Some(ContentType::Path)
+    } else {
+        None
+    }
+}
+
+/// 7 to 15 digits in phone-like groups, but not a date.
+fn is_phone(value: &str) -> bool {
+    let digits = value.chars().filter(char::is_ascii_digit).count();
+    (7..=15).contains(&digits) && PHONE.is_match(value) && !ISO_DATE.is_match(value)
+}
+
+// ============================================================================
+// Structured formats
+// ============================================================================
+
+fn is_json(text: &str) -> bool {
+    (text.starts_with('{') || text.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+}
+
+static MARKUP_START: Lazy<Regex> = Lazy::new(|| regex(r"^<(?:\?xml|!--|!DOCTYPE|[A-Za-z][\w:.-]*[\s/>])"));
+static HTML_TAG: Lazy<Regex> = Lazy::new(|| {
+    regex_ci(r"<(?:!doctype html|html|head|body|div|span|p|a|img|br|ul|ol|li|table|tr|td|h[1-6]|script|style|meta|link|form|input|button|section|nav)[\s/>]")
+});
+
+fn classify_markup(text: &str) -> Option<ContentType> {
+    if !MARKUP_START.is_match(text) || !text.ends_with('>') {
+        return None;
+    }
+    if HTML_TAG.is_match(text) && !text.starts_with("<?xml") {
+        Some(ContentType::Html)
+    } else {
+        Some(ContentType::Xml)
+    }
+}
+
+static MD_HEADING: Lazy<Regex> = Lazy::new(|| regex(r"(?m)^#{1,6} \S"));
+static MD_LIST: Lazy<Regex> = Lazy::new(|| regex(r"(?m)^\s*(?:[-*+]|\d+\.) \S"));
+static MD_LINK: Lazy<Regex> = Lazy::new(|| regex(r"!?\[[^\]\n]+\]\([^)\s]+\)"));
+static MD_EMPHASIS: Lazy<Regex> = Lazy::new(|| regex(r"\*\*[^*\n]+\*\*|__[^_\n]+__|`[^`\n]+`"));
+static MD_QUOTE: Lazy<Regex> = Lazy::new(|| regex(r"(?m)^> \S"));
+static MD_TABLE: Lazy<Regex> = Lazy::new(|| regex(r"(?m)^\|?\s*:?-{3,}:?\s*\|"));
+
+/// A fenced code block, or at least two kinds of markdown syntax.
+fn is_markdown(text: &str) -> bool {
+    if text.lines().any(|line| line.trim_start().starts_with("```")) {
+        return true;
+    }
+    let signals = [&*MD_HEADING, &*MD_LIST, &*MD_LINK, &*MD_EMPHASIS, &*MD_QUOTE, &*MD_TABLE];
+    signals.iter().filter(|signal| signal.is_match(text)).count() >= 2
+}
+
+/// Commands common enough that a line starting with one is a command even
+/// without any shell syntax.
+const STRONG_COMMANDS: &[&str] = &[
+    "sudo", "git", "npm", "npx", "yarn", "pnpm", "cargo", "rustup", "docker", "kubectl", "apt", "apt-get",
+    "brew", "pip", "pip3", "curl", "wget", "ssh", "scp", "systemctl", "chmod", "chown", "mkdir", "cd", "ls",
+];
+/// Commands that double as English words, so only count with shell syntax.
+const WEAK_COMMANDS: &[&str] = &[
+    "cat", "echo", "export", "find", "grep", "make", "mv", "cp", "rm", "tar", "touch", "python", "python3",
+    "node", "go", "kill", "sed", "awk", "source", "which", "head", "tail", "less", "man", "open", "set",
+];
+static SHELL_SYNTAX: Lazy<Regex> = Lazy::new(|| regex(r"(?:^|\s)--?\w|\||&&|>|\$\w|\s[~.]?/|=|\*"));
+
+fn is_shell(text: &str) -> bool {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && (!line.starts_with('#') || line.starts_with("#!")))
+        .collect();
+    if lines.is_empty() || lines.len() > 20 {
+        return false;
+    }
+    if text.starts_with("#!") && (text.contains("sh\n") || text.contains("bash") || text.contains("zsh")) {
+        return true;
+    }
+    lines.iter().all(|line| is_shell_line(line))
+}
+
+fn is_shell_line(line: &str) -> bool {
+    let line = line.strip_prefix("$ ").unwrap_or(line);
+    // Sentences and code statements, e.g. `export const x = 1;`
+    if line.ends_with(['.', '?', ';', '{']) {
+        return false;
+    }
+    let command = line.split_whitespace().next().unwrap_or_default();
+    let rest = &line[command.len()..];
+    if rest.is_empty() {
+        return STRONG_COMMANDS.contains(&command);
+    }
+    STRONG_COMMANDS.contains(&command) || (WEAK_COMMANDS.contains(&command) && SHELL_SYNTAX.is_match(rest))
+}
+
+static YAML_LINE: Lazy<Regex> = Lazy::new(|| regex(r#"^\s*(?:- |-$|[\w"'.-]+:(?:\s|$)|---$|#)"#));
+
+/// Lines that all look like `key: value`, list items or comments, with some
+/// nesting or at least three keys. A line ending in a full stop is prose.
+fn is_yaml(text: &str) -> bool {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let keys = lines.iter().filter(|line| line.contains(':')).count();
+    let nested = lines.iter().any(|line| line.starts_with([' ', '-']));
+    lines.len() >= 2
+        && lines.iter().all(|line| YAML_LINE.is_match(line) && !line.ends_with('.'))
+        && keys > 0
+        && (nested || keys >= 3)
+}
+
+// ============================================================================
+// Code
+// ============================================================================
+
+/// Patterns hinting at a language, with weights. A language needs a total
+/// of `MIN_SCORE`, so a single English phrase like "let me know" is not
+/// enough.
+static LANGUAGE_PATTERNS: Lazy<Vec<(Language, Regex, u32)>> = Lazy::new(|| {
+    let patterns: &[(Language, &str, u32)] = &[
+        (Language::Rust, r"\bfn\s+\w+\s*[<(]", 3),
+        (Language::Rust, r"\blet\s+(?:mut\s+)?\w+(?:\s*:\s*[\w<>&\[\], ]+)?\s*=", 2),
+        (Language::Rust, r"\b(?:pub(?:\(crate\))?\s+)?(?:struct|enum|trait|mod)\s+\w+", 1),
+        (Language::Rust, r"\bimpl\b(?:<.*>)?\s+\w+", 3),
+        (Language::Rust, r"\buse\s+(?:std|crate|super|self)::", 3),
+        (Language::Rust, r"\w+!\(|#\[\w+", 2),
+        (Language::Rust, r"&mut\s|->\s*(?:Self|Result|Option|bool|String|u\d+|i\d+|usize)\b", 2),
+        (Language::Python, r"(?m)^\s*def\s+\w+\s*\(.*\)\s*(?:->\s*[\w\[\], .]+)?:\s*$", 4),
+        (Language::Python, r"(?m)^\s*(?:from\s+[\w.]+\s+import\s+\w|import\s+\w+(?:\.\w+)*\s*$)", 3),
+        (Language::Python, r"(?m)^\s*class\s+\w+(?:\(.*\))?:\s*$", 3),
+        (Language::Python, r"\bself\.\w+|__\w+__|\bNone\b|\bTrue\b|\bFalse\b", 1),
+        (Language::Python, r"(?m)^\s*(?:if|elif|for|while|with|try|except)\b.*:\s*$", 2),
+        (Language::Python, r"\bprint\(", 1),
+        (Language::JavaScript, r"\b(?:const|let|var)\s+\w+(?:\s*:\s*[\w<>\[\]|]+)?\s*=", 2),
+        (Language::JavaScript, r"=>", 2),
+        (Language::JavaScript, r"\bfunction\s*\w*\s*\(", 3),
+        (Language::JavaScript, r"\bconsole\.\w+\(|\bdocument\.\w+|\bwindow\.\w+", 3),
+        (Language::JavaScript, r#"\brequire\(['"]|\bimport\s+.*\s+from\s+['"]|\bexport\s+(?:default|const|function|class)\b"#, 3),
+        (Language::JavaScript, r"===|!==", 2),
+        (Language::JavaScript, r"\binterface\s+\w+\s*\{|\btype\s+\w+\s*=|\w\)?\s*:\s*(?:string|number|boolean)\b", 3),
+        (Language::Go, r"(?m)^package\s+\w+\s*$", 4),
+        (Language::Go, r"\bfunc\s+(?:\(\w+\s+\*?\w+\)\s*)?\w+\s*\(", 4),
+        (Language::Go, r"\w\s*:=", 2),
+        (Language::Go, r"\bfmt\.\w+\(|\bdefer\b|\bchan\b|\bgo\s+func\b", 2),
+        (Language::Java, r"\bpublic\s+(?:static\s+)?(?:final\s+)?(?:class|void|interface)\b", 3),
+        (Language::Java, r"\bSystem\.out\.print|@Override\b|\bString\[\]", 3),
+        (Language::Java, r"\b(?:private|protected|public)\s+[\w<>\[\]]+\s+\w+\s*[;=(]", 2),
+        (Language::CSharp, r"\busing\s+System\b|\bnamespace\s+[\w.]+", 3),
+        (Language::CSharp, r"\bConsole\.Write|\{\s*get;\s*(?:set;)?\s*\}|\bvar\s+\w+\s*=\s*new\b", 3),
+        (Language::C, r#"(?m)^\s*#include\s*[<"]"#, 4),
+        (Language::C, r"\bint\s+main\s*\(|\bprintf\(|\bmalloc\(|\bsizeof\(", 3),
+        (Language::Cpp, r"\bstd::(?:cout|cin|cerr|endl|string|vector|map|unique_ptr|shared_ptr|move)\b|\bcout\s*<<|\btemplate\s*<|\bnullptr\b|\bnamespace\s+\w+\s*\{", 4),
+        (Language::Ruby, r"(?m)^\s*def\s+\w+[?!]?(?:\(.*\))?\s*$", 3),
+        (Language::Ruby, r"(?m)^\s*end\s*$", 2),
+        (Language::Ruby, r#"\bputs\b|\.each\s+do\s*\||\brequire\s+['"]|\battr_accessor\b"#, 3),
+        (Language::Php, r"<\?php", 5),
+        (Language::Php, r"\$\w+\s*=|\$this->|function\s+\w+\s*\(\s*\$", 3),
+        (Language::Sql, r"(?im)^\s*(?:select\s+(?:distinct\s+)?(?:\*|[\w.()*]+(?:\s*,\s*[\w.()*]+)*)\s+from\s+\w|insert\s+into\s+\w|update\s+\w+\s+set\s+\w|delete\s+from\s+\w|create\s+(?:table|index|view|unique\s+index)\s+\w|alter\s+table\s+\w|drop\s+table\s+\w)", 5),
+        (Language::Sql, r"(?i)\b(?:where|join|group\s+by|order\s+by|values)\b", 1),
+        (Language::Css, r"(?m)^\s*(?:[.#]?[\w-]+|\*)(?:[\s,>+~:]+[.#:]?[\w-]+)*\s*\{", 2),
+        (Language::Css, r"(?m)^\s*[\w-]+\s*:\s*[^;{}]+;\s*$", 2),
+        (Language::Css, r"@media\b|!important\b|\b\d+(?:px|em|rem|vh|vw)\b", 2),
+    ];
+    patterns
+        .iter()
+        .map(|(language, pattern, weight)| (*language, regex(pattern), *weight))
+        .collect()
+});
+
+/// TypeScript is JavaScript with type annotations.
+static TYPESCRIPT: Lazy<Regex> = Lazy::new(|| {
+    regex(r"\b(?:interface|type)\s+\w+\s*(?:=|\{)|\w\s*:\s*(?:string|number|boolean|any|void|unknown)\b|\bas\s+const\b")
+});
+
+const MIN_SCORE: u32 = 4;
+
+fn detect_language(text: &str) -> Option<Language> {
+    let mut scores = [0u32; Language::ALL.len()];
+    for (language, regex, weight) in LANGUAGE_PATTERNS.iter() {
+        if regex.is_match(text) {
+            scores[*language as usize] += weight;
+        }
+    }
+
+    // C++ is usually also C; prefer it when its own signals are present
+    if scores[Language::Cpp as usize] > 0 {
+        scores[Language::Cpp as usize] += scores[Language::C as usize];
+    }
+
+    let (best, score) = Language::ALL
+        .iter()
+        .zip(scores)
+        .max_by_key(|(_, score)| *score)?;
+    if score < MIN_SCORE {
+        return None;
+    }
+    match best {
+        Language::JavaScript if TYPESCRIPT.is_match(text) => Some(Language::TypeScript),
+        language => Some(*language),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_each_type() {
+        let cases = [
+            ("https://example.com/a?b=c", ContentType::Url),
+            ("www.rust-lang.org", ContentType::Url),
+            ("jane.doe+x@example.co.uk", ContentType::Email),
+            ("550e8400-e29b-41d4-a716-446655440000", ContentType::Uuid),
+            ("#ff8800", ContentType::Color),
+            ("rgba(10, 20, 30, 0.5)", ContentType::Color),
+            ("+1 (555) 123-4567", ContentType::Phone),
+            ("+44 20 7946 0958", ContentType::Phone),
+            ("42", ContentType::Number),
+            ("1,234,567.89", ContentType::Number),
+            ("0xFF", ContentType::Number),
+            ("/usr/local/bin/rustc", ContentType::Path),
+            ("~/Documents/My Notes/todo.txt", ContentType::Path),
+            (r"C:\Users\jane\Desktop\report.docx", ContentType::Path),
+            (r#"{"a": [1, 2, {"b": null}]}"#, ContentType::Json),
+            ("[1, 2, 3]", ContentType::Json),
+            ("<div class=\"x\"><p>Hello</p></div>", ContentType::Html),
+            ("<?xml version=\"1.0\"?>\n<note><to>Tove</to></note>", ContentType::Xml),
+            ("name: app\nversion: 1.0\ndependencies:\n  - serde", ContentType::Yaml),
+            ("---\nkey: value\nother: 2", ContentType::Yaml),
+            ("# Title\n\nSome text with a [link](https://x.y).\n\n- item one\n- item two", ContentType::Markdown),
+            ("Install it:\n\n```\ncargo install x\n```", ContentType::Markdown),
+            ("git commit -m \"fix\"", ContentType::Shell),
+            ("$ npm install --save-dev vite", ContentType::Shell),
+            ("cat /etc/hosts | grep local", ContentType::Shell),
+            ("export PATH=$HOME/bin:$PATH", ContentType::Shell),
+            ("fn main() {\n    println!(\"hi\");\n}", ContentType::Code),
+            ("hello world", ContentType::Text),
+            ("", ContentType::Text),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(classify(text).content_type, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn prose_is_text() {
+        let cases = [
+            "let me know if that works for you",
+            "Let me know when the class starts, I'll import the photos later.",
+            "The function of this committee is to define the const values of the project.",
+            "Meeting notes: discussed roadmap. Next: hiring.",
+            "Hi team,\nplease find the report attached.\nThanks,\nJane",
+            "Note: the build is broken.\nReason: missing dependency.",
+            "Make sure to find time for it.",
+            "Select the items from the list that you like",
+            "and/or",
+            "// just a comment",
+            "2024-01-05",
+            "192.168.1.1",
+            "{not json",
+        ];
+        for text in cases {
+            assert_eq!(classify(text), Classification::of(ContentType::Text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn detects_languages() {
+        let cases = [
+            ("fn main() {\n    let mut x = 5;\n    println!(\"{}\", x);\n}", Language::Rust),
+            ("use std::collections::HashMap;\nlet map: HashMap<String, i32> = HashMap::new();", Language::Rust),
+            ("def greet(name):\n    print(f\"Hello {name}\")\n    return None", Language::Python),
+            ("const add = (a, b) => a + b;\nconsole.log(add(1, 2));", Language::JavaScript),
+            ("interface User {\n  name: string;\n  age: number;\n}\nconst u: User = { name: 'a', age: 1 };", Language::TypeScript),
+            ("package main\n\nimport \"fmt\"\n\nfunc main() {\n\tx := 1\n\tfmt.Println(x)\n}", Language::Go),
+            ("public class Hello {\n    public static void main(String[] args) {\n        System.out.println(\"hi\");\n    }\n}", Language::Java),
+            ("using System;\nnamespace App {\n    class P { static void Main() { Console.WriteLine(\"hi\"); } }\n}", Language::CSharp),
+            ("#include <stdio.h>\nint main(void) {\n    printf(\"hi\\n\");\n    return 0;\n}", Language::C),
+            ("#include <iostream>\nint main() {\n    std::cout << \"hi\" << std::endl;\n}", Language::Cpp),
+            ("def greet(name)\n  puts \"Hello #{name}\"\nend", Language::Ruby),
+            ("<?php\n$name = 'x';\necho $name;", Language::Php),
+            ("SELECT id, name FROM users WHERE age > 30 ORDER BY name;", Language::Sql),
+            (".button {\n  color: red;\n  padding: 4px 8px;\n}", Language::Css),
+        ];
+        for (text, expected) in cases {
+            let classification = classify(text);
+            assert_eq!(classification.content_type, ContentType::Code, "{:?}", text);
+            assert_eq!(classification.language, Some(expected), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn one_weak_signal_is_not_code() {
+        // Each of these scores below MIN_SCORE for every language
+        for text in ["x := 1", "print(total)", "let x = 5", "a === b"] {
+            assert_eq!(detect_language(text), None, "{:?}", text);
+        }
+        assert!(detect_language("fn main() {\n    let x = 5;\n}").is_some());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for content_type in ContentType::ALL {
+            assert_eq!(ContentType::from_name(content_type.as_str()), Some(content_type));
+            assert_eq!(serde_json::to_value(content_type).unwrap(), content_type.as_str());
+        }
+        for language in Language::ALL {
+            assert_eq!(Language::from_name(language.as_str()), Some(language));
+            assert_eq!(serde_json::to_value(language).unwrap(), language.as_str());
+        }
+        assert_eq!(Language::from_name("js"), Some(Language::JavaScript));
+        assert_eq!(Language::from_name("c++"), Some(Language::Cpp));
+        assert_eq!(Language::from_name("cobol"), None);
+    }
+}
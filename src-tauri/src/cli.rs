@@ -0,0 +1,87 @@
+//! Command line subcommands that run without starting the app:
+//!
+//! ```text
+//! clipstream export <file> [--after DATE] [--before DATE] [--app NAME] [--pinned]
+//! clipstream import <file>
+//! ```
+//!
+//! A passphrase-encrypted history is unlocked with `CLIPSTREAM_PASSPHRASE`;
+//! key file histories unlock on their own.
+
+use crate::archive::{self, ExportFilter};
+use crate::database::Database;
+use std::path::Path;
+
+const PASSPHRASE_ENV: &str = "CLIPSTREAM_PASSPHRASE";
+
+const USAGE: &str = "Usage:
+  clipstream export <file> [--after YYYY-MM-DD] [--before YYYY-MM-DD] [--app NAME] [--pinned]
+  clipstream import <file>";
+
+/// Run the subcommand in `args` (without the program name). Returns the exit
+/// code, or `None` when `args` is not a subcommand and the app should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first()?;
+    let result = match command.as_str() {
+        "export" => export(&args[1..]),
+        "import" => import(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+
+    Some(match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    })
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut filter = ExportFilter::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE));
+        match arg.as_str() {
+            "--after" => filter.after = Some(value()?),
+            "--before" => filter.before = Some(value()?),
+            "--app" => filter.app = Some(value()?),
+            "--pinned" => filter.pinned_only = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+
+    let db = open_database()?;
+    let count = archive::export_to(&db, Path::new(&path), &filter)?;
+    println!("Exported {} entries to {}", count, path);
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+
+    let db = open_database()?;
+    let summary = archive::import_from(&db, Path::new(path))?;
+    println!("Imported {} entries, skipped {} duplicates", summary.imported, summary.duplicates);
+    Ok(())
+}
+
+fn open_database() -> Result<Database, String> {
+    let db = Database::new().map_err(|e| e.to_string())?;
+    if db.is_locked() {
+        let passphrase = std::env::var(PASSPHRASE_ENV)
+            .map_err(|_| format!("History is encrypted; set {} to unlock it", PASSPHRASE_ENV))?;
+        db.unlock(Some(&passphrase))?;
+    }
+    Ok(db)
+}
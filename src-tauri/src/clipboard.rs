@@ -1,6 +1,6 @@
 use arboard::{Clipboard, ImageData};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use std::borrow::Cow;
@@ -12,30 +12,68 @@ pub enum ClipboardContent {
     Image { data: Vec<u8>, width: usize, height: usize },
 }
 
+/// The window in front when a clip was captured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForegroundWindow {
+    /// Executable or application name.
+    pub process: Option<String>,
+    pub title: Option<String>,
+}
+
+impl ForegroundWindow {
+    /// Name recorded as the entry's source.
+    pub fn app_name(&self) -> Option<&str> {
+        self.process.as_deref().or(self.title.as_deref())
+    }
+}
+
+/// What the app that set the clipboard says about its contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClipboardHints {
+    /// Marked as a secret that clipboard managers should not record, as
+    /// password managers do.
+    pub concealed: bool,
+}
+
 #[derive(Debug)]
 pub struct ClipboardListener {
     running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    /// Held by the polling thread while it reads the clipboard and runs the
+    /// callback, so `pause` can wait for a clip that is being stored.
+    busy: Arc<Mutex<()>>,
 }
 
 impl ClipboardListener {
     pub fn new() -> Self {
         Self {
             running: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            busy: Arc::new(Mutex::new(())),
         }
     }
     
     pub fn start<F>(&self, on_change: F)
     where
-        F: Fn(ClipboardContent, Option<String>) + Send + 'static,
+        F: Fn(ClipboardContent, ForegroundWindow, ClipboardHints) + Send + 'static,
     {
         self.running.store(true, Ordering::SeqCst);
         let running = self.running.clone();
+        let paused = self.paused.clone();
+        let busy = self.busy.clone();
         
         thread::spawn(move || {
             let mut last_text: Option<String> = None;
             let mut last_image_hash: Option<u64> = None;
             
             while running.load(Ordering::SeqCst) {
+                let guard = busy.lock().unwrap();
+                if paused.load(Ordering::SeqCst) {
+                    drop(guard);
+                    thread::sleep(Duration::from_millis(300));
+                    continue;
+                }
+                
                 if let Ok(mut clipboard) = Clipboard::new() {
                     // Try to read text first
                     if let Ok(text) = clipboard.get_text() {
@@ -49,14 +87,14 @@ impl ClipboardListener {
                             if should_update {
                                 last_text = Some(text_trimmed.to_string());
                                 last_image_hash = None; // Reset image hash when text changes
-                                let source_app = get_foreground_app();
-                                on_change(ClipboardContent::Text(text_trimmed.to_string()), source_app);
+                                let window = get_foreground_window();
+                                on_change(ClipboardContent::Text(text_trimmed.to_string()), window, get_hints());
                             }
                         }
                     }
                     // Try to read image if no text update
                     else if let Ok(image) = clipboard.get_image() {
-                        let hash = simple_hash(&image.bytes);
+                        let hash = image_hash(&image.bytes, image.width, image.height);
                         let should_update = match last_image_hash {
                             Some(last_hash) => last_hash != hash,
                             None => true,
@@ -65,18 +103,20 @@ impl ClipboardListener {
                         if should_update {
                             last_image_hash = Some(hash);
                             last_text = None; // Reset text when image changes
-                            let source_app = get_foreground_app();
+                            let window = get_foreground_window();
                             on_change(
                                 ClipboardContent::Image {
                                     data: image.bytes.to_vec(),
                                     width: image.width,
                                     height: image.height,
                                 },
-                                source_app,
+                                window,
+                                get_hints(),
                             );
                         }
                     }
                 }
+                drop(guard);
                 
                 thread::sleep(Duration::from_millis(300));
             }
@@ -86,17 +126,29 @@ impl ClipboardListener {
     pub fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
     }
+    
+    /// Stop recording clipboard changes until `resume`, e.g. while the
+    /// database is being swapped out. Returns once a clip already being
+    /// stored is done, so none lands after this.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        drop(self.busy.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+    
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
 }
 
-/// Simple hash for image deduplication
-fn simple_hash(data: &[u8]) -> u64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    let mut hasher = DefaultHasher::new();
-    // Hash first 1KB + length for performance
-    data.len().hash(&mut hasher);
-    data.iter().take(1024).for_each(|b| b.hash(&mut hasher));
-    hasher.finish()
+/// Change detection for clipboard images. XXH3 over the full RGBA buffer is
+/// fast enough for every poll, and unlike a sampled hash it notices edits
+/// anywhere in the image.
+fn image_hash(data: &[u8], width: usize, height: usize) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(&(width as u64).to_le_bytes());
+    hasher.update(&(height as u64).to_le_bytes());
+    hasher.update(data);
+    hasher.digest()
 }
 
 pub fn set_clipboard_text(content: &str) -> Result<(), String> {
@@ -104,6 +156,34 @@ pub fn set_clipboard_text(content: &str) -> Result<(), String> {
     clipboard.set_text(content).map_err(|e| format!("Failed to set clipboard: {}", e))
 }
 
+/// Empty the clipboard if `matches` accepts the text it holds. On Linux the
+/// primary selection is checked and cleared too, since it pastes with a
+/// middle click. Returns whether anything was cleared.
+pub fn clear_clipboard_if(matches: impl Fn(&str) -> bool) -> Result<bool, String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+    let holds = |current: Result<String, arboard::Error>| current.is_ok_and(|current| matches(&current));
+    
+    let mut cleared = false;
+    if holds(clipboard.get_text()) {
+        clipboard.clear().map_err(|e| format!("Failed to clear clipboard: {}", e))?;
+        cleared = true;
+    }
+    
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::{ClearExtLinux, GetExtLinux, LinuxClipboardKind};
+        if holds(clipboard.get().clipboard(LinuxClipboardKind::Primary).text()) {
+            clipboard
+                .clear_with()
+                .clipboard(LinuxClipboardKind::Primary)
+                .map_err(|e| format!("Failed to clear primary selection: {}", e))?;
+            cleared = true;
+        }
+    }
+    
+    Ok(cleared)
+}
+
 pub fn set_clipboard_image(data: &[u8], width: usize, height: usize) -> Result<(), String> {
     let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
     let image = ImageData {
@@ -119,7 +199,7 @@ pub fn set_clipboard_image(data: &[u8], width: usize, height: usize) -> Result<(
 // ============================================================================
 
 #[cfg(target_os = "windows")]
-fn get_foreground_app() -> Option<String> {
+fn get_foreground_window() -> ForegroundWindow {
     use windows::{
         Win32::Foundation::*,
         Win32::UI::WindowsAndMessaging::*,
@@ -127,17 +207,24 @@ fn get_foreground_app() -> Option<String> {
         core::PWSTR,
     };
     
+    let mut window = ForegroundWindow::default();
     unsafe {
         let hwnd = GetForegroundWindow();
         if hwnd.0.is_null() {
-            return None;
+            return window;
+        }
+        
+        let mut title = [0u16; 512];
+        let len = GetWindowTextW(hwnd, &mut title);
+        if len > 0 {
+            window.title = Some(String::from_utf16_lossy(&title[..len as usize]));
         }
         
         let mut process_id: u32 = 0;
         GetWindowThreadProcessId(hwnd, Some(&mut process_id));
         
         if process_id == 0 {
-            return None;
+            return window;
         }
         
         let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id);
@@ -146,70 +233,180 @@ fn get_foreground_app() -> Option<String> {
             let mut size = buffer.len() as u32;
             
             if QueryFullProcessImageNameW(handle, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut size).is_ok() {
-                let _ = CloseHandle(handle);
                 let path = String::from_utf16_lossy(&buffer[..size as usize]);
-                return path.split('\\').last().map(|s| s.to_string());
+                window.process = path.split('\\').last().map(|s| s.to_string());
             }
             let _ = CloseHandle(handle);
         }
-        
-        None
     }
+    window
 }
 
 #[cfg(target_os = "macos")]
-fn get_foreground_app() -> Option<String> {
+fn get_foreground_window() -> ForegroundWindow {
     use std::process::Command;
     
-    // Use osascript to get the frontmost application name
-    let output = Command::new("osascript")
-        .args(["-e", "tell application \"System Events\" to get name of first process whose frontmost is true"])
-        .output()
-        .ok()?;
+    // The frontmost application's name, then its front window's title,
+    // which needs accessibility access and may be missing
+    let script = r#"tell application "System Events"
+        set frontApp to first process whose frontmost is true
+        set windowTitle to ""
+        try
+            set windowTitle to name of front window of frontApp
+        end try
+        return (name of frontApp) & linefeed & windowTitle
+    end tell"#;
     
-    if output.status.success() {
-        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        if !name.is_empty() {
-            return Some(name);
+    let mut window = ForegroundWindow::default();
+    if let Ok(output) = Command::new("osascript").args(["-e", script]).output() {
+        if output.status.success() {
+            let output = String::from_utf8_lossy(&output.stdout);
+            let mut lines = output.lines().map(str::trim);
+            window.process = lines.next().filter(|s| !s.is_empty()).map(str::to_string);
+            window.title = lines.next().filter(|s| !s.is_empty()).map(str::to_string);
         }
     }
-    None
+    window
 }
 
 #[cfg(target_os = "linux")]
-fn get_foreground_app() -> Option<String> {
+fn get_foreground_window() -> ForegroundWindow {
     use std::process::Command;
     
-    // Try xdotool first (X11)
-    if let Ok(output) = Command::new("xdotool")
-        .args(["getactivewindow", "getwindowname"])
-        .output()
-    {
-        if output.status.success() {
-            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !name.is_empty() {
-                return Some(name);
-            }
+    let xdotool = |command: &str| {
+        let output = Command::new("xdotool").args(["getactivewindow", command]).output().ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    };
+    
+    let title = xdotool("getwindowname");
+    let process = xdotool("getwindowpid")
+        .and_then(|pid| std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok())
+        .map(|comm| comm.trim().to_string())
+        .filter(|comm| !comm.is_empty());
+    ForegroundWindow { process, title }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+fn get_foreground_window() -> ForegroundWindow {
+    ForegroundWindow::default()
+}
+
+// ============================================================================
+// Platform-specific clipboard hints
+// ============================================================================
+
+/// Target set by KeePassXC and others next to the data, with the value
+/// `secret`, when clipboard managers should not record it.
+#[cfg(target_os = "linux")]
+const KDE_PASSWORD_HINT: &str = "x-kde-passwordManagerHint";
+
+/// Hints are read from the X11 selection's targets, which also covers
+/// Wayland apps through XWayland.
+#[cfg(target_os = "linux")]
+fn get_hints() -> ClipboardHints {
+    ClipboardHints {
+        concealed: x11_targets::is_concealed().unwrap_or(false),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_hints() -> ClipboardHints {
+    ClipboardHints::default()
+}
+
+#[cfg(target_os = "linux")]
+mod x11_targets {
+    use super::KDE_PASSWORD_HINT;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, CreateWindowAux, Window, WindowClass};
+    use x11rb::protocol::Event;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE};
+    
+    /// How long the selection owner gets to answer.
+    const TIMEOUT: Duration = Duration::from_millis(250);
+    
+    /// Whether the clipboard owner offers the password manager hint with
+    /// the value `secret`. `None` when there is no X server or no answer.
+    pub fn is_concealed() -> Option<bool> {
+        let (conn, screen) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots.get(screen)?.root;
+        let window = conn.generate_id().ok()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT, window, root, 0, 0, 1, 1, 0,
+            WindowClass::INPUT_ONLY, COPY_FROM_PARENT, &CreateWindowAux::new(),
+        ).ok()?;
+        
+        let atom = |name: &str| Some(conn.intern_atom(false, name.as_bytes()).ok()?.reply().ok()?.atom);
+        let clipboard = atom("CLIPBOARD")?;
+        let targets = atom("TARGETS")?;
+        let hint = atom(KDE_PASSWORD_HINT)?;
+        let property = atom("CLIPSTREAM_SELECTION")?;
+        
+        let offered = read_selection(&conn, window, clipboard, targets, property)?;
+        let offered: Vec<Atom> = offered.value32().map(|atoms| atoms.collect()).unwrap_or_default();
+        if !offered.contains(&hint) {
+            return Some(false);
         }
+        
+        let value = read_selection(&conn, window, clipboard, hint, property)?;
+        Some(String::from_utf8_lossy(&value.value).trim() == "secret")
     }
     
-    // Fallback: try to get from /proc for Wayland
-    if let Ok(output) = Command::new("sh")
-        .args(["-c", "cat /proc/$(xdotool getactivewindow getwindowpid)/comm 2>/dev/null"])
-        .output()
-    {
-        if output.status.success() {
-            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !name.is_empty() {
-                return Some(name);
+    /// Ask the owner of `selection` to convert it to `target` and read the
+    /// result off our window.
+    fn read_selection(
+        conn: &RustConnection,
+        window: Window,
+        selection: Atom,
+        target: Atom,
+        property: Atom,
+    ) -> Option<x11rb::protocol::xproto::GetPropertyReply> {
+        conn.convert_selection(window, selection, target, property, CURRENT_TIME).ok()?;
+        conn.flush().ok()?;
+        
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match conn.poll_for_event().ok()? {
+                Some(Event::SelectionNotify(event)) if event.requestor == window && event.target == target => {
+                    if event.property == NONE {
+                        return None;
+                    }
+                    break;
+                }
+                Some(_) => continue,
+                None if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+                None => return None,
             }
         }
+        
+        conn.get_property(true, window, property, AtomEnum::ANY, 0, u32::MAX).ok()?.reply().ok()
     }
-    
-    None
 }
 
-#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
-fn get_foreground_app() -> Option<String> {
-    None
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use arboard::SetExtLinux;
+    
+    /// Needs an X server, e.g. `xvfb-run cargo test`.
+    #[test]
+    fn reads_the_password_manager_hint() {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("DISPLAY not set, skipping");
+            return;
+        }
+        // The selection is served from this process for as long as the
+        // clipboard is alive
+        let mut clipboard = Clipboard::new().unwrap();
+        
+        clipboard.set().exclude_from_history().text("hunter2").unwrap();
+        assert_eq!(get_hints(), ClipboardHints { concealed: true });
+        
+        clipboard.set_text("hello").unwrap();
+        assert_eq!(get_hints(), ClipboardHints { concealed: false });
+    }
 }
@@ -0,0 +1,174 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::Path;
+
+const NONCE_LEN: usize = 12;
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+
+/// Known plaintext encrypted with the active key so unlock can tell a wrong
+/// passphrase from a right one without touching any history rows.
+const KEY_CHECK_PLAINTEXT: &[u8] = b"clipstream-key-check";
+
+/// Domain separation for the content hash key derived from the main key.
+const HASH_KEY_CONTEXT: &[u8] = b"clipstream-content-hash";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Where the encryption key comes from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    Passphrase(String),
+    KeyFile(String),
+}
+
+/// Authenticated encryption for history content and image blobs.
+///
+/// Ciphertext layout is `nonce || ChaCha20-Poly1305(plaintext)`.
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+    /// Separate key for `keyed_hash`, so hashes reveal nothing about the
+    /// encryption key and cannot be precomputed without it.
+    hash_key: [u8; 32],
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cipher { .. }")
+    }
+}
+
+impl Cipher {
+    pub fn from_key(key: &[u8; KEY_LEN]) -> Self {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(HASH_KEY_CONTEXT);
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+            hash_key: mac.finalize().into_bytes().into(),
+        }
+    }
+
+    /// Derive a key from a passphrase with Argon2id.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self, String> {
+        Ok(Self::from_key(&derive_key(passphrase, salt)?))
+    }
+
+    /// Read a raw 32-byte key from a file only the current user can access.
+    pub fn from_key_file(path: &Path) -> Result<Self, String> {
+        check_key_file_permissions(path)?;
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed to read key file: {}", e))?;
+        let key: [u8; KEY_LEN] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Key file must contain exactly {} bytes", KEY_LEN))?;
+        Ok(Self::from_key(&key))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .aead
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("ChaCha20-Poly1305 encryption cannot fail");
+
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if data.len() < NONCE_LEN {
+            return Err("Ciphertext too short".to_string());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Failed to decrypt: wrong key or corrupted data".to_string())
+    }
+
+    /// HMAC-SHA256 of `data` as hex, for dedup lookups on encrypted rows
+    /// where a plain hash would let anyone confirm guessed contents.
+    pub fn keyed_hash(&self, data: &[u8]) -> String {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.hash_key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn key_check(&self) -> Vec<u8> {
+        self.encrypt(KEY_CHECK_PLAINTEXT)
+    }
+
+    pub fn verify_key_check(&self, check: &[u8]) -> bool {
+        self.decrypt(check).is_ok_and(|plain| plain == KEY_CHECK_PLAINTEXT)
+    }
+}
+
+/// Stretch a passphrase into a raw key with Argon2id.
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+pub fn random_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Create a new random key file readable only by the current user.
+pub fn create_key_file(path: &Path) -> Result<(), String> {
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    use std::io::Write;
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to create key file: {}", e))?;
+    file.write_all(&key)
+        .map_err(|e| format!("Failed to write key file: {}", e))
+}
+
+#[cfg(unix)]
+fn check_key_file_permissions(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::metadata(path).map_err(|e| format!("Failed to read key file: {}", e))?;
+    let mode = metadata.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "Key file {} is accessible by other users (mode {:o}); run chmod 600 on it",
+            path.display(),
+            mode & 0o777
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_key_file_permissions(path: &Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!("Key file {} not found", path.display()));
+    }
+    Ok(())
+}
@@ -1,253 +1,891 @@
-use rusqlite::{Connection, params};
+use crate::archive::{ArchiveEntry, ExportFilter, ImportSummary};
+use crate::classify::{self, Classification, ContentType, Language};
+use crate::crypto::{self, Cipher, KeySource};
+use crate::migrations::{self, MigrationError};
+use crate::fuzzy::{self, FuzzyMatch};
+use crate::profiles;
+use crate::query::{self, CompiledQuery, SearchMode, SearchQuery, SearchSort};
+use crate::sensitive::Sensitivity;
+use crate::snippet::{self, Snippet};
+use crate::sync::{tag_key, Fingerprint, LocalEntry, SyncedEntry};
+use crate::retention::{RetentionCandidate, RetentionPolicy, RetentionReason};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::DynamicImage;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{Connection, DatabaseName, OpenFlags, OptionalExtension, Row, params, params_from_iter};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClipboardEntry {
     pub id: i64,
     pub content: String,
     pub source_app: Option<String>,
-    pub content_type: String,
+    pub content_type: ContentType,
+    pub language: Option<Language>,
     pub created_at: String,
     pub is_pinned: bool,
-    pub content_blob: Option<String>, // Base64 encoded image data
+    pub thumbnail: Option<String>, // Base64 encoded PNG thumbnail; full image via get_image
+    pub tags: Vec<String>,
+    pub use_count: i64,
+    pub last_used_at: Option<String>,
+    pub deleted_at: Option<String>, // Set while the entry is in the trash
+    pub sensitivity: Option<Sensitivity>,
+    pub expires_at: Option<String>, // Deleted by the reaper after this time
+    pub snippet: Option<Snippet>, // Only set on search hits
+}
+
+/// A page of search results. `next_offset` is `None` on the last page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub entries: Vec<ClipboardEntry>,
+    pub total: usize,
+    pub next_offset: Option<usize>,
+}
+
+impl SearchPage {
+    fn new(entries: Vec<ClipboardEntry>, offset: usize, total: usize) -> Self {
+        let end = offset + entries.len();
+        let next_offset = (!entries.is_empty() && end < total).then_some(end);
+        Self { entries, total, next_offset }
+    }
+}
+
+/// A previous version of an entry's content, recorded when it was replaced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryRevision {
+    pub id: i64,
+    pub entry_id: i64,
+    pub content: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffTag {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub tag: DiffTag,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    pub entry_count: i64,
+}
+
+/// Content address of an image blob: hex-encoded SHA-256 of the PNG bytes.
+pub fn blob_hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Address of an image blob in an encrypted history is keyed, as a plain
+/// SHA-256 would let anyone confirm a guessed image.
+fn blob_address(data: &[u8], cipher: Option<&Cipher>) -> String {
+    match cipher {
+        Some(cipher) => cipher.keyed_hash(data),
+        None => blob_hash(data),
+    }
+}
+
+/// Bounding box for list thumbnails (2x the preview's CSS size for HiDPI).
+const THUMBNAIL_MAX_WIDTH: u32 = 240;
+const THUMBNAIL_MAX_HEIGHT: u32 = 120;
+
+/// Downscale a PNG to a small PNG thumbnail, preserving aspect ratio.
+pub fn make_thumbnail(png: &[u8]) -> Option<Vec<u8>> {
+    thumbnail_of(&decode_png(png)?)
+}
+
+/// 64-bit difference hash of a PNG; see `dhash`.
+pub fn perceptual_hash(png: &[u8]) -> Option<i64> {
+    Some(dhash(&decode_png(png)?))
+}
+
+/// Hamming distance at or below which two perceptual hashes are considered
+/// the same picture, e.g. a screenshot retaken with the cursor moved.
+pub const NEAR_DUPLICATE_DISTANCE: u32 = 5;
+
+fn decode_png(png: &[u8]) -> Option<DynamicImage> {
+    image::load_from_memory_with_format(png, image::ImageFormat::Png).ok()
+}
+
+fn thumbnail_of(img: &DynamicImage) -> Option<Vec<u8>> {
+    let thumb = img.thumbnail(THUMBNAIL_MAX_WIDTH, THUMBNAIL_MAX_HEIGHT);
+    let mut bytes = Vec::new();
+    thumb
+        .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .ok()?;
+    Some(bytes)
+}
+
+/// dHash: shrink to 9x8 grayscale and record whether each pixel is darker
+/// than its right neighbour. Small edits flip only a few of the 64 bits.
+fn dhash(img: &DynamicImage) -> i64 {
+    let small = img.thumbnail_exact(9, 8).to_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash as i64
+}
+
+/// Fill `phash` for plaintext image entries that lack one. Encrypted entries
+/// are skipped: even a coarse fingerprint of a sealed image stays off disk.
+pub fn backfill_perceptual_hashes(conn: &Connection) -> rusqlite::Result<()> {
+    let rows: Vec<(i64, Vec<u8>)> = {
+        let mut stmt = conn.prepare(
+            "SELECT h.id, b.data FROM clipboard_history h
+             JOIN blobs b ON b.hash = h.blob_hash
+             WHERE h.phash IS NULL AND h.encrypted = 0 AND b.encrypted = 0"
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+    
+    for (id, data) in rows {
+        if let Some(phash) = perceptual_hash(&data) {
+            conn.execute("UPDATE clipboard_history SET phash = ?1 WHERE id = ?2", params![phash, id])?;
+        }
+    }
+    Ok(())
+}
+
+/// Column list read by `row_to_entry`; pair with `ENTRY_JOINS`.
+const ENTRY_COLUMNS: &str = "h.id, clip_text(h.content, h.encrypted), h.source_app, h.content_type, h.created_at, h.is_pinned, clip_blob(t.data, t.encrypted), h.tags, h.use_count, h.last_used_at, h.deleted_at, h.sensitivity, h.expires_at, h.language";
+const ENTRY_JOINS: &str = "LEFT JOIN blob_thumbnails t ON t.hash = h.blob_hash";
+
+/// Separator between tag names in the denormalized `clipboard_history.tags`.
+const TAG_SEPARATOR: char = '\n';
+
+/// Columns holding user content that encryption covers:
+/// `(table, key column, content column, is text)`.
+const SEALED_COLUMNS: &[(&str, &str, &str, bool)] = &[
+    ("clipboard_history", "id", "content", true),
+    ("entry_revisions", "id", "content", true),
+    ("blobs", "hash", "data", false),
+    ("blob_thumbnails", "hash", "data", false),
+    ("secrets", "key", "value", true),
+];
+
+const ENCRYPTION_MODE_KEY: &str = "encryption_mode";
+const ENCRYPTION_SALT_KEY: &str = "encryption_salt";
+const ENCRYPTION_CHECK_KEY: &str = "encryption_check";
+const ENCRYPTION_KEY_FILE_KEY: &str = "encryption_key_file";
+
+const LOCKED_MESSAGE: &str = "History is locked";
+
+const DEDUP_MODE_KEY: &str = "dedup_mode";
+
+/// `SearchMode::Auto` adds fuzzy matches when prefix search finds fewer rows.
+const FUZZY_FALLBACK_THRESHOLD: usize = 5;
+/// Trigram candidates scored per fuzzy search, best FTS rank first.
+const FUZZY_CANDIDATE_LIMIT: i64 = 500;
+/// Frecency of `h`: use count plus one, decayed by days since it was last
+/// used (or copied) with a one-week half-weight.
+const FRECENCY_SQL: &str = "((1.0 + h.use_count) / (1.0 + (julianday('now') - julianday(COALESCE(h.last_used_at, h.created_at))) / 7.0))";
+/// Tokens of context in FTS snippets.
+const SNIPPET_TOKENS: i64 = 16;
+
+/// Which copies count as the same clip when deduplicating.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// Byte-for-byte identical text.
+    #[default]
+    Exact,
+    /// Text that differs only in whitespace, e.g. a trailing newline.
+    Whitespace,
+}
+
+impl DedupMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            DedupMode::Exact => "exact",
+            DedupMode::Whitespace => "whitespace",
+        }
+    }
+}
+
+/// Dedup hash of `content`: SHA-256, or an HMAC under the history key when
+/// encrypted so the hash does not reveal guessable contents.
+fn hash_text(content: &str, mode: DedupMode, cipher: Option<&Cipher>) -> String {
+    let normalized;
+    let text = match mode {
+        DedupMode::Exact => content,
+        DedupMode::Whitespace => {
+            normalized = content.split_whitespace().collect::<Vec<_>>().join(" ");
+            normalized.as_str()
+        }
+    };
+    match cipher {
+        Some(cipher) => cipher.keyed_hash(text.as_bytes()),
+        None => blob_hash(text.as_bytes()),
+    }
+}
+
+/// UUID derived from an entry's SHA-256 content hash, so the same clip
+/// copied on two devices syncs as one entry.
+pub fn content_uuid(hash: &str) -> String {
+    let mut bytes = [0u8; 16];
+    for (byte, pair) in bytes.iter_mut().zip(hash.as_bytes().chunks(2)) {
+        *byte = std::str::from_utf8(pair).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()).unwrap_or(0);
+    }
+    uuid::Uuid::new_v8(bytes).to_string()
+}
+
+pub fn random_uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Dedup hash of an entry. Images are identified by their blob address,
+/// which already covers the full pixel data.
+fn entry_hash(content: &str, image_hash: Option<&str>, mode: DedupMode, cipher: Option<&Cipher>) -> String {
+    match (image_hash, cipher) {
+        (Some(hash), Some(cipher)) => cipher.keyed_hash(hash.as_bytes()),
+        (Some(hash), None) => hash.to_string(),
+        (None, _) => hash_text(content, mode, cipher),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub locked: bool,
+    pub mode: Option<String>, // "passphrase" or "keyfile"
 }
 
 #[derive(Debug)]
 pub struct Database {
     conn: Mutex<Connection>,
+    /// Key for encrypted rows; `None` while locked or when encryption is off.
+    /// Shared with the `clip_text`/`clip_blob` SQL functions.
+    cipher: Arc<RwLock<Option<Cipher>>>,
+    encrypted: AtomicBool,
+    path: PathBuf,
+    expiring: Mutex<ExpiringClips>,
+}
+
+/// Fingerprints of entries with an expiry, by id, so the clipboard can be
+/// cleared when they are reaped while the history is locked. Never written
+/// to disk; see `Database::clipboard_fingerprint`.
+struct ExpiringClips {
+    key: [u8; crypto::SALT_LEN],
+    by_id: HashMap<i64, String>,
+}
+
+impl std::fmt::Debug for ExpiringClips {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ExpiringClips { .. }")
+    }
+}
+
+impl ExpiringClips {
+    fn fingerprint(&self, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        hasher.update(text.trim().as_bytes());
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
 }
 
 impl Database {
-    pub fn new() -> Result<Self, rusqlite::Error> {
-        let db_path = Self::get_db_path();
+    /// Open the active profile's database.
+    pub fn new() -> Result<Self, MigrationError> {
+        Self::open(&profiles::database_path(&profiles::active()))
+    }
+    
+    pub fn open(db_path: &Path) -> Result<Self, MigrationError> {
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent).ok();
         }
         
-        let conn = Connection::open(&db_path)?;
-        
-        // Main clipboard history table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS clipboard_history (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                content TEXT NOT NULL,
-                source_app TEXT,
-                content_type TEXT DEFAULT 'text',
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                is_pinned BOOLEAN DEFAULT 0,
-                content_blob TEXT
-            );
-            
-            CREATE INDEX IF NOT EXISTS idx_created_at ON clipboard_history(created_at DESC);
-            CREATE INDEX IF NOT EXISTS idx_source_app ON clipboard_history(source_app);
-            "
-        )?;
+        let mut conn = Connection::open(db_path)?;
+        migrations::run(&mut conn)?;
         
-        // Create FTS5 virtual table if not exists
-        conn.execute_batch(
-            "CREATE VIRTUAL TABLE IF NOT EXISTS history_fts USING fts5(
-                content,
-                source_app,
-                content='clipboard_history',
-                content_rowid='id'
-            );
-            
-            CREATE TRIGGER IF NOT EXISTS history_ai AFTER INSERT ON clipboard_history BEGIN
-                INSERT INTO history_fts(rowid, content, source_app) VALUES (new.id, new.content, new.source_app);
-            END;
-            
-            CREATE TRIGGER IF NOT EXISTS history_ad AFTER DELETE ON clipboard_history BEGIN
-                INSERT INTO history_fts(history_fts, rowid, content, source_app) VALUES('delete', old.id, old.content, old.source_app);
-            END;
-            
-            CREATE TRIGGER IF NOT EXISTS history_au AFTER UPDATE ON clipboard_history BEGIN
-                INSERT INTO history_fts(history_fts, rowid, content, source_app) VALUES('delete', old.id, old.content, old.source_app);
-                INSERT INTO history_fts(rowid, content, source_app) VALUES (new.id, new.content, new.source_app);
-            END;"
-        )?;
+        // Decrypted search index for encrypted histories must never hit disk
+        conn.pragma_update(None, "temp_store", "MEMORY")?;
         
-        // Ignored apps table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS ignored_apps (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                app_name TEXT UNIQUE NOT NULL
-            );"
-        )?;
+        let cipher = Arc::new(RwLock::new(None));
+        Self::register_functions(&conn, &cipher)?;
         
-        // Settings table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            );"
-        )?;
+        let mode = Self::read_setting(&conn, ENCRYPTION_MODE_KEY)?;
+        if mode.is_some() {
+            conn.pragma_update(None, "secure_delete", "ON")?;
+        }
         
-        // Add content_blob column if it doesn't exist (migration)
-        let has_blob: bool = conn.query_row(
-            "SELECT COUNT(*) > 0 FROM pragma_table_info('clipboard_history') WHERE name = 'content_blob'",
-            [],
-            |row| row.get(0)
-        ).unwrap_or(false);
+        let db = Self {
+            conn: Mutex::new(conn),
+            cipher,
+            encrypted: AtomicBool::new(mode.is_some()),
+            path: db_path.to_path_buf(),
+            expiring: Mutex::new(ExpiringClips {
+                key: crypto::random_salt(),
+                by_id: HashMap::new(),
+            }),
+        };
         
-        if !has_blob {
-            conn.execute("ALTER TABLE clipboard_history ADD COLUMN content_blob TEXT", [])?;
+        // Key files unlock without user interaction; passphrases start locked
+        if mode.as_deref() == Some("keyfile") {
+            if let Err(e) = db.unlock(None) {
+                eprintln!("Failed to unlock history with key file: {}", e);
+            }
         }
         
-        Ok(Self { conn: Mutex::new(conn) })
+        Ok(db)
+    }
+    
+    /// `clip_text(value, encrypted)` and `clip_blob(value, encrypted)` return
+    /// the stored value as-is for plaintext rows and decrypt it otherwise, so
+    /// queries read the same way whether or not encryption is on.
+    fn register_functions(conn: &Connection, cipher: &Arc<RwLock<Option<Cipher>>>) -> rusqlite::Result<()> {
+        for (name, as_text) in [("clip_text", true), ("clip_blob", false)] {
+            let cipher = cipher.clone();
+            conn.create_scalar_function(name, 2, FunctionFlags::SQLITE_UTF8, move |ctx| {
+                let value: Value = ctx.get(0)?;
+                let encrypted: bool = ctx.get::<Option<bool>>(1)?.unwrap_or(false);
+                
+                let data = match value {
+                    Value::Blob(data) if encrypted => data,
+                    other => return Ok(other),
+                };
+                
+                let guard = cipher.read().unwrap();
+                let cipher = guard
+                    .as_ref()
+                    .ok_or_else(|| rusqlite::Error::UserFunctionError(LOCKED_MESSAGE.into()))?;
+                let plain = cipher
+                    .decrypt(&data)
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                
+                if as_text {
+                    String::from_utf8(plain)
+                        .map(Value::Text)
+                        .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+                } else {
+                    Ok(Value::Blob(plain))
+                }
+            })?;
+        }
+        Ok(())
+    }
+    
+    fn locked_error() -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_AUTH),
+            Some(LOCKED_MESSAGE.to_string()),
+        )
+    }
+    
+    /// Encrypt `plain` if the history is encrypted. Returns the value to store
+    /// and whether it is ciphertext.
+    fn seal(&self, plain: &[u8]) -> Result<(Option<Vec<u8>>, bool), rusqlite::Error> {
+        if !self.encrypted.load(Ordering::SeqCst) {
+            return Ok((None, false));
+        }
+        let guard = self.cipher.read().unwrap();
+        let cipher = guard.as_ref().ok_or_else(Self::locked_error)?;
+        Ok((Some(cipher.encrypt(plain)), true))
+    }
+    
+    fn seal_text(&self, content: &str) -> Result<(Value, bool), rusqlite::Error> {
+        Ok(match self.seal(content.as_bytes())? {
+            (Some(sealed), true) => (Value::Blob(sealed), true),
+            _ => (Value::Text(content.to_string()), false),
+        })
     }
     
-    fn get_db_path() -> PathBuf {
-        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
-        path.push("ClipStream");
-        path.push("clipboard.db");
-        path
+    fn seal_blob(&self, data: &[u8]) -> Result<(Vec<u8>, bool), rusqlite::Error> {
+        Ok(match self.seal(data)? {
+            (Some(sealed), true) => (sealed, true),
+            _ => (data.to_vec(), false),
+        })
     }
     
-    pub fn insert(&self, content: &str, source_app: Option<&str>, content_blob: Option<&str>) -> Result<i64, rusqlite::Error> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    /// Insert a clipboard entry. `image` is the encoded PNG for image entries;
+    /// it is stored once per distinct hash in the `blobs` table.
+    pub fn insert(&self, content: &str, source_app: Option<&str>, image: Option<&[u8]>) -> Result<i64, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         
-        // Check for duplicate (skip if content is just an image placeholder)
-        let is_image = content.starts_with("[Image ");
+        // Entries are deduplicated through the unique content hash index;
+        // images by the address of their full pixel data
+        let image_hash = image.map(|data| self.image_address(data)).transpose()?;
+        let content_hash = self.content_hash(&conn, content, image_hash.as_deref())?;
         
-        if !is_image {
-            let exists: bool = conn.query_row(
-                "SELECT EXISTS(SELECT 1 FROM clipboard_history WHERE content = ?1 ORDER BY created_at DESC LIMIT 1)",
-                params![content],
-                |row| row.get(0)
-            ).unwrap_or(false);
-            
-            if exists {
-                // Update timestamp of existing entry
-                conn.execute(
-                    "UPDATE clipboard_history SET created_at = CURRENT_TIMESTAMP, source_app = COALESCE(?2, source_app) WHERE content = ?1",
-                    params![content, source_app]
-                )?;
-                let id: i64 = conn.query_row(
-                    "SELECT id FROM clipboard_history WHERE content = ?1",
-                    params![content],
-                    |row| row.get(0)
-                )?;
-                return Ok(id);
-            }
+        let existing: Option<i64> = conn.query_row(
+            "SELECT id FROM clipboard_history WHERE content_hash = ?1",
+            params![content_hash],
+            |row| row.get(0)
+        ).optional()?;
+        
+        if let Some(id) = existing {
+            // Update timestamp of existing entry; copying it again also
+            // brings it back from the trash
+            conn.execute(
+                "UPDATE clipboard_history SET created_at = CURRENT_TIMESTAMP, source_app = COALESCE(?2, source_app),
+                    deleted_at = NULL, delete_batch = NULL
+                 WHERE id = ?1",
+                params![id, source_app]
+            )?;
+            return Ok(id);
         }
         
-        let content_type = if content_blob.is_some() {
-            "image"
-        } else {
-            Self::detect_content_type(content)
+        let phash = match (image, &image_hash) {
+            (Some(data), Some(hash)) => self.store_image(&conn, data, hash)?,
+            _ => None,
         };
+        let kind = Self::classify(content, image.is_some());
         
+        let uuid = self.new_uuid(&conn, content, image_hash.as_deref())?;
+        let (stored, encrypted) = self.seal_text(content)?;
         conn.execute(
-            "INSERT INTO clipboard_history (content, source_app, content_type, content_blob) VALUES (?1, ?2, ?3, ?4)",
-            params![content, source_app, content_type, content_blob]
+            "INSERT INTO clipboard_history (content, source_app, content_type, language, blob_hash, encrypted, content_hash, phash, uuid) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![stored, source_app, kind.content_type.as_str(), kind.language.map(Language::as_str), image_hash, encrypted, content_hash, phash, uuid]
         )?;
         
         Ok(conn.last_insert_rowid())
     }
     
-    fn detect_content_type(content: &str) -> &'static str {
-        let trimmed = content.trim();
-        if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
-            "url"
-        } else if trimmed.contains("fn ") || trimmed.contains("function ") || 
-                  trimmed.contains("def ") || trimmed.contains("class ") ||
-                  trimmed.contains("const ") || trimmed.contains("let ") ||
-                  trimmed.contains("import ") || trimmed.contains("#include") {
-            "code"
+    /// Store image bytes under their blob `hash`, with a thumbnail on first
+    /// sight. Returns the perceptual hash to record on the entry.
+    fn store_image(&self, conn: &Connection, data: &[u8], hash: &str) -> Result<Option<i64>, rusqlite::Error> {
+        let (sealed, encrypted) = self.seal_blob(data)?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO blobs (hash, data, size, encrypted) VALUES (?1, ?2, ?3, ?4)",
+            params![hash, sealed, data.len() as i64, encrypted]
+        )?;
+        let phash = if inserted > 0 {
+            let decoded = decode_png(data);
+            if let Some(thumb) = decoded.as_ref().and_then(thumbnail_of) {
+                let (sealed, encrypted) = self.seal_blob(&thumb)?;
+                conn.execute(
+                    "INSERT OR REPLACE INTO blob_thumbnails (hash, data, encrypted) VALUES (?1, ?2, ?3)",
+                    params![hash, sealed, encrypted]
+                )?;
+            }
+            decoded.as_ref().map(dhash)
+        } else {
+            conn.query_row(
+                "SELECT phash FROM clipboard_history WHERE blob_hash = ?1 AND phash IS NOT NULL LIMIT 1",
+                params![hash],
+                |row| row.get(0)
+            ).optional()?.or_else(|| perceptual_hash(data))
+        };
+        // Perceptual hashes are only kept for plaintext history
+        Ok(phash.filter(|_| !self.encrypted.load(Ordering::SeqCst)))
+    }
+    
+    /// UUID for a new entry: derived from its content when that is free,
+    /// random when taken or when an unkeyed content hash would leak.
+    fn new_uuid(&self, conn: &Connection, content: &str, image_hash: Option<&str>) -> Result<String, rusqlite::Error> {
+        if self.encrypted.load(Ordering::SeqCst) {
+            return Ok(random_uuid());
+        }
+        let uuid = content_uuid(&image_hash.map_or_else(|| blob_hash(content.as_bytes()), str::to_string));
+        let taken: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM clipboard_history WHERE uuid = ?1)",
+            params![uuid],
+            |row| row.get(0)
+        )?;
+        Ok(if taken { random_uuid() } else { uuid })
+    }
+    
+    fn row_to_entry(row: &Row) -> Result<ClipboardEntry, rusqlite::Error> {
+        let thumbnail: Option<Vec<u8>> = row.get(6)?;
+        let tags: Option<String> = row.get(7)?;
+        Ok(ClipboardEntry {
+            id: row.get(0)?,
+            content: row.get(1)?,
+            source_app: row.get(2)?,
+            content_type: row.get::<_, String>(3).map(|name| ContentType::from_name(&name).unwrap_or(ContentType::Text))?,
+            language: row.get::<_, Option<String>>(13)?.as_deref().and_then(Language::from_name),
+            created_at: row.get(4)?,
+            is_pinned: row.get(5)?,
+            thumbnail: thumbnail.map(|data| STANDARD.encode(data)),
+            tags: tags
+                .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+                .unwrap_or_default(),
+            use_count: row.get(8)?,
+            last_used_at: row.get(9)?,
+            deleted_at: row.get(10)?,
+            sensitivity: row.get::<_, Option<String>>(11)?.as_deref().and_then(Sensitivity::from_name),
+            expires_at: row.get(12)?,
+            snippet: None,
+        })
+    }
+    
+    /// Image entries are always `image`; text is classified by content.
+    fn classify(content: &str, is_image: bool) -> Classification {
+        if is_image {
+            Classification::of(ContentType::Image)
         } else {
-            "text"
+            classify::classify(content)
+        }
+    }
+    
+    /// Imported and synced entries keep the type and language they arrive
+    /// with; only missing or unknown names are classified here.
+    fn classify_received(content: &str, content_type: &str, language: Option<&str>, is_image: bool) -> Classification {
+        match ContentType::from_name(content_type) {
+            Some(ContentType::Image) | None => Self::classify(content, is_image),
+            Some(_) if is_image => Self::classify(content, is_image),
+            Some(content_type) => {
+                let language = if content_type == ContentType::Code {
+                    language.and_then(Language::from_name).or_else(|| classify::classify(content).language)
+                } else {
+                    None
+                };
+                Classification { content_type, language }
+            }
         }
     }
     
-    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<ClipboardEntry>, rusqlite::Error> {
+    /// One page of search results, `limit` entries starting at `offset`.
+    pub fn search(
+        &self,
+        query: &SearchQuery,
+        mode: SearchMode,
+        sort: SearchSort,
+        offset: usize,
+        limit: usize,
+    ) -> Result<SearchPage, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         
-        let query_trimmed = query.trim();
+        let encrypted = self.encrypted.load(Ordering::SeqCst);
+        if encrypted && self.cipher.read().unwrap().is_none() {
+            return Err(Self::locked_error());
+        }
         
-        if query_trimmed.is_empty() {
-            let mut stmt = conn.prepare(
-                "SELECT id, content, source_app, content_type, created_at, is_pinned, content_blob 
-                 FROM clipboard_history 
-                 ORDER BY is_pinned DESC, created_at DESC 
-                 LIMIT ?1"
-            )?;
-            
-            let entries = stmt.query_map(params![limit as i64], |row| {
-                Ok(ClipboardEntry {
-                    id: row.get(0)?,
-                    content: row.get(1)?,
-                    source_app: row.get(2)?,
-                    content_type: row.get(3)?,
-                    created_at: row.get(4)?,
-                    is_pinned: row.get(5)?,
-                    content_blob: row.get(6)?,
-                })
-            })?.collect::<Result<Vec<_>, _>>()?;
-            
-            return Ok(entries);
+        // Encrypted histories are indexed in in-memory tables while unlocked
+        let (fts_table, trigram_table) = if encrypted {
+            ("secure_fts", "secure_trigram")
+        } else {
+            ("history_fts", "history_trigram")
+        };
+        let compiled = query.compile(fts_table);
+        let text = query.text();
+        
+        if mode == SearchMode::Fuzzy && !text.is_empty() {
+            let ranked = Self::fuzzy_rank(&conn, trigram_table, &text, &compiled, sort)?;
+            let total = ranked.len();
+            let entries = Self::load_fuzzy(&conn, ranked.into_iter().skip(offset).take(limit))?;
+            return Ok(SearchPage::new(entries, offset, total));
         }
         
-        // FTS5 search with highlighting
-        let fts_query = format!("{}*", query_trimmed.replace("\"", ""));
+        let total = Self::fts_count(&conn, fts_table, &compiled)?;
         
-        let mut stmt = conn.prepare(
-            "SELECT h.id, h.content, h.source_app, h.content_type, h.created_at, h.is_pinned, h.content_blob
-             FROM clipboard_history h
-             JOIN history_fts fts ON h.id = fts.rowid
-             WHERE history_fts MATCH ?1
-             ORDER BY h.is_pinned DESC, rank
-             LIMIT ?2"
-        )?;
+        // Typos and mid-word substrings miss the prefix index; top up from the trigram index
+        let fallback = mode == SearchMode::Auto
+            && !text.is_empty()
+            && !query.has_phrase()
+            && total < FUZZY_FALLBACK_THRESHOLD;
+        if !fallback {
+            let entries = Self::fts_page(&conn, fts_table, &compiled, sort, offset, limit)?;
+            return Ok(SearchPage::new(entries, offset, total));
+        }
         
-        let entries = stmt.query_map(params![fts_query, limit as i64], |row| {
-            Ok(ClipboardEntry {
-                id: row.get(0)?,
-                content: row.get(1)?,
-                source_app: row.get(2)?,
-                content_type: row.get(3)?,
-                created_at: row.get(4)?,
-                is_pinned: row.get(5)?,
-                content_blob: row.get(6)?,
-            })
-        })?.collect::<Result<Vec<_>, _>>()?;
+        let exact = Self::fts_page(&conn, fts_table, &compiled, sort, 0, total)?;
+        let seen: HashSet<i64> = exact.iter().map(|e| e.id).collect();
+        let fuzzy: Vec<_> = Self::fuzzy_rank(&conn, trigram_table, &text, &compiled, sort)?
+            .into_iter()
+            .filter(|(id, _, _)| !seen.contains(id))
+            .collect();
+        
+        let merged_total = total + fuzzy.len();
+        let mut entries: Vec<ClipboardEntry> = exact.into_iter().skip(offset).take(limit).collect();
+        let fuzzy_page = fuzzy.into_iter().skip(offset.saturating_sub(total)).take(limit - entries.len());
+        entries.extend(Self::load_fuzzy(&conn, fuzzy_page)?);
+        Ok(SearchPage::new(entries, offset, merged_total))
+    }
+    
+    /// `FROM`/`WHERE` clauses and bound values for the prefix FTS path.
+    fn fts_clauses(fts_table: &str, compiled: &CompiledQuery) -> (String, Vec<Value>) {
+        let mut conditions = vec!["h.deleted_at IS NULL".to_string()];
+        let mut values = Vec::new();
+        let mut from = "FROM clipboard_history h".to_string();
+        if let Some(fts_match) = &compiled.fts_match {
+            from.push_str(&format!(" JOIN {fts} fts ON h.id = fts.rowid", fts = fts_table));
+            conditions.push(format!("{} MATCH ?", fts_table));
+            values.push(Value::Text(fts_match.clone()));
+        }
+        conditions.extend(compiled.conditions.iter().cloned());
+        values.extend(compiled.params.iter().cloned());
+        
+        from.push_str(&format!(" {ENTRY_JOINS} WHERE {}", conditions.join(" AND ")));
+        (from, values)
+    }
+    
+    fn fts_count(conn: &Connection, fts_table: &str, compiled: &CompiledQuery) -> Result<usize, rusqlite::Error> {
+        let (from, values) = Self::fts_clauses(fts_table, compiled);
+        let count: i64 = conn.query_row(&format!("SELECT COUNT(*) {from}"), params_from_iter(values), |row| row.get(0))?;
+        Ok(count as usize)
+    }
+    
+    fn fts_page(
+        conn: &Connection,
+        fts_table: &str,
+        compiled: &CompiledQuery,
+        sort: SearchSort,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<ClipboardEntry>, rusqlite::Error> {
+        let (from, mut values) = Self::fts_clauses(fts_table, compiled);
+        let (snippet_column, order) = if compiled.fts_match.is_some() {
+            let snippet_column = format!(
+                "snippet({fts}, 0, '{}', '{}', '{}', {})",
+                snippet::MATCH_START,
+                snippet::MATCH_END,
+                snippet::ELLIPSIS,
+                SNIPPET_TOKENS,
+                fts = fts_table
+            );
+            let order = match sort {
+                SearchSort::Relevance => "fts.rank".to_string(),
+                // bm25 ranks are negative, better matches more so
+                SearchSort::Frecency => format!("fts.rank * {FRECENCY_SQL}"),
+            };
+            (snippet_column, order)
+        } else {
+            let order = match sort {
+                SearchSort::Relevance => "h.created_at DESC".to_string(),
+                SearchSort::Frecency => format!("{FRECENCY_SQL} DESC"),
+            };
+            ("NULL".to_string(), order)
+        };
+        values.push(Value::Integer(limit as i64));
+        values.push(Value::Integer(offset as i64));
+        
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}, {snippet_column}
+             {from}
+             ORDER BY h.is_pinned DESC, {order}, h.id DESC
+             LIMIT ? OFFSET ?"
+        ))?;
+        
+        let entries = stmt
+            .query_map(params_from_iter(values), |row| {
+                let mut entry = Self::row_to_entry(row)?;
+                entry.snippet = row.get::<_, Option<String>>(14)?.map(|marked| Snippet::from_marked(&marked));
+                Ok(entry)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+    
+    /// Rank rows sharing trigrams with `text` by fuzzy score, best first.
+    /// Structured filters in `compiled` still apply; its FTS match is ignored.
+    fn fuzzy_rank(
+        conn: &Connection,
+        trigram_table: &str,
+        text: &str,
+        compiled: &CompiledQuery,
+        sort: SearchSort,
+    ) -> Result<Vec<(i64, String, FuzzyMatch)>, rusqlite::Error> {
+        let trigrams = fuzzy::trigrams(text);
+        if trigrams.is_empty() {
+            return Ok(Vec::new());
+        }
         
+        let mut conditions = vec![format!("{} MATCH ?", trigram_table), "h.deleted_at IS NULL".to_string()];
+        conditions.extend(compiled.conditions.iter().cloned());
+        let mut values = vec![Value::Text(fuzzy::candidate_match(&trigrams))];
+        values.extend(compiled.params.iter().cloned());
+        values.push(Value::Integer(FUZZY_CANDIDATE_LIMIT));
+        
+        let mut stmt = conn.prepare(&format!(
+            "SELECT h.id, clip_text(h.content, h.encrypted), h.is_pinned, {FRECENCY_SQL}
+             FROM {tri} JOIN clipboard_history h ON h.id = {tri}.rowid
+             WHERE {conditions}
+             ORDER BY rank
+             LIMIT ?",
+            tri = trigram_table,
+            conditions = conditions.join(" AND ")
+        ))?;
+        let candidates = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, bool>(2)?, row.get::<_, f64>(3)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        let mut scored: Vec<(bool, i64, String, FuzzyMatch)> = candidates
+            .into_iter()
+            .filter_map(|(id, content, pinned, frecency)| {
+                fuzzy::score(text, &trigrams, &content).map(|mut m| {
+                    if sort == SearchSort::Frecency {
+                        m.score *= frecency;
+                    }
+                    (pinned, id, content, m)
+                })
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.3.score.total_cmp(&a.3.score)).then(b.1.cmp(&a.1)));
+        Ok(scored.into_iter().map(|(_, id, content, m)| (id, content, m)).collect())
+    }
+    
+    fn load_fuzzy<I>(conn: &Connection, ranked: I) -> Result<Vec<ClipboardEntry>, rusqlite::Error>
+    where
+        I: IntoIterator<Item = (i64, String, FuzzyMatch)>,
+    {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}
+             FROM clipboard_history h
+             {ENTRY_JOINS}
+             WHERE h.id = ?1"
+        ))?;
+        let mut entries = Vec::new();
+        for (id, content, matched) in ranked {
+            let mut entry = stmt.query_row(params![id], Self::row_to_entry)?;
+            entry.snippet = Snippet::around(&content, &matched.positions);
+            entries.push(entry);
+        }
         Ok(entries)
     }
     
     pub fn get_by_id(&self, id: i64) -> Result<Option<ClipboardEntry>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         
-        let mut stmt = conn.prepare(
-            "SELECT id, content, source_app, content_type, created_at, is_pinned, content_blob 
-             FROM clipboard_history WHERE id = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}
+             FROM clipboard_history h
+             {ENTRY_JOINS}
+             WHERE h.id = ?1"
+        ))?;
         
         let mut rows = stmt.query(params![id])?;
         
         if let Some(row) = rows.next()? {
-            Ok(Some(ClipboardEntry {
-                id: row.get(0)?,
-                content: row.get(1)?,
-                source_app: row.get(2)?,
-                content_type: row.get(3)?,
-                created_at: row.get(4)?,
-                is_pinned: row.get(5)?,
-                content_blob: row.get(6)?,
-            }))
+            Ok(Some(Self::row_to_entry(row)?))
         } else {
             Ok(None)
         }
     }
     
-    pub fn update_content(&self, id: i64, content: &str) -> Result<(), rusqlite::Error> {
+    /// Full-size PNG for an image entry, loaded on demand.
+    pub fn get_image(&self, id: i64) -> Result<Option<Vec<u8>>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE clipboard_history SET content = ?1 WHERE id = ?2",
-            params![content, id]
+        
+        let result: Result<Vec<u8>, _> = conn.query_row(
+            "SELECT clip_blob(b.data, b.encrypted) FROM clipboard_history h
+             JOIN blobs b ON b.hash = h.blob_hash
+             WHERE h.id = ?1",
+            params![id],
+            |row| row.get(0)
+        );
+        
+        match result {
+            Ok(data) => Ok(Some(data)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    
+    /// Other image entries whose perceptual hash is within `max_distance` bits
+    /// of entry `id`, closest first. Empty for encrypted history.
+    pub fn find_similar_images(&self, id: i64, max_distance: u32) -> Result<Vec<ClipboardEntry>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+    
+        let target: Option<i64> = conn.query_row(
+            "SELECT phash FROM clipboard_history WHERE id = ?1",
+            params![id],
+            |row| row.get(0)
+        ).optional()?.flatten();
+        let Some(target) = target else {
+            return Ok(Vec::new());
+        };
+    
+        let mut similar: Vec<(u32, i64)> = {
+            let mut stmt = conn.prepare(
+                "SELECT id, phash FROM clipboard_history WHERE phash IS NOT NULL AND id != ?1 AND deleted_at IS NULL"
+            )?;
+            let rows = stmt
+                .query_map(params![id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+                .filter_map(|row| match row {
+                    Ok((other, phash)) => {
+                        let distance = (target ^ phash).count_ones();
+                        (distance <= max_distance).then_some(Ok((distance, other)))
+                    }
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        similar.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}
+             FROM clipboard_history h
+             {ENTRY_JOINS}
+             WHERE h.id = ?1"
+        ))?;
+        similar
+            .into_iter()
+            .map(|(_, other)| stmt.query_row(params![other], Self::row_to_entry))
+            .collect()
+    }
+    
+    /// Replace an entry's content, keeping the previous version as a revision.
+    pub fn update_content(&self, id: i64, content: &str) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        self.replace_content(&tx, id, content)?;
+        tx.commit()
+    }
+    
+    /// Set an entry's content, keeping the previous version as a revision.
+    /// Returns whether the content changed.
+    fn replace_content(&self, conn: &Connection, id: i64, content: &str) -> Result<bool, rusqlite::Error> {
+        let (stored, encrypted) = self.seal_text(content)?;
+        let content_hash = self.content_hash(conn, content, None)?;
+        
+        // The old value is copied as stored, so encrypted content stays sealed
+        let recorded = conn.execute(
+            "INSERT INTO entry_revisions (entry_id, content, encrypted)
+             SELECT id, content, encrypted FROM clipboard_history
+             WHERE id = ?1 AND clip_text(content, encrypted) IS NOT ?2",
+            params![id, content]
         )?;
-        Ok(())
+        if recorded > 0 {
+            // If another entry already holds this content it keeps the hash.
+            // Images are deduplicated by their pixels, so a renamed image keeps
+            // its hash. Edited text is classified again; image previews stay images.
+            let kind = classify::classify(content);
+            conn.execute(
+                "UPDATE clipboard_history SET content = ?1, encrypted = ?2,
+                    content_hash = CASE
+                        WHEN content_type = 'image' THEN content_hash
+                        WHEN EXISTS (SELECT 1 FROM clipboard_history WHERE content_hash = ?4 AND id != ?3) THEN NULL
+                        ELSE ?4
+                    END,
+                    content_type = CASE WHEN content_type = 'image' THEN content_type ELSE ?5 END,
+                    language = CASE WHEN content_type = 'image' THEN NULL ELSE ?6 END
+                 WHERE id = ?3",
+                params![stored, encrypted, id, content_hash, kind.content_type.as_str(), kind.language.map(Language::as_str)]
+            )?;
+        }
+        Ok(recorded > 0)
     }
     
     pub fn toggle_pin(&self, id: i64) -> Result<bool, rusqlite::Error> {
@@ -266,61 +904,873 @@ impl Database {
         Ok(is_pinned)
     }
     
-    pub fn delete(&self, id: i64) -> Result<(), rusqlite::Error> {
+    pub fn set_pinned(&self, id: i64, pinned: bool) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM clipboard_history WHERE id = ?1", params![id])?;
+        conn.execute(
+            "UPDATE clipboard_history SET is_pinned = ?2 WHERE id = ?1",
+            params![id, pinned]
+        )?;
         Ok(())
     }
     
-    pub fn cleanup(&self, max_age_days: i64, max_entries: i64) -> Result<usize, rusqlite::Error> {
+    /// Flag an entry as holding sensitive data. With `expire_minutes` it is
+    /// deleted once that time has passed; otherwise any earlier expiry is
+    /// cleared.
+    pub fn mark_sensitive(&self, id: i64, sensitivity: Sensitivity, expire_minutes: Option<u64>) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        
-        // Delete old entries (except pinned)
-        let deleted_old = conn.execute(
-            "DELETE FROM clipboard_history 
-             WHERE is_pinned = 0 
-             AND created_at < datetime('now', ?1)",
-            params![format!("-{} days", max_age_days)]
-        )?;
-        
-        // Keep only max_entries (except pinned)
-        let deleted_excess = conn.execute(
-            "DELETE FROM clipboard_history 
-             WHERE is_pinned = 0 
-             AND id NOT IN (
-                SELECT id FROM clipboard_history 
-                ORDER BY is_pinned DESC, created_at DESC 
-                LIMIT ?1
-             )",
-            params![max_entries]
+        conn.execute(
+            "UPDATE clipboard_history SET sensitivity = ?2 WHERE id = ?1",
+            params![id, sensitivity.as_str()]
         )?;
-        
-        Ok(deleted_old + deleted_excess)
+        self.write_expiry(&conn, id, expire_minutes)
     }
     
-    // ========================================================================
-    // Ignored Apps
-    // ========================================================================
-    
-    pub fn get_ignored_apps(&self) -> Result<Vec<String>, rusqlite::Error> {
+    /// Delete an entry `minutes` from now, or keep it again with `None`.
+    pub fn set_expiry(&self, id: i64, minutes: Option<u64>) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT app_name FROM ignored_apps ORDER BY app_name")?;
-        let apps = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<_>, _>>()?;
-        Ok(apps)
+        self.write_expiry(&conn, id, minutes)
     }
     
-    pub fn add_ignored_app(&self, app_name: &str) -> Result<(), rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
+    fn write_expiry(&self, conn: &Connection, id: i64, minutes: Option<u64>) -> Result<(), rusqlite::Error> {
         conn.execute(
-            "INSERT OR IGNORE INTO ignored_apps (app_name) VALUES (?1)",
-            params![app_name]
+            "UPDATE clipboard_history
+             SET expires_at = CASE WHEN ?2 IS NULL THEN NULL ELSE datetime('now', '+' || ?2 || ' minutes') END
+             WHERE id = ?1",
+            params![id, minutes.map(|m| m as i64)]
         )?;
-        Ok(())
+        
+        let mut expiring = self.expiring.lock().unwrap();
+        expiring.by_id.remove(&id);
+        if minutes.is_some() && !self.is_locked() {
+            let text: Option<String> = conn.query_row(
+                "SELECT clip_text(content, encrypted) FROM clipboard_history WHERE id = ?1 AND content_type != 'image'",
+                params![id],
+                |row| row.get(0)
+            ).optional()?;
+            if let Some(text) = text {
+                let fingerprint = expiring.fingerprint(&text);
+                expiring.by_id.insert(id, fingerprint);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Count a paste or copy of the entry for frecency ranking.
+    pub fn record_use(&self, id: i64) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE clipboard_history SET use_count = use_count + 1, last_used_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![id]
+        )?;
+        Ok(())
+    }
+    
+    /// Move an entry to the trash as a batch of its own, so
+    /// `undo_delete` restores it.
+    pub fn delete(&self, id: i64) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE clipboard_history SET deleted_at = CURRENT_TIMESTAMP,
+                delete_batch = (SELECT COALESCE(MAX(delete_batch), 0) + 1 FROM clipboard_history)
+             WHERE id = ?1 AND deleted_at IS NULL",
+            params![id]
+        )?;
+        Ok(())
+    }
+    
+    /// Entries the retention policy would delete, without deleting them.
+    pub fn cleanup_preview(&self, policy: &RetentionPolicy) -> Result<Vec<RetentionCandidate>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut candidates = Self::plan_cleanup(&conn, policy)?;
+        
+        // Encrypted previews are only readable while unlocked
+        let guard = self.cipher.read().unwrap();
+        for candidate in candidates.iter_mut().filter(|c| c.preview.is_empty()) {
+            let sealed: Vec<u8> = conn.query_row(
+                "SELECT content FROM clipboard_history WHERE id = ?1",
+                params![candidate.id],
+                |row| row.get(0)
+            )?;
+            candidate.preview = guard
+                .as_ref()
+                .and_then(|cipher| cipher.decrypt(&sealed).ok())
+                .map(|plain| String::from_utf8_lossy(&plain).chars().take(80).collect())
+                .unwrap_or_else(|| "[Encrypted]".to_string());
+        }
+        
+        Ok(candidates)
+    }
+    
+    pub fn cleanup(&self, policy: &RetentionPolicy) -> Result<usize, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let candidates = Self::plan_cleanup(&conn, policy)?;
+        
+        let tx = conn.transaction()?;
+        for candidate in &candidates {
+            tx.execute(
+                "UPDATE clipboard_history SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                params![candidate.id]
+            )?;
+        }
+        Self::purge_trash(&tx, policy.trash_days)?;
+        tx.commit()?;
+        
+        Ok(candidates.len())
+    }
+    
+    /// Walk entries newest first (pinned and tagged ones ahead of everything
+    /// else, as they always take a slot) and collect the others that break a rule.
+    fn plan_cleanup(conn: &Connection, policy: &RetentionPolicy) -> Result<Vec<RetentionCandidate>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT h.id, CASE WHEN h.encrypted THEN '' ELSE substr(h.content, 1, 80) END, h.source_app, h.content_type, h.created_at,
+                    h.is_pinned OR EXISTS(SELECT 1 FROM entry_tags et WHERE et.entry_id = h.id) AS is_kept,
+                    julianday('now') - julianday(h.created_at),
+                    length(CAST(h.content AS BLOB)), h.blob_hash, b.size
+             FROM clipboard_history h
+             LEFT JOIN blobs b ON b.hash = h.blob_hash
+             WHERE h.deleted_at IS NULL
+             ORDER BY is_kept DESC, h.created_at DESC"
+        )?;
+        
+        let max_storage = policy.max_storage_bytes();
+        let mut kept_count: i64 = 0;
+        let mut kept_bytes: i64 = 0;
+        let mut seen_blobs = HashSet::new();
+        let mut candidates = Vec::new();
+        
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let content_type: String = row.get(3)?;
+            let source_app: Option<String> = row.get(2)?;
+            let is_kept: bool = row.get(5)?;
+            let age_days: f64 = row.get(6)?;
+            let blob_hash: Option<String> = row.get(8)?;
+            
+            // Shared images only count against storage once
+            let mut size: i64 = row.get(7)?;
+            if let Some(hash) = blob_hash {
+                if seen_blobs.insert(hash) {
+                    size += row.get::<_, Option<i64>>(9)?.unwrap_or(0);
+                }
+            }
+            
+            let reason = if is_kept {
+                None
+            } else if policy
+                .max_age_for(&content_type, source_app.as_deref())
+                .is_some_and(|max_age| age_days > max_age)
+            {
+                Some(RetentionReason::Age)
+            } else if policy.max_entries.is_some_and(|max| kept_count >= max) {
+                Some(RetentionReason::Count)
+            } else if max_storage.is_some_and(|max| kept_bytes + size > max) {
+                Some(RetentionReason::Storage)
+            } else {
+                None
+            };
+            
+            match reason {
+                Some(reason) => candidates.push(RetentionCandidate {
+                    id: row.get(0)?,
+                    preview: row.get(1)?,
+                    content_type,
+                    source_app,
+                    created_at: row.get(4)?,
+                    reason,
+                }),
+                None => {
+                    kept_count += 1;
+                    kept_bytes += size;
+                }
+            }
+        }
+        
+        Ok(candidates)
+    }
+    
+    // ========================================================================
+    // Trash
+    // ========================================================================
+    
+    /// Trashed entries, most recently deleted first.
+    pub fn get_trash(&self) -> Result<Vec<ClipboardEntry>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(Self::locked_error());
+        }
+        
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}
+             FROM clipboard_history h
+             {ENTRY_JOINS}
+             WHERE h.deleted_at IS NOT NULL
+             ORDER BY h.deleted_at DESC, h.id DESC"
+        ))?;
+        let entries = stmt.query_map([], Self::row_to_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+    
+    pub fn restore_entry(&self, id: i64) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE clipboard_history SET deleted_at = NULL, delete_batch = NULL WHERE id = ?1",
+            params![id]
+        )?;
+        Ok(())
+    }
+    
+    /// Restore the entries of the most recent delete still in the trash.
+    /// Returns how many came back; repeated calls step further back.
+    pub fn undo_delete(&self) -> Result<usize, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE clipboard_history SET deleted_at = NULL, delete_batch = NULL
+             WHERE delete_batch = (SELECT MAX(delete_batch) FROM clipboard_history WHERE deleted_at IS NOT NULL)",
+            []
+        )
+    }
+    
+    /// Permanently delete everything in the trash.
+    pub fn empty_trash(&self) -> Result<usize, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM clipboard_history WHERE deleted_at IS NOT NULL", [])
+    }
+    
+    /// Permanently delete entries that have been in the trash for more than
+    /// `days`.
+    fn purge_trash(conn: &Connection, days: f64) -> Result<usize, rusqlite::Error> {
+        conn.execute(
+            "DELETE FROM clipboard_history
+             WHERE deleted_at IS NOT NULL AND julianday('now') - julianday(deleted_at) > ?1",
+            params![days]
+        )
+    }
+    
+    // ========================================================================
+    // Expiry
+    // ========================================================================
+    
+    /// Permanently delete entries past their expiry, skipping the trash
+    /// since expiry is mostly set on secrets. Returns the fingerprints of
+    /// the deleted text, see `clipboard_fingerprint`, so the caller can
+    /// clear it from the clipboard even while the history is locked.
+    pub fn reap_expired(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let due: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM clipboard_history WHERE expires_at <= CURRENT_TIMESTAMP)",
+            [],
+            |row| row.get(0)
+        )?;
+        if !due {
+            return Ok(Vec::new());
+        }
+        
+        let tx = conn.transaction()?;
+        let rows: Vec<(i64, Option<String>)> = {
+            // Entries that got their expiry in an earlier run are only
+            // fingerprinted here, which needs the key
+            let mut stmt = tx.prepare(
+                "SELECT id, CASE WHEN ?1 AND content_type != 'image' THEN clip_text(content, encrypted) END
+                 FROM clipboard_history
+                 WHERE expires_at <= CURRENT_TIMESTAMP"
+            )?;
+            let rows = stmt.query_map(params![!self.is_locked()], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        tx.execute("DELETE FROM clipboard_history WHERE expires_at <= CURRENT_TIMESTAMP", [])?;
+        tx.commit()?;
+        
+        let mut expiring = self.expiring.lock().unwrap();
+        Ok(rows
+            .into_iter()
+            .filter_map(|(id, text)| expiring.by_id.remove(&id).or_else(|| text.map(|text| expiring.fingerprint(&text))))
+            .collect())
+    }
+    
+    /// Time until the next entry expires, if any has an expiry.
+    pub fn next_expiry(&self) -> Result<Option<std::time::Duration>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let seconds: Option<f64> = conn.query_row(
+            "SELECT (julianday(MIN(expires_at)) - julianday('now')) * 86400.0
+             FROM clipboard_history WHERE expires_at IS NOT NULL",
+            [],
+            |row| row.get(0)
+        )?;
+        Ok(seconds.map(|seconds| std::time::Duration::from_secs_f64(seconds.max(0.0))))
+    }
+    
+    /// Hash of clipboard text, as captured (trimmed), under a key that only
+    /// lives as long as the process. It identifies expired entries on the
+    /// clipboard without keeping their text, and unlike `content_hash` it
+    /// does not need the history key.
+    pub fn clipboard_fingerprint(&self, text: &str) -> String {
+        self.expiring.lock().unwrap().fingerprint(text)
+    }
+    
+    // ========================================================================
+    // Revisions
+    // ========================================================================
+    
+    /// Earlier versions of an entry, newest first.
+    pub fn get_revisions(&self, entry_id: i64) -> Result<Vec<EntryRevision>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, entry_id, clip_text(content, encrypted), created_at
+             FROM entry_revisions
+             WHERE entry_id = ?1
+             ORDER BY id DESC"
+        )?;
+        let revisions = stmt.query_map(params![entry_id], Self::row_to_revision)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(revisions)
+    }
+    
+    /// Line diff between two versions of an entry. `None` on either side
+    /// stands for the entry's current content.
+    pub fn diff_revisions(&self, entry_id: i64, from: Option<i64>, to: Option<i64>) -> Result<Vec<DiffLine>, String> {
+        let conn = self.conn.lock().unwrap();
+        let old = Self::read_version(&conn, entry_id, from)?;
+        let new = Self::read_version(&conn, entry_id, to)?;
+        
+        let diff = similar::TextDiff::from_lines(&old, &new);
+        let lines = diff
+            .iter_all_changes()
+            .map(|change| DiffLine {
+                tag: match change.tag() {
+                    similar::ChangeTag::Equal => DiffTag::Equal,
+                    similar::ChangeTag::Insert => DiffTag::Insert,
+                    similar::ChangeTag::Delete => DiffTag::Delete,
+                },
+                text: change.value().to_string(),
+            })
+            .collect();
+        Ok(lines)
+    }
+    
+    /// Make a revision the current content again. The content it replaces is
+    /// itself recorded, so a restore can be undone the same way.
+    pub fn restore_revision(&self, revision_id: i64) -> Result<(), rusqlite::Error> {
+        let revision = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT id, entry_id, clip_text(content, encrypted), created_at FROM entry_revisions WHERE id = ?1",
+                params![revision_id],
+                Self::row_to_revision
+            )?
+        };
+        self.update_content(revision.entry_id, &revision.content)
+    }
+    
+    fn row_to_revision(row: &Row) -> Result<EntryRevision, rusqlite::Error> {
+        Ok(EntryRevision {
+            id: row.get(0)?,
+            entry_id: row.get(1)?,
+            content: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    }
+    
+    fn read_version(conn: &Connection, entry_id: i64, revision_id: Option<i64>) -> Result<String, String> {
+        let result = match revision_id {
+            Some(revision_id) => conn.query_row(
+                "SELECT clip_text(content, encrypted) FROM entry_revisions WHERE id = ?1 AND entry_id = ?2",
+                params![revision_id, entry_id],
+                |row| row.get(0)
+            ),
+            None => conn.query_row(
+                "SELECT clip_text(content, encrypted) FROM clipboard_history WHERE id = ?1",
+                params![entry_id],
+                |row| row.get(0)
+            ),
+        };
+        
+        match result {
+            Ok(content) => Ok(content),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err("Revision not found".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    
+    // ========================================================================
+    // Tags
+    // ========================================================================
+    
+    pub fn get_tags(&self) -> Result<Vec<Tag>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT t.id, t.name, COUNT(h.id)
+             FROM tags t
+             LEFT JOIN entry_tags et ON et.tag_id = t.id
+             LEFT JOIN clipboard_history h ON h.id = et.entry_id AND h.deleted_at IS NULL
+             GROUP BY t.id
+             ORDER BY t.name"
+        )?;
+        let tags = stmt.query_map([], |row| {
+            Ok(Tag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                entry_count: row.get(2)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(tags)
+    }
+    
+    pub fn create_tag(&self, name: &str) -> Result<Tag, String> {
+        let name = Self::validate_tag_name(name)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT INTO tags (name) VALUES (?1)", params![name])
+            .map_err(|e| Self::tag_error(e, &name))?;
+        Ok(Tag {
+            id: conn.last_insert_rowid(),
+            name,
+            entry_count: 0,
+        })
     }
     
-    pub fn remove_ignored_app(&self, app_name: &str) -> Result<(), rusqlite::Error> {
+    pub fn rename_tag(&self, id: i64, name: &str) -> Result<(), String> {
+        let name = Self::validate_tag_name(name)?;
         let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM ignored_apps WHERE app_name = ?1", params![app_name])?;
+        conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![name, id])
+            .map_err(|e| Self::tag_error(e, &name))?;
+        Ok(())
+    }
+    
+    pub fn delete_tag(&self, id: i64) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+    
+    pub fn tag_entries(&self, tag_id: i64, entry_ids: &[i64]) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for entry_id in entry_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO entry_tags (entry_id, tag_id)
+                 SELECT id, ?2 FROM clipboard_history WHERE id = ?1",
+                params![entry_id, tag_id]
+            )?;
+        }
+        tx.commit()
+    }
+    
+    pub fn untag_entries(&self, tag_id: i64, entry_ids: &[i64]) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for entry_id in entry_ids {
+            tx.execute(
+                "DELETE FROM entry_tags WHERE entry_id = ?1 AND tag_id = ?2",
+                params![entry_id, tag_id]
+            )?;
+        }
+        tx.commit()
+    }
+    
+    pub fn get_entries_by_tag(&self, tag_id: i64) -> Result<Vec<ClipboardEntry>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(Self::locked_error());
+        }
+        
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS}
+             FROM clipboard_history h
+             JOIN entry_tags et ON et.entry_id = h.id
+             {ENTRY_JOINS}
+             WHERE et.tag_id = ?1 AND h.deleted_at IS NULL
+             ORDER BY h.is_pinned DESC, h.created_at DESC"
+        ))?;
+        let entries = stmt.query_map(params![tag_id], Self::row_to_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+    
+    fn validate_tag_name(name: &str) -> Result<String, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Tag name must not be empty".to_string());
+        }
+        if name.contains(TAG_SEPARATOR) {
+            return Err("Tag name must be a single line".to_string());
+        }
+        Ok(name.to_string())
+    }
+    
+    fn tag_error(e: rusqlite::Error, name: &str) -> String {
+        match e {
+            rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+                format!("Tag \"{}\" already exists", name)
+            }
+            e => e.to_string(),
+        }
+    }
+    
+    // ========================================================================
+    // Export / Import
+    // ========================================================================
+    
+    /// Entries selected by `filter`, oldest first, decrypted for the archive.
+    pub fn export_entries(&self, filter: &ExportFilter) -> Result<Vec<ArchiveEntry>, String> {
+        let conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(LOCKED_MESSAGE.to_string());
+        }
+        
+        let mut conditions = vec!["h.deleted_at IS NULL"];
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(after) = &filter.after {
+            conditions.push("h.created_at >= ?");
+            values.push(Value::Text(query::parse_date(after).map_err(|e| e.0)?));
+        }
+        if let Some(before) = &filter.before {
+            conditions.push("h.created_at < ?");
+            values.push(Value::Text(query::parse_date(before).map_err(|e| e.0)?));
+        }
+        if let Some(app) = &filter.app {
+            conditions.push("COALESCE(h.source_app, '') LIKE ? ESCAPE '\\'");
+            values.push(Value::Text(format!("%{}%", query::escape_like(app))));
+        }
+        if filter.pinned_only {
+            conditions.push("h.is_pinned = 1");
+        }
+        
+        let mut stmt = conn.prepare(&format!(
+            "SELECT clip_text(h.content, h.encrypted), h.content_type, h.source_app, h.created_at,
+                    h.is_pinned, h.tags, clip_blob(b.data, b.encrypted), h.language
+             FROM clipboard_history h
+             LEFT JOIN blobs b ON b.hash = h.blob_hash
+             WHERE {}
+             ORDER BY h.created_at, h.id",
+            conditions.join(" AND ")
+        )).map_err(|e| e.to_string())?;
+        let entries = stmt.query_map(params_from_iter(values), |row| {
+            let tags: Option<String> = row.get(5)?;
+            let image: Option<Vec<u8>> = row.get(6)?;
+            Ok(ArchiveEntry {
+                content: row.get(0)?,
+                content_type: row.get(1)?,
+                language: row.get(7)?,
+                source_app: row.get(2)?,
+                created_at: row.get(3)?,
+                is_pinned: row.get(4)?,
+                tags: tags
+                    .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+                    .unwrap_or_default(),
+                image: image.map(|data| STANDARD.encode(data)),
+            })
+        }).map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(entries)
+    }
+    
+    /// Add archived entries in one transaction, keeping their timestamps.
+    /// Entries whose content hash already exists are skipped, though their
+    /// pin and tags are carried over to the existing entry, which also comes
+    /// back from the trash.
+    pub fn import_entries(&self, entries: &[ArchiveEntry]) -> Result<ImportSummary, String> {
+        let mut conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(LOCKED_MESSAGE.to_string());
+        }
+        
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut summary = ImportSummary::default();
+        for entry in entries {
+            let image = entry.image.as_deref()
+                .map(|data| STANDARD.decode(data))
+                .transpose()
+                .map_err(|e| format!("Invalid image data: {}", e))?;
+            let image_hash = image.as_deref().map(|data| self.image_address(data)).transpose()
+                .map_err(|e| e.to_string())?;
+            let content_hash = self.content_hash(&tx, &entry.content, image_hash.as_deref())
+                .map_err(|e| e.to_string())?;
+            
+            let existing: Option<i64> = tx.query_row(
+                "SELECT id FROM clipboard_history WHERE content_hash = ?1",
+                params![content_hash],
+                |row| row.get(0)
+            ).optional().map_err(|e| e.to_string())?;
+            if let Some(id) = existing {
+                tx.execute(
+                    "UPDATE clipboard_history SET is_pinned = is_pinned OR ?2, deleted_at = NULL, delete_batch = NULL WHERE id = ?1",
+                    params![id, entry.is_pinned]
+                ).map_err(|e| e.to_string())?;
+                Self::import_tags(&tx, id, &entry.tags).map_err(|e| e.to_string())?;
+                summary.duplicates += 1;
+                continue;
+            }
+            
+            let phash = match (&image, &image_hash) {
+                (Some(data), Some(hash)) => self.store_image(&tx, data, hash).map_err(|e| e.to_string())?,
+                _ => None,
+            };
+            let kind = Self::classify_received(&entry.content, &entry.content_type, entry.language.as_deref(), image.is_some());
+            let uuid = self.new_uuid(&tx, &entry.content, image_hash.as_deref()).map_err(|e| e.to_string())?;
+            let (stored, encrypted) = self.seal_text(&entry.content).map_err(|e| e.to_string())?;
+            tx.execute(
+                "INSERT INTO clipboard_history (content, source_app, content_type, language, created_at, is_pinned, blob_hash, encrypted, content_hash, phash, uuid)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![stored, entry.source_app, kind.content_type.as_str(), kind.language.map(Language::as_str), entry.created_at, entry.is_pinned, image_hash, encrypted, content_hash, phash, uuid]
+            ).map_err(|e| e.to_string())?;
+            let id = tx.last_insert_rowid();
+            
+            Self::import_tags(&tx, id, &entry.tags).map_err(|e| e.to_string())?;
+            summary.imported += 1;
+        }
+        
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(summary)
+    }
+    
+    /// Tag entry `id` by name, creating missing tags. Invalid names are dropped.
+    fn import_tags(conn: &Connection, id: i64, tags: &[String]) -> Result<(), rusqlite::Error> {
+        for name in tags {
+            let Ok(name) = Self::validate_tag_name(name) else {
+                continue;
+            };
+            conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", params![name])?;
+            conn.execute(
+                "INSERT OR IGNORE INTO entry_tags (entry_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
+                params![id, name]
+            )?;
+        }
+        Ok(())
+    }
+    
+    // ========================================================================
+    // Sync
+    // ========================================================================
+    
+    /// Entries that take part in sync: everything when `all`, otherwise the
+    /// pinned ones plus any already synced, so unpinning still propagates.
+    pub fn sync_local_entries(&self, all: bool) -> Result<Vec<LocalEntry>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT h.uuid, clip_text(h.content, h.encrypted), h.content_type, h.source_app, h.created_at,
+                    h.is_pinned, h.tags, h.blob_hash, h.language
+             FROM clipboard_history h
+             WHERE h.uuid IS NOT NULL AND h.deleted_at IS NULL AND h.sensitivity IS NULL
+               AND (?1 OR h.is_pinned = 1 OR h.uuid IN (SELECT uuid FROM sync_state))
+             ORDER BY h.created_at, h.id"
+        )?;
+        let entries = stmt.query_map(params![all], |row| {
+            let tags: Option<String> = row.get(6)?;
+            Ok(LocalEntry {
+                uuid: row.get(0)?,
+                content: row.get(1)?,
+                content_type: row.get(2)?,
+                language: row.get(8)?,
+                source_app: row.get(3)?,
+                created_at: row.get(4)?,
+                is_pinned: row.get(5)?,
+                tags: tags
+                    .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+                    .unwrap_or_default(),
+                blob_hash: row.get(7)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+    
+    /// Synced fields of each entry as of the end of the last sync pass.
+    pub fn sync_fingerprints(&self) -> Result<HashMap<String, Fingerprint>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT uuid, content_hash, is_pinned, tags FROM sync_state")?;
+        let states = stmt.query_map([], |row| {
+            Ok((row.get(0)?, Fingerprint {
+                content_hash: row.get(1)?,
+                is_pinned: row.get(2)?,
+                tags: row.get(3)?,
+            }))
+        })?.collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(states)
+    }
+    
+    pub fn get_blob(&self, hash: &str) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT clip_blob(data, encrypted) FROM blobs WHERE hash = ?1",
+            params![hash],
+            |row| row.get(0)
+        ).optional()
+    }
+    
+    /// Make the history match the merged sync state in one transaction and
+    /// record it as the baseline for the next pass. Returns the number of
+    /// entries created, changed or deleted.
+    pub fn apply_synced(&self, entries: &[SyncedEntry]) -> Result<usize, String> {
+        let mut conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(LOCKED_MESSAGE.to_string());
+        }
+        
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut applied = 0;
+        for entry in entries {
+            let local: Option<(i64, String, bool, Option<String>, bool)> = tx.query_row(
+                "SELECT id, clip_text(content, encrypted), is_pinned, tags, deleted_at IS NOT NULL
+                 FROM clipboard_history WHERE uuid = ?1",
+                params![entry.uuid],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            ).optional().map_err(|e| e.to_string())?;
+            
+            // Deletions from other devices land in the trash like local ones
+            if !entry.alive {
+                if let Some((id, .., false)) = local {
+                    tx.execute(
+                        "UPDATE clipboard_history SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                        params![id]
+                    ).map_err(|e| e.to_string())?;
+                    applied += 1;
+                }
+                tx.execute("DELETE FROM sync_state WHERE uuid = ?1", params![entry.uuid])
+                    .map_err(|e| e.to_string())?;
+                continue;
+            }
+            
+            match local {
+                None => {
+                    self.insert_synced(&tx, entry)?;
+                    applied += 1;
+                }
+                Some((id, content, is_pinned, tags, trashed)) => {
+                    let mut changed = false;
+                    if trashed {
+                        tx.execute(
+                            "UPDATE clipboard_history SET deleted_at = NULL, delete_batch = NULL WHERE id = ?1",
+                            params![id]
+                        ).map_err(|e| e.to_string())?;
+                        changed = true;
+                    }
+                    if content != entry.content {
+                        changed |= self.replace_content(&tx, id, &entry.content).map_err(|e| e.to_string())?;
+                    }
+                    if is_pinned != entry.is_pinned {
+                        tx.execute(
+                            "UPDATE clipboard_history SET is_pinned = ?1 WHERE id = ?2",
+                            params![entry.is_pinned, id]
+                        ).map_err(|e| e.to_string())?;
+                        changed = true;
+                    }
+                    let tags: Vec<String> = tags
+                        .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+                        .unwrap_or_default();
+                    if tag_key(&tags) != tag_key(&entry.tags) {
+                        tx.execute("DELETE FROM entry_tags WHERE entry_id = ?1", params![id])
+                            .map_err(|e| e.to_string())?;
+                        Self::import_tags(&tx, id, &entry.tags).map_err(|e| e.to_string())?;
+                        changed = true;
+                    }
+                    if changed {
+                        applied += 1;
+                    }
+                }
+            }
+            
+            let fingerprint = entry.fingerprint();
+            tx.execute(
+                "INSERT OR REPLACE INTO sync_state (uuid, content_hash, is_pinned, tags) VALUES (?1, ?2, ?3, ?4)",
+                params![entry.uuid, fingerprint.content_hash, fingerprint.is_pinned, fingerprint.tags]
+            ).map_err(|e| e.to_string())?;
+        }
+        
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(applied)
+    }
+    
+    fn insert_synced(&self, conn: &Connection, entry: &SyncedEntry) -> Result<(), String> {
+        let image = entry.image.as_deref()
+            .map(|data| STANDARD.decode(data))
+            .transpose()
+            .map_err(|e| format!("Invalid image data: {}", e))?;
+        let image_hash = image.as_deref().map(|data| self.image_address(data)).transpose()
+            .map_err(|e| e.to_string())?;
+        let content_hash = self.content_hash(conn, &entry.content, image_hash.as_deref())
+            .map_err(|e| e.to_string())?;
+        let phash = match (&image, &image_hash) {
+            (Some(data), Some(hash)) => self.store_image(conn, data, hash).map_err(|e| e.to_string())?,
+            _ => None,
+        };
+        
+        // Content another local entry already holds keeps its hash there,
+        // as with edits
+        let kind = Self::classify_received(&entry.content, &entry.content_type, entry.language.as_deref(), image.is_some());
+        let (stored, encrypted) = self.seal_text(&entry.content).map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT INTO clipboard_history (content, source_app, content_type, language, created_at, is_pinned, blob_hash, encrypted, content_hash, phash, uuid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8,
+                     CASE WHEN EXISTS (SELECT 1 FROM clipboard_history WHERE content_hash = ?9) THEN NULL ELSE ?9 END,
+                     ?10, ?11)",
+            params![stored, entry.source_app, kind.content_type.as_str(), kind.language.map(Language::as_str), entry.created_at, entry.is_pinned, image_hash, encrypted, content_hash, phash, entry.uuid]
+        ).map_err(|e| e.to_string())?;
+        Self::import_tags(conn, conn.last_insert_rowid(), &entry.tags).map_err(|e| e.to_string())
+    }
+    
+    // ========================================================================
+    // Backups
+    // ========================================================================
+    
+    /// Snapshot the live database to `dest` with the online backup API.
+    /// Encrypted content is copied sealed.
+    pub fn backup_to(&self, dest: &Path) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.backup(DatabaseName::Main, dest, None)
+    }
+    
+    /// Replace the live database with the snapshot at `src`, then bring it
+    /// up to the current schema. The key stays loaded if it also opens the
+    /// restored history; otherwise the history is locked again.
+    pub fn restore_from(&self, src: &Path) -> Result<(), String> {
+        {
+            // Refuse damaged or too new snapshots before touching the live database
+            let snapshot = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .map_err(|e| e.to_string())?;
+            let check: String = snapshot.query_row("PRAGMA quick_check", [], |row| row.get(0))
+                .map_err(|e| format!("Backup is damaged: {}", e))?;
+            if check != "ok" {
+                return Err(format!("Backup is damaged: {}", check));
+            }
+            let found = migrations::current_version(&snapshot).map_err(|e| e.to_string())?;
+            if found > migrations::latest_version() {
+                return Err(MigrationError::TooNew { found, supported: migrations::latest_version() }.to_string());
+            }
+        }
+        
+        let mut conn = self.conn.lock().unwrap();
+        Self::drop_secure_index(&conn).map_err(|e| e.to_string())?;
+        conn.restore(DatabaseName::Main, src, None::<fn(rusqlite::backup::Progress)>)
+            .map_err(|e| e.to_string())?;
+        migrations::run(&mut conn).map_err(|e| e.to_string())?;
+        
+        let mode = Self::read_setting(&conn, ENCRYPTION_MODE_KEY).map_err(|e| e.to_string())?;
+        if mode.is_some() {
+            conn.pragma_update(None, "secure_delete", "ON").map_err(|e| e.to_string())?;
+        }
+        self.encrypted.store(mode.is_some(), Ordering::SeqCst);
+        
+        let check = Self::read_setting(&conn, ENCRYPTION_CHECK_KEY).map_err(|e| e.to_string())?
+            .and_then(|check| STANDARD.decode(check).ok());
+        let keep_key = {
+            let mut guard = self.cipher.write().unwrap();
+            let keep = match (guard.as_ref(), check) {
+                (Some(cipher), Some(check)) => cipher.verify_key_check(&check),
+                _ => false,
+            };
+            if !keep {
+                *guard = None;
+            }
+            keep
+        };
+        
+        if keep_key {
+            Self::build_secure_index(&conn).map_err(|e| e.to_string())?;
+        } else if mode.as_deref() == Some("keyfile") {
+            drop(conn);
+            self.unlock(None)?;
+        }
         Ok(())
     }
     
@@ -330,6 +1780,10 @@ impl Database {
     
     pub fn get_setting(&self, key: &str) -> Result<Option<String>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, key)
+    }
+    
+    fn read_setting(conn: &Connection, key: &str) -> Result<Option<String>, rusqlite::Error> {
         let result: Result<String, _> = conn.query_row(
             "SELECT value FROM settings WHERE key = ?1",
             params![key],
@@ -352,11 +1806,428 @@ impl Database {
         Ok(())
     }
     
+    /// Read a value kept out of `settings`. Fails while the history is locked.
+    pub fn get_secret(&self, key: &str) -> Result<Option<String>, rusqlite::Error> {
+        if self.is_locked() {
+            return Err(Self::locked_error());
+        }
+        let secret: Option<(String, bool)> = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT clip_text(value, encrypted), encrypted FROM secrets WHERE key = ?1",
+                params![key],
+                |row| Ok((row.get(0)?, row.get(1)?))
+            ).optional()?
+        };
+        
+        // Secrets migrated into an encrypted history are sealed on first read
+        if let Some((value, false)) = &secret {
+            if self.encrypted.load(Ordering::SeqCst) {
+                self.set_secret(key, value)?;
+            }
+        }
+        Ok(secret.map(|(value, _)| value))
+    }
+    
+    /// Store a value that the settings commands cannot reach, sealed with the
+    /// history while encryption is on.
+    pub fn set_secret(&self, key: &str, value: &str) -> Result<(), rusqlite::Error> {
+        let (value, encrypted) = self.seal_text(value)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO secrets (key, value, encrypted) VALUES (?1, ?2, ?3)",
+            params![key, value, encrypted]
+        )?;
+        Ok(())
+    }
+    
+    // ========================================================================
+    // Deduplication
+    // ========================================================================
+    
+    pub fn dedup_mode(&self) -> Result<DedupMode, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_dedup_mode(&conn)
+    }
+    
+    /// Switch dedup mode and rehash every entry under it. Entries that now
+    /// collide are kept; only the newest of them is matched from then on.
+    pub fn set_dedup_mode(&self, mode: DedupMode) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        if self.is_locked() {
+            return Err(LOCKED_MESSAGE.to_string());
+        }
+        
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![DEDUP_MODE_KEY, mode.as_str()]
+        ).map_err(|e| e.to_string())?;
+        // `rehash_all` decrypts through the cipher lock, so only take it per row
+        Self::rehash_all(&tx, |text, image| entry_hash(text, image, mode, self.cipher.read().unwrap().as_ref()))
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+    
+    fn read_dedup_mode(conn: &Connection) -> Result<DedupMode, rusqlite::Error> {
+        Ok(match Self::read_setting(conn, DEDUP_MODE_KEY)?.as_deref() {
+            Some("whitespace") => DedupMode::Whitespace,
+            _ => DedupMode::Exact,
+        })
+    }
+    
+    fn content_hash(&self, conn: &Connection, content: &str, image_hash: Option<&str>) -> Result<String, rusqlite::Error> {
+        let mode = Self::read_dedup_mode(conn)?;
+        if !self.encrypted.load(Ordering::SeqCst) {
+            return Ok(entry_hash(content, image_hash, mode, None));
+        }
+        let guard = self.cipher.read().unwrap();
+        let cipher = guard.as_ref().ok_or_else(Self::locked_error)?;
+        Ok(entry_hash(content, image_hash, mode, Some(cipher)))
+    }
+    
+    fn image_address(&self, data: &[u8]) -> Result<String, rusqlite::Error> {
+        if !self.encrypted.load(Ordering::SeqCst) {
+            return Ok(blob_address(data, None));
+        }
+        let guard = self.cipher.read().unwrap();
+        let cipher = guard.as_ref().ok_or_else(Self::locked_error)?;
+        Ok(blob_address(data, Some(cipher)))
+    }
+    
+    /// Move every blob, with its thumbnail and the entries showing it, to the
+    /// address `address` gives its image bytes.
+    fn readdress_blobs<F>(conn: &Connection, address: F) -> Result<(), rusqlite::Error>
+    where
+        F: Fn(&[u8]) -> String,
+    {
+        let blobs: Vec<(String, Vec<u8>)> = {
+            let mut stmt = conn.prepare("SELECT hash, clip_blob(data, encrypted) FROM blobs")?;
+            let blobs = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            blobs
+        };
+        
+        for (old, data) in blobs {
+            let new = address(&data);
+            if new == old {
+                continue;
+            }
+            for table in ["blobs", "blob_thumbnails"] {
+                conn.execute(&format!("UPDATE {table} SET hash = ?1 WHERE hash = ?2"), params![new, old])?;
+            }
+            conn.execute("UPDATE clipboard_history SET blob_hash = ?1 WHERE blob_hash = ?2", params![new, old])?;
+        }
+        Ok(())
+    }
+    
+    /// Recompute `content_hash` for every entry, newest first, leaving older
+    /// duplicates without a hash so the unique index holds. `hash` receives
+    /// the text and, for images, the blob address.
+    fn rehash_all<F>(conn: &Connection, hash: F) -> Result<(), rusqlite::Error>
+    where
+        F: Fn(&str, Option<&str>) -> String,
+    {
+        let rows: Vec<(i64, String, Option<String>)> = {
+            let mut stmt = conn.prepare(
+                "SELECT id, clip_text(content, encrypted), CASE WHEN content_type = 'image' THEN blob_hash END
+                 FROM clipboard_history
+                 ORDER BY created_at DESC, id DESC"
+            )?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        
+        conn.execute("UPDATE clipboard_history SET content_hash = NULL", [])?;
+        let mut seen = HashSet::new();
+        for (id, content, image_hash) in rows {
+            let content_hash = hash(&content, image_hash.as_deref());
+            if seen.insert(content_hash.clone()) {
+                conn.execute(
+                    "UPDATE clipboard_history SET content_hash = ?1 WHERE id = ?2",
+                    params![content_hash, id]
+                )?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Give every entry a random UUID, as new entries get while encrypted.
+    fn randomize_uuids(conn: &Connection) -> Result<(), rusqlite::Error> {
+        let ids: Vec<i64> = {
+            let mut stmt = conn.prepare("SELECT id FROM clipboard_history")?;
+            let ids = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+        for id in ids {
+            conn.execute("UPDATE clipboard_history SET uuid = ?1 WHERE id = ?2", params![random_uuid(), id])?;
+        }
+        Ok(())
+    }
+    
+    // ========================================================================
+    // Encryption
+    // ========================================================================
+    
+    pub fn encryption_status(&self) -> EncryptionStatus {
+        let conn = self.conn.lock().unwrap();
+        EncryptionStatus {
+            enabled: self.encrypted.load(Ordering::SeqCst),
+            locked: self.is_locked(),
+            mode: Self::read_setting(&conn, ENCRYPTION_MODE_KEY).ok().flatten(),
+        }
+    }
+    
+    pub fn is_locked(&self) -> bool {
+        self.encrypted.load(Ordering::SeqCst) && self.cipher.read().unwrap().is_none()
+    }
+    
+    /// Encrypt the existing plaintext history in place and keep it unlocked.
+    /// A key file that does not exist yet is generated with 0600 permissions.
+    pub fn enable_encryption(&self, source: &KeySource) -> Result<(), String> {
+        if self.encrypted.load(Ordering::SeqCst) {
+            return Err("Encryption is already enabled".to_string());
+        }
+        
+        let (cipher, mode, salt, key_file) = match source {
+            KeySource::Passphrase(passphrase) => {
+                if passphrase.is_empty() {
+                    return Err("Passphrase must not be empty".to_string());
+                }
+                let salt = crypto::random_salt();
+                let cipher = Cipher::from_passphrase(passphrase, &salt)?;
+                (cipher, "passphrase", Some(STANDARD.encode(salt)), None)
+            }
+            KeySource::KeyFile(path) => {
+                let path = Path::new(path);
+                if !path.exists() {
+                    crypto::create_key_file(path)?;
+                }
+                let cipher = Cipher::from_key_file(path)?;
+                (cipher, "keyfile", None, Some(path.to_string_lossy().to_string()))
+            }
+        };
+        
+        let mut conn = self.conn.lock().unwrap();
+        conn.pragma_update(None, "secure_delete", "ON").map_err(|e| e.to_string())?;
+        
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        // Plain hashes would let anyone confirm a guessed clip; key them first
+        let dedup_mode = Self::read_dedup_mode(&tx).map_err(|e| e.to_string())?;
+        Self::readdress_blobs(&tx, |data| blob_address(data, Some(&cipher))).map_err(|e| e.to_string())?;
+        Self::rehash_all(&tx, |text, image| entry_hash(text, image, dedup_mode, Some(&cipher))).map_err(|e| e.to_string())?;
+        // UUIDs derived from content and the hashes of the last sync pass
+        // are unkeyed SHA-256 of the plaintext just the same
+        Self::randomize_uuids(&tx).map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM sync_state", []).map_err(|e| e.to_string())?;
+        Self::reseal_all(&tx, |plain| Ok(cipher.encrypt(plain)), true).map_err(|e| e.to_string())?;
+        tx.execute("UPDATE clipboard_history SET phash = NULL", []).map_err(|e| e.to_string())?;
+        
+        let check = STANDARD.encode(cipher.key_check());
+        let settings = [
+            (ENCRYPTION_MODE_KEY, Some(mode.to_string())),
+            (ENCRYPTION_SALT_KEY, salt),
+            (ENCRYPTION_CHECK_KEY, Some(check)),
+            (ENCRYPTION_KEY_FILE_KEY, key_file),
+        ];
+        for (key, value) in settings.into_iter().filter_map(|(k, v)| v.map(|v| (k, v))) {
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![key, value]
+            ).map_err(|e| e.to_string())?;
+        }
+        
+        // Drop every plaintext token from the persistent indexes
+        tx.execute_batch(
+            "INSERT INTO history_fts(history_fts) VALUES('delete-all');
+            INSERT INTO history_trigram(history_trigram) VALUES('delete-all');"
+        ).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        
+        // Rewrite the file so freed pages holding plaintext are gone
+        conn.execute_batch("VACUUM").map_err(|e| e.to_string())?;
+        
+        *self.cipher.write().unwrap() = Some(cipher);
+        self.encrypted.store(true, Ordering::SeqCst);
+        Self::build_secure_index(&conn).map_err(|e| e.to_string())
+    }
+    
+    /// Decrypt the history back to plaintext. Requires the history to be unlocked.
+    pub fn disable_encryption(&self) -> Result<(), String> {
+        if !self.encrypted.load(Ordering::SeqCst) {
+            return Err("Encryption is not enabled".to_string());
+        }
+        
+        let mut conn = self.conn.lock().unwrap();
+        // The secure index triggers decrypt through the cipher lock held below
+        Self::drop_secure_index(&conn).map_err(|e| e.to_string())?;
+        let mut guard = self.cipher.write().unwrap();
+        let cipher = guard.as_ref().ok_or(LOCKED_MESSAGE)?;
+        
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        Self::reseal_all(&tx, |sealed| cipher.decrypt(sealed), false).map_err(|e| e.to_string())?;
+        let dedup_mode = Self::read_dedup_mode(&tx).map_err(|e| e.to_string())?;
+        Self::readdress_blobs(&tx, |data| blob_address(data, None)).map_err(|e| e.to_string())?;
+        Self::rehash_all(&tx, |text, image| entry_hash(text, image, dedup_mode, None)).map_err(|e| e.to_string())?;
+        backfill_perceptual_hashes(&tx).map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM settings WHERE key IN (?1, ?2, ?3, ?4)",
+            params![ENCRYPTION_MODE_KEY, ENCRYPTION_SALT_KEY, ENCRYPTION_CHECK_KEY, ENCRYPTION_KEY_FILE_KEY]
+        ).map_err(|e| e.to_string())?;
+        tx.execute_batch(
+            "INSERT INTO history_fts(history_fts) VALUES('rebuild');
+            INSERT INTO history_trigram(history_trigram) VALUES('rebuild');"
+        ).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        
+        *guard = None;
+        self.encrypted.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+    
+    /// Forget the key. Encrypted content stays unreadable until `unlock`.
+    pub fn lock(&self) -> Result<(), String> {
+        if !self.encrypted.load(Ordering::SeqCst) {
+            return Err("Encryption is not enabled".to_string());
+        }
+        let conn = self.conn.lock().unwrap();
+        *self.cipher.write().unwrap() = None;
+        Self::drop_secure_index(&conn).map_err(|e| e.to_string())
+    }
+    
+    /// Unlock with a passphrase, or with the configured key file when `None`.
+    pub fn unlock(&self, passphrase: Option<&str>) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        let setting = |key| Self::read_setting(&conn, key).map_err(|e| e.to_string());
+        
+        let mode = setting(ENCRYPTION_MODE_KEY)?.ok_or("Encryption is not enabled")?;
+        let cipher = match mode.as_str() {
+            "keyfile" => {
+                let path = setting(ENCRYPTION_KEY_FILE_KEY)?.ok_or("Key file location is missing")?;
+                Cipher::from_key_file(Path::new(&path))?
+            }
+            _ => {
+                let passphrase = passphrase.ok_or("Passphrase required")?;
+                let salt = setting(ENCRYPTION_SALT_KEY)?.ok_or("Encryption salt is missing")?;
+                let salt = STANDARD.decode(salt).map_err(|e| e.to_string())?;
+                Cipher::from_passphrase(passphrase, &salt)?
+            }
+        };
+        
+        let check = setting(ENCRYPTION_CHECK_KEY)?.ok_or("Encryption key check is missing")?;
+        let check = STANDARD.decode(check).map_err(|e| e.to_string())?;
+        if !cipher.verify_key_check(&check) {
+            return Err("Wrong passphrase or key file".to_string());
+        }
+        
+        *self.cipher.write().unwrap() = Some(cipher);
+        
+        // Databases migrated while encrypted have no keyed hashes yet
+        if setting(migrations::CONTENT_HASH_PENDING_KEY)?.is_some() {
+            let dedup_mode = Self::read_dedup_mode(&conn).map_err(|e| e.to_string())?;
+            let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+            Self::readdress_blobs(&tx, |data| blob_address(data, self.cipher.read().unwrap().as_ref()))
+                .map_err(|e| e.to_string())?;
+            Self::rehash_all(&tx, |text, image| entry_hash(text, image, dedup_mode, self.cipher.read().unwrap().as_ref()))
+                .map_err(|e| e.to_string())?;
+            tx.execute("DELETE FROM settings WHERE key = ?1", params![migrations::CONTENT_HASH_PENDING_KEY])
+                .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+        
+        Self::build_secure_index(&conn).map_err(|e| e.to_string())
+    }
+    
+    /// Rewrite every content and blob value through `transform`, marking the
+    /// rows with `encrypted`. Only rows in the opposite state are touched.
+    fn reseal_all<F>(conn: &Connection, transform: F, encrypted: bool) -> Result<(), rusqlite::Error>
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>, String>,
+    {
+        for &(table, key, column, is_text) in SEALED_COLUMNS {
+            let rows = {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {key}, CAST({column} AS BLOB) FROM {table} WHERE encrypted = ?1"
+                ))?;
+                let rows = stmt
+                    .query_map(params![!encrypted], |row| Ok((row.get::<_, Value>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            
+            for (row_key, value) in rows {
+                let resealed = transform(&value).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                let stored = if is_text && !encrypted {
+                    let text = String::from_utf8(resealed).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                    Value::Text(text)
+                } else {
+                    Value::Blob(resealed)
+                };
+                conn.execute(
+                    &format!("UPDATE {table} SET {column} = ?1, encrypted = ?2 WHERE {key} = ?3"),
+                    params![stored, encrypted, row_key]
+                )?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// In-memory FTS index over decrypted content, kept in sync by TEMP
+    /// triggers for as long as the history stays unlocked.
+    fn build_secure_index(conn: &Connection) -> Result<(), rusqlite::Error> {
+        Self::drop_secure_index(conn)?;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE temp.secure_fts USING fts5(content, source_app, tags);
+            
+            INSERT INTO temp.secure_fts(rowid, content, source_app, tags)
+                SELECT id, clip_text(content, encrypted), source_app, tags FROM main.clipboard_history;
+            
+            CREATE VIRTUAL TABLE temp.secure_trigram USING fts5(content, tokenize='trigram');
+            
+            INSERT INTO temp.secure_trigram(rowid, content)
+                SELECT rowid, content FROM temp.secure_fts;
+            
+            CREATE TEMP TRIGGER secure_fts_ai AFTER INSERT ON main.clipboard_history BEGIN
+                INSERT INTO secure_fts(rowid, content, source_app, tags) VALUES (new.id, clip_text(new.content, new.encrypted), new.source_app, new.tags);
+                INSERT INTO secure_trigram(rowid, content) VALUES (new.id, clip_text(new.content, new.encrypted));
+            END;
+            
+            CREATE TEMP TRIGGER secure_fts_ad AFTER DELETE ON main.clipboard_history BEGIN
+                DELETE FROM secure_fts WHERE rowid = old.id;
+                DELETE FROM secure_trigram WHERE rowid = old.id;
+            END;
+            
+            CREATE TEMP TRIGGER secure_fts_au AFTER UPDATE OF content, source_app, tags, encrypted ON main.clipboard_history BEGIN
+                DELETE FROM secure_fts WHERE rowid = old.id;
+                INSERT INTO secure_fts(rowid, content, source_app, tags) VALUES (new.id, clip_text(new.content, new.encrypted), new.source_app, new.tags);
+            END;
+            
+            CREATE TEMP TRIGGER secure_trigram_au AFTER UPDATE OF content ON main.clipboard_history BEGIN
+                DELETE FROM secure_trigram WHERE rowid = old.id;
+                INSERT INTO secure_trigram(rowid, content) VALUES (new.id, clip_text(new.content, new.encrypted));
+            END;"
+        )
+    }
+    
+    fn drop_secure_index(conn: &Connection) -> Result<(), rusqlite::Error> {
+        conn.execute_batch(
+            "DROP TRIGGER IF EXISTS temp.secure_fts_ai;
+            DROP TRIGGER IF EXISTS temp.secure_fts_ad;
+            DROP TRIGGER IF EXISTS temp.secure_fts_au;
+            DROP TRIGGER IF EXISTS temp.secure_trigram_au;
+            DROP TABLE IF EXISTS temp.secure_fts;
+            DROP TABLE IF EXISTS temp.secure_trigram;"
+        )
+    }
+    
     pub fn get_last_content(&self) -> Result<Option<String>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         
         let result: Result<String, _> = conn.query_row(
-            "SELECT content FROM clipboard_history ORDER BY created_at DESC LIMIT 1",
+            "SELECT clip_text(content, encrypted) FROM clipboard_history WHERE deleted_at IS NULL ORDER BY created_at DESC LIMIT 1",
             [],
             |row| row.get(0)
         );
@@ -368,3 +2239,598 @@ impl Database {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// A database in a directory of its own, removed again when dropped.
+    struct TempDb {
+        db: Option<Database>,
+        dir: PathBuf,
+    }
+
+    impl std::ops::Deref for TempDb {
+        type Target = Database;
+
+        fn deref(&self) -> &Database {
+            self.db.as_ref().unwrap()
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            // Close the connection first so the file can be removed everywhere
+            drop(self.db.take());
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn temp_db(name: &str) -> TempDb {
+        let dir = std::env::temp_dir().join(format!("clipstream-db-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(&dir.join("clipboard.db")).unwrap();
+        TempDb { db: Some(db), dir }
+    }
+
+    fn png(width: u32, height: u32, shade: impl Fn(u32, u32) -> u8) -> Vec<u8> {
+        let img = image::GrayImage::from_fn(width, height, |x, y| image::Luma([shade(x, y)]));
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageLuma8(img)
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn count(db: &Database, table: &str) -> i64 {
+        db.conn.lock().unwrap().query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn images_are_stored_once_and_freed_with_their_last_entry() {
+        let db = temp_db("blobs");
+        let gradient = png(64, 32, |x, _| (x * 4) as u8);
+        let stripes = png(64, 32, |_, y| if y % 2 == 0 { 0 } else { 255 });
+
+        let first = db.insert("[Image 64x32]", Some("Paint"), Some(&gradient)).unwrap();
+        let other = db.insert("[Image 64x32]", None, Some(&stripes)).unwrap();
+        assert_eq!(db.insert("[Image 64x32]", None, Some(&gradient)).unwrap(), first);
+        assert_ne!(first, other);
+        assert_eq!(count(&db, "blobs"), 2);
+        assert_eq!(db.get_image(first).unwrap().unwrap(), gradient);
+
+        db.delete(first).unwrap();
+        assert_eq!(count(&db, "blobs"), 2, "trashed entries keep their image");
+        db.empty_trash().unwrap();
+        assert_eq!(count(&db, "blobs"), 1);
+        assert_eq!(count(&db, "blob_thumbnails"), 1);
+        assert_eq!(db.get_image(other).unwrap().unwrap(), stripes);
+    }
+
+    fn backdate(db: &Database, id: i64, days: f64) {
+        db.conn.lock().unwrap().execute(
+            "UPDATE clipboard_history SET created_at = datetime('now', ?2) WHERE id = ?1",
+            params![id, format!("-{} days", days)]
+        ).unwrap();
+    }
+
+    /// Run the policy for real and check it removed exactly what the dry run
+    /// reported, returning the ids and reasons.
+    fn clean(db: &Database, policy: &RetentionPolicy) -> Vec<(i64, RetentionReason)> {
+        let trash = |db: &Database| -> HashSet<i64> { db.get_trash().unwrap().into_iter().map(|entry| entry.id).collect() };
+        let before = trash(db);
+        let preview: Vec<_> = db.cleanup_preview(policy).unwrap().into_iter().map(|c| (c.id, c.reason)).collect();
+        assert_eq!(db.cleanup(policy).unwrap(), preview.len());
+        let trashed: HashSet<i64> = trash(db).difference(&before).copied().collect();
+        assert_eq!(trashed, preview.iter().map(|(id, _)| *id).collect());
+        preview
+    }
+
+    #[test]
+    fn cleanup_ages_out_by_type_and_app() {
+        let db = temp_db("retention-age");
+        let policy = RetentionPolicy {
+            max_age_days: Some(30.0),
+            max_entries: None,
+            content_type_max_age_days: BTreeMap::from([("url".to_string(), 1.0)]),
+            app_max_age_days: BTreeMap::from([("keepass".to_string(), 0.5)]),
+            ..RetentionPolicy::default()
+        };
+
+        let link = db.insert("https://example.com", None, None).unwrap();
+        let note = db.insert("a recent note", None, None).unwrap();
+        let secret = db.insert("hunter2", Some("KeePassXC"), None).unwrap();
+        let old = db.insert("an old note", None, None).unwrap();
+        let pinned = db.insert("an old pinned note", None, None).unwrap();
+        let tagged = db.insert("an old tagged note", None, None).unwrap();
+        for id in [link, note] {
+            backdate(&db, id, 2.0);
+        }
+        backdate(&db, secret, 1.0);
+        for id in [old, pinned, tagged] {
+            backdate(&db, id, 40.0);
+        }
+        db.toggle_pin(pinned).unwrap();
+        let tag = db.create_tag("keep").unwrap();
+        db.tag_entries(tag.id, &[tagged]).unwrap();
+
+        let mut removed = clean(&db, &policy);
+        removed.sort_by_key(|(id, _)| *id);
+        assert_eq!(removed, vec![
+            (link, RetentionReason::Age),
+            (secret, RetentionReason::Age),
+            (old, RetentionReason::Age),
+        ]);
+        assert!(clean(&db, &policy).is_empty());
+    }
+
+    #[test]
+    fn cleanup_caps_count_and_storage() {
+        let db = temp_db("retention-caps");
+        let count_policy = RetentionPolicy { max_age_days: None, max_entries: Some(2), ..RetentionPolicy::default() };
+        let pinned = db.insert("oldest but pinned", None, None).unwrap();
+        let older = db.insert("older", None, None).unwrap();
+        let newer = db.insert("newer", None, None).unwrap();
+        backdate(&db, pinned, 3.0);
+        backdate(&db, older, 2.0);
+        backdate(&db, newer, 1.0);
+        db.toggle_pin(pinned).unwrap();
+
+        // The pinned entry is never removed but still takes one of the slots
+        assert_eq!(clean(&db, &count_policy), vec![(older, RetentionReason::Count)]);
+
+        let db = temp_db("retention-storage");
+        let storage_policy = RetentionPolicy {
+            max_age_days: None,
+            max_entries: None,
+            max_storage_mb: Some(100.0 / (1024.0 * 1024.0)),
+            ..RetentionPolicy::default()
+        };
+        let big_old = db.insert(&"a".repeat(60), None, None).unwrap();
+        let big_new = db.insert(&"b".repeat(60), None, None).unwrap();
+        backdate(&db, big_old, 2.0);
+        backdate(&db, big_new, 1.0);
+
+        assert_eq!(clean(&db, &storage_policy), vec![(big_old, RetentionReason::Storage)]);
+    }
+
+    fn search_ids(db: &Database, query: &str) -> Vec<i64> {
+        let query = SearchQuery::parse(query).unwrap();
+        db.search(&query, SearchMode::Auto, SearchSort::Relevance, 0, 50)
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    #[test]
+    fn tags_are_searchable_and_keep_entries_from_cleanup() {
+        let db = temp_db("tags");
+        let alpha = db.insert("alpha one", None, None).unwrap();
+        let beta = db.insert("beta two", None, None).unwrap();
+        let tag = db.create_tag("Client Work").unwrap();
+        assert!(db.create_tag("client work").is_err());
+        db.tag_entries(tag.id, &[alpha]).unwrap();
+
+        assert_eq!(search_ids(&db, "client"), vec![alpha]);
+        assert_eq!(search_ids(&db, "tag:\"client work\""), vec![alpha]);
+        assert_eq!(db.get_by_id(alpha).unwrap().unwrap().tags, vec!["Client Work"]);
+
+        let policy = RetentionPolicy { max_entries: Some(0), ..RetentionPolicy::default() };
+        let candidates: Vec<i64> = db.cleanup_preview(&policy).unwrap().into_iter().map(|c| c.id).collect();
+        assert_eq!(candidates, vec![beta]);
+
+        db.rename_tag(tag.id, "archive").unwrap();
+        assert!(search_ids(&db, "client").is_empty());
+        assert_eq!(search_ids(&db, "archive"), vec![alpha]);
+
+        db.delete_tag(tag.id).unwrap();
+        assert!(db.get_by_id(alpha).unwrap().unwrap().tags.is_empty());
+        assert_eq!(db.cleanup_preview(&policy).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn edits_are_kept_as_revisions_that_can_be_restored() {
+        let db = temp_db("revisions");
+        let id = db.insert("line1\nline2", None, None).unwrap();
+        db.update_content(id, "line1\nline2 changed").unwrap();
+        db.update_content(id, "line1\nline2 changed").unwrap();
+
+        let revisions = db.get_revisions(id).unwrap();
+        assert_eq!(revisions.len(), 1, "unchanged saves record nothing");
+        assert_eq!(revisions[0].content, "line1\nline2");
+
+        let diff: Vec<(DiffTag, String)> = db.diff_revisions(id, Some(revisions[0].id), None).unwrap()
+            .into_iter()
+            .map(|line| (line.tag, line.text))
+            .collect();
+        assert_eq!(diff, vec![
+            (DiffTag::Equal, "line1\n".to_string()),
+            (DiffTag::Delete, "line2".to_string()),
+            (DiffTag::Insert, "line2 changed".to_string()),
+        ]);
+
+        db.restore_revision(revisions[0].id).unwrap();
+        assert_eq!(db.get_by_id(id).unwrap().unwrap().content, "line1\nline2");
+        let contents: Vec<String> = db.get_revisions(id).unwrap().into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["line1\nline2 changed", "line1\nline2"]);
+
+        db.delete(id).unwrap();
+        db.empty_trash().unwrap();
+        assert!(db.get_revisions(id).unwrap().is_empty());
+
+        let image = png(8, 8, |x, _| (x * 30) as u8);
+        let screenshot = db.insert("[Image 8x8]", None, Some(&image)).unwrap();
+        db.update_content(screenshot, "Login screen").unwrap();
+        assert_eq!(db.insert("[Image 8x8]", None, Some(&image)).unwrap(), screenshot, "a renamed image is still deduplicated");
+    }
+
+    #[test]
+    fn search_pages_report_the_total_and_next_offset() {
+        let db = temp_db("pages");
+        let mut ids: Vec<i64> = (0..5).map(|i| db.insert(&format!("note {i}"), None, None).unwrap()).collect();
+        db.insert("something else", None, None).unwrap();
+        let query = SearchQuery::parse("note").unwrap();
+
+        let mut seen = Vec::new();
+        let mut offsets = Vec::new();
+        let mut offset = Some(0);
+        while let Some(start) = offset {
+            let page = db.search(&query, SearchMode::Auto, SearchSort::Relevance, start, 2).unwrap();
+            assert_eq!(page.total, 5);
+            seen.extend(page.entries.iter().map(|entry| entry.id));
+            offsets.push(page.next_offset);
+            offset = page.next_offset;
+        }
+        assert_eq!(offsets, vec![Some(2), Some(4), None]);
+        seen.sort();
+        ids.sort();
+        assert_eq!(seen, ids);
+
+        let past_the_end = db.search(&query, SearchMode::Auto, SearchSort::Relevance, 10, 2).unwrap();
+        assert!(past_the_end.entries.is_empty());
+        assert_eq!((past_the_end.total, past_the_end.next_offset), (5, None));
+    }
+
+    #[test]
+    fn frecency_ranks_often_used_entries_first() {
+        let db = temp_db("frecency");
+        let used = db.insert("note used often", None, None).unwrap();
+        let newer = db.insert("note copied once", None, None).unwrap();
+        backdate(&db, used, 3.0);
+        for _ in 0..3 {
+            db.record_use(used).unwrap();
+        }
+        let entry = db.get_by_id(used).unwrap().unwrap();
+        assert_eq!(entry.use_count, 3);
+        assert!(entry.last_used_at.is_some());
+
+        let order = |query: &str, sort: SearchSort| -> Vec<i64> {
+            let query = SearchQuery::parse(query).unwrap();
+            db.search(&query, SearchMode::Auto, sort, 0, 10).unwrap().entries.into_iter().map(|e| e.id).collect()
+        };
+        assert_eq!(order("", SearchSort::Relevance), vec![newer, used]);
+        assert_eq!(order("", SearchSort::Frecency), vec![used, newer]);
+        assert_eq!(order("note", SearchSort::Frecency), vec![used, newer]);
+    }
+
+    #[test]
+    fn copies_are_deduplicated_by_hash() {
+        let db = temp_db("dedup");
+        let first = db.insert("hello world", Some("Editor"), None).unwrap();
+        assert_eq!(db.insert("hello world", None, None).unwrap(), first);
+        assert_eq!(db.get_by_id(first).unwrap().unwrap().source_app.as_deref(), Some("Editor"));
+        let spaced = db.insert("hello   world\n", None, None).unwrap();
+        assert_ne!(spaced, first);
+
+        // Copying a trashed entry again brings it back
+        db.delete(first).unwrap();
+        assert_eq!(db.insert("hello world", None, None).unwrap(), first);
+        assert!(db.get_by_id(first).unwrap().unwrap().deleted_at.is_none());
+
+        db.set_dedup_mode(DedupMode::Whitespace).unwrap();
+        assert_eq!(db.dedup_mode().unwrap(), DedupMode::Whitespace);
+        let matched = db.insert("  hello\tworld ", None, None).unwrap();
+        assert!(matched == first || matched == spaced);
+        assert_eq!(count(&db, "clipboard_history"), 2);
+        assert_ne!(db.insert("hello world!", None, None).unwrap(), matched);
+    }
+
+    #[test]
+    fn near_duplicate_images_are_found_by_perceptual_hash() {
+        let db = temp_db("phash");
+        let screenshot = png(64, 32, |x, _| (x * 4) as u8);
+        let retaken = png(64, 32, |x, y| if (x, y) == (10, 10) { 255 } else { (x * 4) as u8 });
+        let unrelated = png(64, 32, |x, _| 255 - (x * 4) as u8);
+
+        let original = db.insert("[Image 64x32]", None, Some(&screenshot)).unwrap();
+        let edited = db.insert("[Image 64x32]", None, Some(&retaken)).unwrap();
+        let other = db.insert("[Image 64x32]", None, Some(&unrelated)).unwrap();
+        assert_ne!(original, edited, "a one pixel change is a different image");
+
+        let similar: Vec<i64> = db.find_similar_images(original, NEAR_DUPLICATE_DISTANCE).unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(similar, vec![edited]);
+        assert!(!db.find_similar_images(other, NEAR_DUPLICATE_DISTANCE).unwrap().iter().any(|e| e.id == original));
+
+        let text = db.insert("not an image", None, None).unwrap();
+        assert!(db.find_similar_images(text, NEAR_DUPLICATE_DISTANCE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn trashed_entries_are_hidden_until_restored() {
+        let db = temp_db("trash");
+        let older = db.insert("older note", None, None).unwrap();
+        let newer = db.insert("newer note", None, None).unwrap();
+        backdate(&db, older, 1.0);
+
+        db.delete(newer).unwrap();
+        assert_eq!(search_ids(&db, "note"), vec![older]);
+        assert_eq!(db.get_last_content().unwrap().as_deref(), Some("older note"));
+        db.delete(older).unwrap();
+        assert!(search_ids(&db, "").is_empty());
+        assert_eq!(db.get_last_content().unwrap(), None);
+        assert_eq!(db.get_trash().unwrap().len(), 2);
+
+        // Undo brings back the most recent delete only
+        assert_eq!(db.undo_delete().unwrap(), 1);
+        assert_eq!(search_ids(&db, "note"), vec![older]);
+        db.restore_entry(newer).unwrap();
+        assert_eq!(db.get_last_content().unwrap().as_deref(), Some("newer note"));
+        assert_eq!(db.undo_delete().unwrap(), 0);
+
+        db.delete(older).unwrap();
+        assert_eq!(db.empty_trash().unwrap(), 1);
+        assert!(db.get_by_id(older).unwrap().is_none());
+        assert!(db.get_trash().unwrap().is_empty());
+    }
+
+    #[test]
+    fn searches_return_thumbnails_and_images_load_on_demand() {
+        let db = temp_db("thumbnails");
+        let full = png(960, 240, |x, y| ((x + y) % 256) as u8);
+        let id = db.insert("[Image 960x240]", None, Some(&full)).unwrap();
+
+        let query = SearchQuery::parse("").unwrap();
+        let entry = db.search(&query, SearchMode::Auto, SearchSort::Relevance, 0, 10).unwrap().entries.remove(0);
+        assert_eq!(entry.id, id);
+        assert_eq!(entry.content, "[Image 960x240]");
+        let thumbnail = image::load_from_memory(&STANDARD.decode(entry.thumbnail.unwrap()).unwrap()).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (THUMBNAIL_MAX_WIDTH, 60));
+
+        assert_eq!(db.get_image(id).unwrap().unwrap(), full);
+        let text = db.insert("no picture", None, None).unwrap();
+        assert!(db.get_by_id(text).unwrap().unwrap().thumbnail.is_none());
+        assert!(db.get_image(text).unwrap().is_none());
+    }
+
+    fn archived(content: &str, content_type: &str, language: Option<&str>) -> ArchiveEntry {
+        ArchiveEntry {
+            content: content.to_string(),
+            content_type: content_type.to_string(),
+            language: language.map(str::to_string),
+            source_app: None,
+            created_at: "2024-04-30 09:15:00".to_string(),
+            is_pinned: false,
+            tags: vec![],
+            image: None,
+        }
+    }
+
+    fn kind_of(db: &Database, content: &str) -> (ContentType, Option<Language>) {
+        let conn = db.conn.lock().unwrap();
+        let id: i64 = conn.query_row(
+            "SELECT id FROM clipboard_history WHERE content = ?1",
+            params![content],
+            |row| row.get(0)
+        ).unwrap();
+        drop(conn);
+        let entry = db.get_by_id(id).unwrap().unwrap();
+        (entry.content_type, entry.language)
+    }
+
+    #[test]
+    fn import_keeps_the_archived_type() {
+        let db = temp_db("import-type");
+        db.import_entries(&[
+            archived("fn main() {}", "text", None),
+            archived("let x = 1;", "code", Some("rust")),
+            archived("def f(): pass", "code", Some("cobol")),
+            archived("https://example.com", "hyperlink", None),
+        ]).unwrap();
+
+        assert_eq!(kind_of(&db, "fn main() {}"), (ContentType::Text, None));
+        assert_eq!(kind_of(&db, "let x = 1;"), (ContentType::Code, Some(Language::Rust)));
+        // Unknown names fall back to the classifier
+        assert_eq!(kind_of(&db, "https://example.com"), (ContentType::Url, None));
+        assert_eq!(kind_of(&db, "def f(): pass").0, ContentType::Code);
+    }
+
+    #[test]
+    fn filters_by_type_and_language() {
+        let db = temp_db("classify");
+        let code = db.insert("fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}", None, None).unwrap();
+        let prose = db.insert("let me know", None, None).unwrap();
+
+        assert_eq!(search_ids(&db, "lang:rust"), vec![code]);
+        assert_eq!(search_ids(&db, "lang:rs"), vec![code]);
+        assert_eq!(search_ids(&db, "type:text"), vec![prose]);
+        assert!(search_ids(&db, "lang:python").is_empty());
+
+        // Edits are classified again
+        db.update_content(prose, "{\"a\": 1}").unwrap();
+        assert_eq!(search_ids(&db, "type:json"), vec![prose]);
+    }
+
+    #[test]
+    fn expired_entries_are_reaped_with_their_fingerprint() {
+        let db = temp_db("expiry");
+        assert!(db.reap_expired().unwrap().is_empty());
+        assert_eq!(db.next_expiry().unwrap(), None);
+
+        let code = db.insert("otp 123456", None, None).unwrap();
+        let kept = db.insert("plain", None, None).unwrap();
+        db.set_expiry(kept, Some(5)).unwrap();
+        let next = db.next_expiry().unwrap().unwrap();
+        assert!(next.as_secs() > 290 && next.as_secs() <= 300, "{:?}", next);
+        db.set_expiry(kept, None).unwrap();
+        assert_eq!(db.next_expiry().unwrap(), None);
+
+        db.mark_sensitive(code, Sensitivity::ApiKey, Some(0)).unwrap();
+        assert_eq!(db.reap_expired().unwrap(), vec![db.clipboard_fingerprint("  otp 123456\n")]);
+        assert!(db.get_by_id(code).unwrap().is_none());
+        assert!(db.get_by_id(kept).unwrap().unwrap().expires_at.is_none());
+        assert!(db.reap_expired().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_entries_are_recognised_while_locked() {
+        let db = temp_db("expiry-locked");
+        db.enable_encryption(&KeySource::Passphrase("correct horse".to_string())).unwrap();
+        let id = db.insert("otp 654321", None, None).unwrap();
+        db.set_expiry(id, Some(0)).unwrap();
+        db.lock().unwrap();
+
+        assert_eq!(db.reap_expired().unwrap(), vec![db.clipboard_fingerprint("otp 654321")]);
+        db.unlock(Some("correct horse")).unwrap();
+        assert!(db.get_by_id(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn enabling_encryption_drops_content_derived_uuids() {
+        let db = temp_db("uuids");
+        let text = db.insert("shared secret", None, None).unwrap();
+        let image = png(8, 8, |x, y| (x * y) as u8);
+        let picture = db.insert("[Image 8x8]", None, Some(&image)).unwrap();
+        db.conn.lock().unwrap().execute(
+            "INSERT INTO sync_state (uuid, content_hash, is_pinned, tags) SELECT uuid, content_hash, 0, '' FROM clipboard_history",
+            [],
+        ).unwrap();
+        assert_eq!(count(&db, "sync_state"), 2);
+        let uuid = |id: i64| -> String {
+            db.conn.lock().unwrap().query_row("SELECT uuid FROM clipboard_history WHERE id = ?1", [id], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(uuid(text), content_uuid(&blob_hash("shared secret".as_bytes())));
+        assert_eq!(uuid(picture), content_uuid(&blob_hash(&image)));
+
+        db.enable_encryption(&KeySource::Passphrase("correct horse".to_string())).unwrap();
+        assert_ne!(uuid(text), content_uuid(&blob_hash("shared secret".as_bytes())));
+        assert_ne!(uuid(picture), content_uuid(&blob_hash(&image)));
+        assert_eq!(count(&db, "sync_state"), 0);
+    }
+
+    /// Stored values that still contain `needle` in the clear.
+    fn leaked(db: &Database, needle: &[u8]) -> Vec<String> {
+        let conn = db.conn.lock().unwrap();
+        let mut found = Vec::new();
+        for &(table, key, column, _) in SEALED_COLUMNS {
+            let mut stmt = conn.prepare(&format!("SELECT {key}, CAST({column} AS BLOB) FROM {table}")).unwrap();
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, Value>(0)?, row.get::<_, Vec<u8>>(1)?))).unwrap();
+            for (row_key, value) in rows.map(Result::unwrap) {
+                if value.windows(needle.len()).any(|window| window == needle) {
+                    found.push(format!("{}.{} {:?}", table, column, row_key));
+                }
+            }
+        }
+        found
+    }
+
+    fn blob_addresses(db: &Database) -> Vec<String> {
+        let conn = db.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT hash FROM blobs UNION ALL SELECT hash FROM blob_thumbnails").unwrap();
+        let addresses = stmt.query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        addresses
+    }
+
+    #[test]
+    fn encryption_seals_existing_history_and_round_trips() {
+        let db = temp_db("encryption");
+        let note = db.insert("  staple horse\n\tbattery ünïcödé 🐎  ", Some("notes"), None).unwrap();
+        db.update_content(note, "correct horse battery staple").unwrap();
+        let image = png(64, 32, |x, y| (x * 3 + y) as u8);
+        let screenshot = db.insert("[Image 64x32]", None, Some(&image)).unwrap();
+        let address = blob_hash(&image);
+        assert_eq!(blob_addresses(&db), vec![address.clone(), address.clone()]);
+
+        db.enable_encryption(&KeySource::Passphrase("tr0ub4dor".to_string())).unwrap();
+        assert!(leaked(&db, b"horse").is_empty(), "{:?}", leaked(&db, b"horse"));
+        assert!(leaked(&db, &image[..16]).is_empty(), "{:?}", leaked(&db, &image[..16]));
+        assert!(blob_addresses(&db).iter().all(|hash| *hash != address), "image addresses are keyed");
+
+        // Reads and the in-memory index see through the encryption
+        assert_eq!(db.get_by_id(note).unwrap().unwrap().content, "correct horse battery staple");
+        assert_eq!(db.get_revisions(note).unwrap()[0].content, "  staple horse\n\tbattery ünïcödé 🐎  ");
+        assert_eq!(db.get_image(screenshot).unwrap().unwrap(), image);
+        assert_eq!(search_ids(&db, "battery"), vec![note]);
+        assert_eq!(search_ids(&db, "orse batt"), vec![note]);
+        assert_eq!(db.insert("[Image 64x32]", None, Some(&image)).unwrap(), screenshot, "images are still deduplicated");
+        let added = db.insert("horse shoe", None, None).unwrap();
+        assert_eq!(search_ids(&db, "shoe"), vec![added]);
+        assert!(leaked(&db, b"shoe").is_empty());
+
+        db.disable_encryption().unwrap();
+        assert_eq!(blob_addresses(&db), vec![address.clone(), address]);
+        assert_eq!(db.get_by_id(note).unwrap().unwrap().content, "correct horse battery staple");
+        assert_eq!(db.get_revisions(note).unwrap()[0].content, "  staple horse\n\tbattery ünïcödé 🐎  ");
+        assert_eq!(db.get_image(screenshot).unwrap().unwrap(), image);
+        assert_eq!(search_ids(&db, "shoe"), vec![added]);
+        assert_eq!(db.insert("horse shoe", None, None).unwrap(), added);
+        let conn = db.conn.lock().unwrap();
+        let encrypted: i64 = conn.query_row("SELECT COUNT(*) FROM clipboard_history WHERE encrypted = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(encrypted, 0);
+    }
+
+    #[test]
+    fn locked_history_needs_the_right_passphrase() {
+        let db = temp_db("locking");
+        let id = db.insert("launch codes", None, None).unwrap();
+        db.enable_encryption(&KeySource::Passphrase("tr0ub4dor".to_string())).unwrap();
+        assert!(db.enable_encryption(&KeySource::Passphrase("again".to_string())).is_err());
+
+        db.lock().unwrap();
+        assert!(db.is_locked());
+        assert!(db.get_by_id(id).is_err());
+        assert!(db.search(&SearchQuery::parse("launch").unwrap(), SearchMode::Auto, SearchSort::Relevance, 0, 50).is_err());
+        assert!(db.insert("while locked", None, None).is_err());
+        assert!(db.disable_encryption().is_err());
+
+        assert_eq!(db.unlock(Some("Tr0ub4dor")).unwrap_err(), "Wrong passphrase or key file");
+        assert!(db.unlock(None).is_err());
+        assert!(db.is_locked());
+
+        db.unlock(Some("tr0ub4dor")).unwrap();
+        assert!(!db.is_locked());
+        assert_eq!(db.get_by_id(id).unwrap().unwrap().content, "launch codes");
+        assert_eq!(search_ids(&db, "launch"), vec![id]);
+    }
+
+    #[test]
+    fn secrets_are_sealed_with_the_history() {
+        let db = temp_db("secrets");
+        let stored = || -> (Vec<u8>, bool) {
+            db.conn.lock().unwrap().query_row(
+                "SELECT CAST(value AS BLOB), encrypted FROM secrets WHERE key = 'lan_peers'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).unwrap()
+        };
+        db.set_secret("lan_peers", "[\"pairing key\"]").unwrap();
+        assert_eq!(stored(), (b"[\"pairing key\"]".to_vec(), false));
+        assert_eq!(db.get_setting("lan_peers").unwrap(), None);
+
+        db.enable_encryption(&KeySource::Passphrase("correct horse".to_string())).unwrap();
+        assert!(stored().1);
+        assert_eq!(db.get_secret("lan_peers").unwrap().as_deref(), Some("[\"pairing key\"]"));
+        db.lock().unwrap();
+        assert!(db.get_secret("lan_peers").is_err());
+        assert!(db.set_secret("lan_peers", "[]").is_err());
+
+        db.unlock(Some("correct horse")).unwrap();
+        db.disable_encryption().unwrap();
+        assert_eq!(stored(), (b"[\"pairing key\"]".to_vec(), false));
+    }
+}
@@ -0,0 +1,66 @@
+//! Deletion of entries whose expiry has passed.
+//!
+//! Expiring entries are mostly one-time codes and secrets, so the reaper
+//! sleeps until the next expiry rather than following the retention
+//! schedule, and clears the system clipboard when it still holds a value
+//! that was just deleted. Setting an expiry wakes it with `wake`.
+
+use crate::clipboard;
+use crate::database::Database;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Entries are deleted within this long of expiring.
+const MIN_SLEEP: Duration = Duration::from_secs(1);
+/// Also look again now and then, for expiries that arrive without a wake,
+/// such as another profile becoming active.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+static WAKE: Lazy<(Mutex<bool>, Condvar)> = Lazy::new(|| (Mutex::new(false), Condvar::new()));
+
+/// Have the reaper look at expiries again, after one was set or changed.
+pub fn wake() {
+    let (woken, condvar) = &*WAKE;
+    *woken.lock().unwrap() = true;
+    condvar.notify_one();
+}
+
+fn sleep(timeout: Duration) {
+    let (woken, condvar) = &*WAKE;
+    let guard = woken.lock().unwrap();
+    let (mut woken, _) = condvar.wait_timeout_while(guard, timeout, |woken| !*woken).unwrap();
+    *woken = false;
+}
+
+/// Delete expired entries as they expire, for as long as the app runs.
+pub fn start_reaper<F>(db: F)
+where
+    F: Fn() -> Arc<Database> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        let db = db();
+        match db.reap_expired() {
+            Ok(fingerprints) if !fingerprints.is_empty() => {
+                let fingerprints: HashSet<String> = fingerprints.into_iter().collect();
+                if let Err(e) = clipboard::clear_clipboard_if(|text| fingerprints.contains(&db.clipboard_fingerprint(text))) {
+                    eprintln!("Failed to clear expired entry from the clipboard: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to delete expired entries: {}", e),
+        }
+
+        let next = match db.next_expiry() {
+            Ok(next) => next.unwrap_or(MAX_SLEEP),
+            Err(e) => {
+                eprintln!("Failed to read the next expiry: {}", e);
+                MAX_SLEEP
+            }
+        };
+        drop(db);
+        sleep(next.clamp(MIN_SLEEP, MAX_SLEEP));
+    });
+}
@@ -0,0 +1,198 @@
+//! Typo-tolerant scoring for the fuzzy search fallback.
+//!
+//! Candidates come from the trigram FTS index (any shared trigram), so this
+//! only ranks a bounded set of rows and never scans the whole history.
+
+/// Minimum share of query trigrams a candidate must contain when the query
+/// is not a subsequence of it.
+const MIN_TRIGRAM_SIMILARITY: f64 = 0.3;
+
+/// Only the start of very long entries is scored.
+const MAX_SCORED_CHARS: usize = 16 * 1024;
+
+/// Distinct lowercase trigrams of every word in `query`.
+pub fn trigrams(query: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for word in query.to_lowercase().split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+        for window in chars.windows(3) {
+            let trigram: String = window.iter().collect();
+            if !out.contains(&trigram) {
+                out.push(trigram);
+            }
+        }
+    }
+    out
+}
+
+/// FTS5 `MATCH` expression that finds rows sharing any trigram with the query.
+pub fn candidate_match(trigrams: &[String]) -> String {
+    trigrams
+        .iter()
+        .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// A plausible fuzzy match of a query within an entry.
+#[derive(Debug, Clone)]
+pub struct FuzzyMatch {
+    /// Higher is better.
+    pub score: f64,
+    /// Character indices into the entry that matched, for highlighting.
+    pub positions: Vec<usize>,
+}
+
+/// Score `text` against `query`, or `None` if it is not a plausible match.
+pub fn score(query: &str, query_trigrams: &[String], text: &str) -> Option<FuzzyMatch> {
+    // Lowercase per character so indices still line up with `text`
+    let chars: Vec<char> = text
+        .chars()
+        .take(MAX_SCORED_CHARS)
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect();
+    let lower: String = chars.iter().collect();
+    let query = query.to_lowercase();
+
+    let shared: Vec<&String> = query_trigrams.iter().filter(|t| lower.contains(t.as_str())).collect();
+    let similarity = if query_trigrams.is_empty() {
+        0.0
+    } else {
+        shared.len() as f64 / query_trigrams.len() as f64
+    };
+
+    let subsequence = query
+        .split_whitespace()
+        .map(|word| subsequence_score(word, &chars))
+        .try_fold((0.0, Vec::new()), |(total, mut positions), found| {
+            found.map(|(score, matched)| {
+                positions.extend(matched);
+                (total + score, positions)
+            })
+        });
+
+    match subsequence {
+        Some((bonus, positions)) => Some(FuzzyMatch { score: similarity * 100.0 + bonus, positions }),
+        None if similarity >= MIN_TRIGRAM_SIMILARITY => Some(FuzzyMatch {
+            score: similarity * 100.0,
+            positions: shared
+                .iter()
+                .filter_map(|t| lower.find(t.as_str()))
+                .flat_map(|byte| {
+                    let start = lower[..byte].chars().count();
+                    start..start + 3
+                })
+                .collect(),
+        }),
+        None => None,
+    }
+}
+
+/// fzf-style score for `needle` appearing in order within a short span of
+/// `haystack`: consecutive characters and matches at word starts score
+/// higher, gaps cost a little. Both inputs are expected to be lowercase.
+fn subsequence_score(needle: &str, haystack: &[char]) -> Option<(f64, Vec<usize>)> {
+    let needle: Vec<char> = needle.chars().collect();
+    let first = *needle.first()?;
+    let max_span = needle.len() * 2 + 2;
+    let mut best: Option<(f64, Vec<usize>)> = None;
+
+    for start in (0..haystack.len()).filter(|&i| haystack[i] == first) {
+        let end = (start + max_span).min(haystack.len());
+        let mut score = 0.0;
+        let mut positions = Vec::with_capacity(needle.len());
+
+        for i in start..end {
+            if haystack[i] != needle[positions.len()] {
+                continue;
+            }
+            score += 1.0;
+            match positions.last() {
+                Some(&last) if last + 1 == i => score += 2.0,
+                Some(&last) => score -= (i - last) as f64 * 0.25,
+                None => {}
+            }
+            if i == 0 || !haystack[i - 1].is_alphanumeric() {
+                score += 1.5;
+            }
+            positions.push(i);
+            if positions.len() == needle.len() {
+                if best.as_ref().is_none_or(|(b, _)| score > *b) {
+                    best = Some((score, positions));
+                }
+                break;
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuzzy(query: &str, text: &str) -> Option<FuzzyMatch> {
+        score(query, &trigrams(query), text)
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn trigrams_are_distinct_and_stay_within_words() {
+        assert_eq!(trigrams("Hello  WORLD hi"), vec!["hel", "ell", "llo", "wor", "orl", "rld"]);
+        assert_eq!(trigrams("aaaa aaa"), vec!["aaa"]);
+        assert_eq!(trigrams("Größe"), vec!["grö", "röß", "öße"]);
+        assert!(trigrams("a bc").is_empty());
+        assert_eq!(candidate_match(&["a\"b".to_string(), "abc".to_string()]), "\"a\"\"b\" OR \"abc\"");
+    }
+
+    #[test]
+    fn subsequences_prefer_word_starts_and_runs() {
+        // f at a word start, then b four characters on, also at a word start
+        assert_eq!(subsequence_score("fb", &chars("foo bar")), Some((4.0, vec![0, 4])));
+        // The run "ab" beats the spread match from the first a
+        assert_eq!(subsequence_score("ab", &chars("a_xab")), Some((4.0, vec![3, 4])));
+        // Too far apart for a two character needle
+        assert_eq!(subsequence_score("fb", &chars("f_________b")), None);
+        assert_eq!(subsequence_score("", &chars("foo")), None);
+    }
+
+    #[test]
+    fn typos_still_match() {
+        let missing = fuzzy("pasword", "my password is long").unwrap();
+        assert_eq!(missing.positions, vec![3, 4, 5, 7, 8, 9, 10]);
+
+        // A doubled letter is no subsequence, but most trigrams are shared
+        let doubled = fuzzy("passwword", "my password is long").unwrap();
+        assert!((doubled.score - 500.0 / 7.0).abs() < 1e-9, "{}", doubled.score);
+
+        assert!(fuzzy("password", "my password").unwrap().score > missing.score);
+    }
+
+    #[test]
+    fn weak_matches_are_rejected() {
+        assert!(fuzzy("zebra", "completely unrelated").is_none());
+        // Sharing one of four trigrams is below the minimum similarity
+        assert_eq!(trigrams("abcxyz").len(), 4);
+        assert!(fuzzy("abcxyz", "abc and nothing else").is_none());
+        assert!(fuzzy("abcxyz", "abc and then cxyz").is_some());
+    }
+
+    #[test]
+    fn positions_count_characters_not_bytes() {
+        let text = "Grüße aus München";
+        assert_eq!(fuzzy("mün", text).unwrap().positions, vec![10, 11, 12]);
+
+        // "munchen" is no subsequence (ü is not u), so the shared trigrams
+        // "nch", "che" and "hen" are highlighted
+        let found = fuzzy("munchen", text).unwrap();
+        let mut positions = found.positions.clone();
+        positions.sort_unstable();
+        positions.dedup();
+        assert_eq!(positions, vec![12, 13, 14, 15, 16]);
+        assert!(positions.iter().all(|&i| i < text.chars().count()));
+    }
+}
@@ -0,0 +1,1001 @@
+//! Peer-to-peer clipboard sharing on the local network.
+//!
+//! Every instance with sharing enabled listens on a TCP port. One device
+//! shows a one-time pairing code, the other connects with it, and both keep
+//! the other as a peer with a shared secret. From then on newly captured
+//! entries are pushed to every peer whose rules allow them.
+//!
+//! Each connection starts with a handshake: both sides send a JSON `Hello`
+//! frame carrying an ephemeral X25519 key, then derive session keys with
+//! HKDF from the Diffie-Hellman result, salted with the pairing code
+//! (stretched with Argon2id over the transcript) or the stored peer secret.
+//! The initiator proves it holds the same keys first, then the responder.
+//! Everything after that is ChaCha20-Poly1305 with a counter nonce per
+//! direction. Frames are a 4-byte big-endian length followed by the payload.
+//!
+//! A pairing code is consumed by the first attempt that sends its proof,
+//! right or wrong, and expires after a few minutes, which leaves no room to
+//! guess it. `LanService::pairing_status` tells the UI when an attempt used
+//! up the code so a new one can be shown.
+//!
+//! Before a handshake completes, frames are limited to a few KiB and only a
+//! handful of connections are served at once, so unauthenticated clients
+//! cannot make the listener allocate much.
+
+use crate::classify::ContentType;
+use crate::crypto;
+use crate::database::Database;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Settings key holding the JSON-encoded sharing configuration.
+pub const CONFIG_SETTING_KEY: &str = "lan_config";
+/// Secrets key holding the paired peers, sealed with the history while
+/// encryption is on.
+const PEERS_SECRET_KEY: &str = "lan_peers";
+const DEVICE_ID_KEY: &str = "lan_device_id";
+
+pub const DEFAULT_PORT: u16 = 47823;
+const PROTOCOL_VERSION: u32 = 1;
+const KEY_INFO: &[u8] = b"clipstream-lan-v1";
+
+/// 32 symbols without look-alikes such as 0/O and 1/I; 8 of them make a
+/// 40-bit code.
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LEN: usize = 8;
+const CODE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// Large enough for a full-size screenshot. Only accepted from an
+/// authenticated peer.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+/// Hello and proof frames are a few hundred bytes.
+const MAX_HANDSHAKE_FRAME_LEN: usize = 4 * 1024;
+/// Connections handled at once; more are closed right away.
+const MAX_CONNECTIONS: usize = 16;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanConfig {
+    pub enabled: bool,
+    /// Takes effect on the next start.
+    pub port: u16,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+impl LanConfig {
+    /// Load the configuration from settings, falling back to the defaults.
+    pub fn load(db: &Database) -> Self {
+        match db.get_setting(CONFIG_SETTING_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Invalid LAN sharing config, using defaults: {}", e);
+                Self::default()
+            }),
+            Ok(None) => Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read LAN sharing config: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        db.set_setting(CONFIG_SETTING_KEY, &json).map_err(|e| e.to_string())
+    }
+}
+
+/// What is exchanged with a peer, in both directions.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerRules {
+    pub text: bool,
+    pub images: bool,
+    /// Only entries that are pinned; they are sent when pinned.
+    pub pinned_only: bool,
+}
+
+impl Default for PeerRules {
+    fn default() -> Self {
+        Self {
+            text: true,
+            images: true,
+            pinned_only: false,
+        }
+    }
+}
+
+impl PeerRules {
+    pub fn allows(&self, entry: &SharedEntry) -> bool {
+        let kind = if entry.image.is_some() { self.images } else { self.text };
+        kind && (entry.is_pinned || !self.pinned_only)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub device_id: String,
+    pub name: String,
+    /// Where the peer listens, updated whenever it connects.
+    pub address: String,
+    pub rules: PeerRules,
+}
+
+/// A peer as stored, with the secret that never leaves the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PeerRecord {
+    #[serde(flatten)]
+    peer: Peer,
+    /// Base64 encoded key established while pairing.
+    secret: String,
+}
+
+/// An entry as sent to a peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedEntry {
+    pub content: String,
+    pub source_app: Option<String>,
+    pub is_pinned: bool,
+    /// Base64 encoded PNG for image entries.
+    pub image: Option<String>,
+}
+
+pub fn peers(db: &Database) -> Vec<Peer> {
+    load_peers(db).into_iter().map(|record| record.peer).collect()
+}
+
+pub fn set_peer_rules(db: &Database, device_id: &str, rules: PeerRules) -> Result<(), String> {
+    let mut records = load_peers(db);
+    let record = records
+        .iter_mut()
+        .find(|record| record.peer.device_id == device_id)
+        .ok_or_else(|| format!("Unknown peer '{}'", device_id))?;
+    record.peer.rules = rules;
+    save_peers(db, &records)
+}
+
+/// Forget a peer. It can no longer connect until paired again.
+pub fn remove_peer(db: &Database, device_id: &str) -> Result<(), String> {
+    let mut records = load_peers(db);
+    records.retain(|record| record.peer.device_id != device_id);
+    save_peers(db, &records)
+}
+
+fn load_peers(db: &Database) -> Vec<PeerRecord> {
+    match db.get_secret(PEERS_SECRET_KEY) {
+        Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("Invalid LAN peer list: {}", e);
+            Vec::new()
+        }),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            eprintln!("Failed to read LAN peers: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn save_peers(db: &Database, records: &[PeerRecord]) -> Result<(), String> {
+    let json = serde_json::to_string(records).map_err(|e| e.to_string())?;
+    db.set_secret(PEERS_SECRET_KEY, &json).map_err(|e| e.to_string())
+}
+
+/// Add a freshly paired peer, replacing an earlier pairing with the same
+/// device but keeping its rules.
+fn store_peer(db: &Database, device_id: &str, name: &str, address: String, secret: &[u8; 32]) -> Result<Peer, String> {
+    let mut records = load_peers(db);
+    let rules = records
+        .iter()
+        .find(|record| record.peer.device_id == device_id)
+        .map(|record| record.peer.rules)
+        .unwrap_or_default();
+    records.retain(|record| record.peer.device_id != device_id);
+
+    let peer = Peer {
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        rules,
+    };
+    records.push(PeerRecord {
+        peer: peer.clone(),
+        secret: STANDARD.encode(secret),
+    });
+    save_peers(db, &records)?;
+    Ok(peer)
+}
+
+fn device_id(db: &Database) -> Result<String, String> {
+    if let Some(id) = db.get_setting(DEVICE_ID_KEY).map_err(|e| e.to_string())? {
+        return Ok(id);
+    }
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    db.set_setting(DEVICE_ID_KEY, &id).map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+fn device_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "ClipStream".to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Purpose {
+    Pair,
+    Share,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    version: u32,
+    purpose: Purpose,
+    device_id: String,
+    name: String,
+    /// Port the sender listens on, so the other side can reach it later.
+    port: u16,
+    /// Base64 encoded ephemeral X25519 public key.
+    key: String,
+}
+
+/// What authenticates a handshake besides the key exchange.
+enum Auth {
+    Code(String),
+    Secret([u8; 32]),
+}
+
+impl Auth {
+    fn key(&self, transcript: &[u8]) -> Result<[u8; 32], String> {
+        match self {
+            // Salting with the transcript ties every guess to one handshake
+            Auth::Code(code) => crypto::derive_key(code, transcript),
+            Auth::Secret(secret) => Ok(*secret),
+        }
+    }
+}
+
+struct SessionKeys {
+    initiator: [u8; 32],
+    responder: [u8; 32],
+    confirm: [u8; 32],
+    /// Long-term secret recorded when the handshake is a pairing.
+    pair_secret: [u8; 32],
+}
+
+impl SessionKeys {
+    fn derive(local: EphemeralSecret, remote_key: &str, transcript: &[u8], auth: &Auth) -> Result<Self, String> {
+        let remote: [u8; 32] = STANDARD
+            .decode(remote_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("Invalid handshake key")?;
+        let shared = local.diffie_hellman(&PublicKey::from(remote));
+        if !shared.was_contributory() {
+            return Err("Invalid handshake key".to_string());
+        }
+
+        let salt = auth.key(transcript)?;
+        let mut info = KEY_INFO.to_vec();
+        info.extend_from_slice(transcript);
+        let mut okm = [0u8; 128];
+        Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+            .expand(&info, &mut okm)
+            .map_err(|e| e.to_string())?;
+
+        let mut keys = Self {
+            initiator: [0; 32],
+            responder: [0; 32],
+            confirm: [0; 32],
+            pair_secret: [0; 32],
+        };
+        keys.initiator.copy_from_slice(&okm[..32]);
+        keys.responder.copy_from_slice(&okm[32..64]);
+        keys.confirm.copy_from_slice(&okm[64..96]);
+        keys.pair_secret.copy_from_slice(&okm[96..]);
+        Ok(keys)
+    }
+
+    fn proof(&self, role: &[u8]) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.confirm).expect("HMAC accepts any key length");
+        mac.update(role);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn verify(&self, role: &[u8], proof: &[u8]) -> Result<(), String> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.confirm).expect("HMAC accepts any key length");
+        mac.update(role);
+        mac.verify_slice(proof).map_err(|_| "Pairing code or peer key does not match".to_string())
+    }
+}
+
+/// One direction of an established connection.
+struct Channel {
+    aead: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Channel {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+            counter: 0,
+        }
+    }
+
+    /// The counter nonce also rejects replayed, dropped or reordered frames.
+    fn nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.nonce();
+        self.aead
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("ChaCha20-Poly1305 encryption does not fail")
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        let nonce = self.nonce();
+        self.aead
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| "Message failed authentication".to_string())
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload)
+}
+
+/// The next frame of at most `max_len` bytes, or `None` if the other side
+/// closed the connection.
+fn read_frame(stream: &mut TcpStream, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+fn expect_frame(stream: &mut TcpStream) -> Result<Vec<u8>, String> {
+    read_frame(stream, MAX_HANDSHAKE_FRAME_LEN)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Connection closed during handshake".to_string())
+}
+
+fn transcript(initiator_hello: &[u8], responder_hello: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update((initiator_hello.len() as u64).to_be_bytes());
+    hasher.update(initiator_hello);
+    hasher.update(responder_hello);
+    hasher.finalize().to_vec()
+}
+
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+struct PendingPairing {
+    code: String,
+    expires: Instant,
+}
+
+/// Where pairing on this device stands, for the UI to poll.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PairingStatus {
+    Idle,
+    /// The code is waiting for the other device.
+    Waiting { expires_in_secs: u64 },
+    Paired { name: String },
+    /// The code was used up or expired; a new one is needed.
+    Failed { reason: String },
+}
+
+/// Counts a connection while it is being handled.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(count: &Arc<AtomicUsize>) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < MAX_CONNECTIONS).then_some(n + 1))
+            .ok()
+            .map(|_| Self(Arc::clone(count)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The running sharing service: a listener thread plus the pairing state.
+pub struct LanService {
+    db: Box<dyn Fn() -> Arc<Database> + Send + Sync>,
+    addr: SocketAddr,
+    enabled: AtomicBool,
+    pairing: Mutex<Option<PendingPairing>>,
+    /// How the last pairing attempt on this device ended.
+    pairing_outcome: Mutex<Option<PairingStatus>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl LanService {
+    /// Listen on `addr` and handle connections in the background. `db` is
+    /// called for each connection so peers follow the active profile.
+    pub fn start<F>(db: F, addr: SocketAddr) -> Result<Arc<Self>, String>
+    where
+        F: Fn() -> Arc<Database> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+        let service = Arc::new(Self {
+            db: Box::new(db),
+            addr: listener.local_addr().map_err(|e| e.to_string())?,
+            enabled: AtomicBool::new(true),
+            pairing: Mutex::new(None),
+            pairing_outcome: Mutex::new(None),
+            connections: Arc::new(AtomicUsize::new(0)),
+        });
+
+        let accepting = Arc::clone(&service);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("LAN sharing accept error: {}", e);
+                        continue;
+                    }
+                };
+                if !accepting.is_enabled() {
+                    continue;
+                }
+                let Some(slot) = ConnectionSlot::acquire(&accepting.connections) else {
+                    continue;
+                };
+                let service = Arc::clone(&accepting);
+                thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(e) = service.handle(stream) {
+                        eprintln!("LAN sharing connection error: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(service)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Stop or resume accepting connections and sharing entries. The port
+    /// stays bound until the app exits.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+        if !enabled {
+            self.pairing.lock().unwrap().take();
+            self.pairing_outcome.lock().unwrap().take();
+        }
+    }
+
+    /// Start pairing and return the code to enter on the other device,
+    /// formatted as `XXXX-XXXX`. Replaces any earlier code.
+    pub fn begin_pairing(&self) -> String {
+        let mut bytes = [0u8; CODE_LEN];
+        OsRng.fill_bytes(&mut bytes);
+        let code: String = bytes
+            .iter()
+            .map(|b| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+            .collect();
+        *self.pairing.lock().unwrap() = Some(PendingPairing {
+            code: code.clone(),
+            expires: Instant::now() + CODE_LIFETIME,
+        });
+        self.pairing_outcome.lock().unwrap().take();
+        format!("{}-{}", &code[..CODE_LEN / 2], &code[CODE_LEN / 2..])
+    }
+
+    pub fn pairing_status(&self) -> PairingStatus {
+        if let Some(pending) = self.pairing.lock().unwrap().as_ref() {
+            let now = Instant::now();
+            return if pending.expires > now {
+                PairingStatus::Waiting {
+                    expires_in_secs: (pending.expires - now).as_secs(),
+                }
+            } else {
+                PairingStatus::Failed {
+                    reason: "The pairing code expired".to_string(),
+                }
+            };
+        }
+        self.pairing_outcome.lock().unwrap().clone().unwrap_or(PairingStatus::Idle)
+    }
+
+    /// Use up the pairing code a handshake was started with. Fails if it was
+    /// replaced or another attempt used it first, without checking the proof.
+    fn consume_code(&self, code: &str) -> Result<(), String> {
+        let mut pairing = self.pairing.lock().unwrap();
+        match pairing.as_ref() {
+            Some(pending) if pending.code == code && pending.expires > Instant::now() => {
+                pairing.take();
+                Ok(())
+            }
+            _ => Err("Pairing code already used or replaced".to_string()),
+        }
+    }
+
+    /// Pair with the device at `address` that is showing `code`.
+    pub fn pair_with(&self, address: &str, code: &str) -> Result<Peer, String> {
+        if !self.is_enabled() {
+            return Err("LAN sharing is disabled".to_string());
+        }
+        let db = (self.db)();
+        let (_, addr, reply, keys) = self.connect(&db, address, Purpose::Pair, &Auth::Code(normalize_code(code)))?;
+        store_peer(&db, &reply.device_id, &reply.name, addr.to_string(), &keys.pair_secret)
+    }
+
+    /// Send entry `id` to every peer whose rules allow it, in the background.
+    pub fn share(self: &Arc<Self>, id: i64) {
+        if !self.is_enabled() {
+            return;
+        }
+        let service = Arc::clone(self);
+        thread::spawn(move || {
+            service.share_now(id);
+        });
+    }
+
+    /// Send entry `id` to every peer whose rules allow it. Returns the number
+    /// of peers it reached.
+    fn share_now(&self, id: i64) -> usize {
+        let db = (self.db)();
+        let entry = match shared_entry(&db, id) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return 0,
+            Err(e) => {
+                eprintln!("Failed to load entry {} for sharing: {}", id, e);
+                return 0;
+            }
+        };
+
+        let mut sent = 0;
+        for record in load_peers(&db).into_iter().filter(|record| record.peer.rules.allows(&entry)) {
+            match self.send(&db, &record, &entry) {
+                Ok(()) => sent += 1,
+                Err(e) => eprintln!("Failed to share with {}: {}", record.peer.name, e),
+            }
+        }
+        sent
+    }
+
+    fn send(&self, db: &Database, record: &PeerRecord, entry: &SharedEntry) -> Result<(), String> {
+        let secret: [u8; 32] = STANDARD
+            .decode(&record.secret)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("Invalid stored peer secret")?;
+        let (mut stream, _, reply, keys) = self.connect(db, &record.peer.address, Purpose::Share, &Auth::Secret(secret))?;
+        if reply.device_id != record.peer.device_id {
+            return Err("A different device answered at the peer's address".to_string());
+        }
+
+        let json = serde_json::to_vec(entry).map_err(|e| e.to_string())?;
+        let mut channel = Channel::new(&keys.initiator);
+        write_frame(&mut stream, &channel.seal(&json)).map_err(|e| e.to_string())?;
+        let _ = stream.shutdown(Shutdown::Write);
+        Ok(())
+    }
+
+    fn hello(&self, db: &Database, purpose: Purpose, key: &PublicKey) -> Result<Vec<u8>, String> {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            purpose,
+            device_id: device_id(db)?,
+            name: device_name(),
+            port: self.addr.port(),
+            key: STANDARD.encode(key.as_bytes()),
+        };
+        serde_json::to_vec(&hello).map_err(|e| e.to_string())
+    }
+
+    /// Open a connection and run the initiator side of the handshake.
+    fn connect(
+        &self,
+        db: &Database,
+        address: &str,
+        purpose: Purpose,
+        auth: &Auth,
+    ) -> Result<(TcpStream, SocketAddr, Hello, SessionKeys), String> {
+        let addr = address
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid address '{}': {}", address, e))?
+            .next()
+            .ok_or_else(|| format!("Invalid address '{}'", address))?;
+        let mut stream = TcpStream::connect_timeout(&addr, IO_TIMEOUT).map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(|e| e.to_string())?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let hello = self.hello(db, purpose, &PublicKey::from(&secret))?;
+        write_frame(&mut stream, &hello).map_err(|e| e.to_string())?;
+
+        let reply_bytes = expect_frame(&mut stream)?;
+        let reply: Hello = serde_json::from_slice(&reply_bytes).map_err(|e| format!("Invalid handshake: {}", e))?;
+        if reply.version != PROTOCOL_VERSION || reply.purpose != purpose {
+            return Err("Peer speaks an incompatible protocol version".to_string());
+        }
+
+        let keys = SessionKeys::derive(secret, &reply.key, &transcript(&hello, &reply_bytes), auth)?;
+        write_frame(&mut stream, &keys.proof(b"initiator")).map_err(|e| e.to_string())?;
+        keys.verify(b"responder", &expect_frame(&mut stream)?)?;
+        Ok((stream, addr, reply, keys))
+    }
+
+    /// Run the responder side of a connection.
+    fn handle(&self, mut stream: TcpStream) -> Result<(), String> {
+        stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(|e| e.to_string())?;
+        let remote_ip = stream.peer_addr().map_err(|e| e.to_string())?.ip();
+        let db = (self.db)();
+
+        let hello_bytes = expect_frame(&mut stream)?;
+        let hello: Hello = serde_json::from_slice(&hello_bytes).map_err(|e| format!("Invalid handshake: {}", e))?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(format!("Unsupported protocol version {}", hello.version));
+        }
+
+        let auth = match hello.purpose {
+            Purpose::Pair => {
+                // The code is only used up once a proof arrives, so a hello
+                // alone cannot burn it
+                let code = self
+                    .pairing
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .filter(|pending| pending.expires > Instant::now())
+                    .map(|pending| pending.code.clone())
+                    .ok_or("Pairing attempt without an active pairing code")?;
+                Auth::Code(code)
+            }
+            Purpose::Share => {
+                let record = load_peers(&db)
+                    .into_iter()
+                    .find(|record| record.peer.device_id == hello.device_id)
+                    .ok_or_else(|| format!("Connection from unpaired device {}", hello.device_id))?;
+                let secret = STANDARD
+                    .decode(&record.secret)
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or("Invalid stored peer secret")?;
+                Auth::Secret(secret)
+            }
+        };
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let reply = self.hello(&db, hello.purpose, &PublicKey::from(&secret))?;
+        write_frame(&mut stream, &reply).map_err(|e| e.to_string())?;
+
+        let keys = SessionKeys::derive(secret, &hello.key, &transcript(&hello_bytes, &reply), &auth)?;
+        let proof = expect_frame(&mut stream)?;
+        if let Auth::Code(code) = &auth {
+            // Right or wrong, this attempt was the one guess the code allows
+            self.consume_code(code)?;
+            if let Err(e) = keys.verify(b"initiator", &proof) {
+                *self.pairing_outcome.lock().unwrap() = Some(PairingStatus::Failed {
+                    reason: format!("{} tried a wrong pairing code, which used it up", remote_ip),
+                });
+                return Err(e);
+            }
+        } else {
+            keys.verify(b"initiator", &proof)?;
+        }
+        write_frame(&mut stream, &keys.proof(b"responder")).map_err(|e| e.to_string())?;
+
+        let address = SocketAddr::new(remote_ip, hello.port).to_string();
+        match hello.purpose {
+            Purpose::Pair => {
+                let peer = store_peer(&db, &hello.device_id, &hello.name, address, &keys.pair_secret)?;
+                *self.pairing_outcome.lock().unwrap() = Some(PairingStatus::Paired { name: peer.name });
+            }
+            Purpose::Share => {
+                self.update_address(&db, &hello.device_id, address)?;
+                let mut channel = Channel::new(&keys.initiator);
+                while let Some(frame) = read_frame(&mut stream, MAX_FRAME_LEN).map_err(|e| e.to_string())? {
+                    let entry: SharedEntry = serde_json::from_slice(&channel.open(&frame)?).map_err(|e| e.to_string())?;
+                    receive(&db, &hello.device_id, &entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Peers on DHCP move around; remember where one last connected from.
+    fn update_address(&self, db: &Database, device_id: &str, address: String) -> Result<(), String> {
+        let mut records = load_peers(db);
+        match records.iter_mut().find(|record| record.peer.device_id == device_id) {
+            Some(record) if record.peer.address != address => {
+                record.peer.address = address;
+                save_peers(db, &records)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn shared_entry(db: &Database, id: i64) -> Result<Option<SharedEntry>, rusqlite::Error> {
+    // Entries flagged as sensitive never leave this device
+    let Some(entry) = db.get_by_id(id)?.filter(|entry| entry.sensitivity.is_none()) else {
+        return Ok(None);
+    };
+    let image = if entry.content_type == ContentType::Image {
+        db.get_image(id)?.map(|data| STANDARD.encode(data))
+    } else {
+        None
+    };
+    Ok(Some(SharedEntry {
+        content: entry.content,
+        source_app: entry.source_app,
+        is_pinned: entry.is_pinned,
+        image,
+    }))
+}
+
+/// Store an entry from a peer if this side's rules for it allow it too.
+fn receive(db: &Database, device_id: &str, entry: &SharedEntry) -> Result<(), String> {
+    let allowed = load_peers(db)
+        .iter()
+        .find(|record| record.peer.device_id == device_id)
+        .is_some_and(|record| record.peer.rules.allows(entry));
+    if !allowed {
+        return Ok(());
+    }
+
+    let image = entry.image.as_deref()
+        .map(|data| STANDARD.decode(data))
+        .transpose()
+        .map_err(|e| format!("Invalid image data: {}", e))?;
+    let id = db.insert(&entry.content, entry.source_app.as_deref(), image.as_deref()).map_err(|e| e.to_string())?;
+    if entry.is_pinned {
+        db.set_pinned(id, true).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    struct Instance {
+        db: Arc<Database>,
+        service: Arc<LanService>,
+    }
+
+    impl Instance {
+        fn new(root: &Path, name: &str) -> Self {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            let db = Arc::new(Database::open(&dir.join("clipboard.db")).unwrap());
+            let handle = Arc::clone(&db);
+            let service = LanService::start(move || Arc::clone(&handle), "127.0.0.1:0".parse().unwrap()).unwrap();
+            Self { db, service }
+        }
+
+        fn address(&self) -> String {
+            self.service.local_addr().to_string()
+        }
+
+        /// Wait for the background handler to store an entry.
+        fn wait_for(&self, content: &str, pinned: bool) -> bool {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while Instant::now() < deadline {
+                let entries = self.db.sync_local_entries(true).unwrap();
+                if entries.iter().any(|entry| entry.content == content && entry.is_pinned == pinned) {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            false
+        }
+    }
+
+    fn pair(root: &str) -> (Instance, Instance) {
+        let root = std::env::temp_dir().join(format!("clipstream-lan-{}-{}", root, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let desktop = Instance::new(&root, "desktop");
+        let laptop = Instance::new(&root, "laptop");
+
+        let code = desktop.service.begin_pairing();
+        let peer = laptop.service.pair_with(&desktop.address(), &code.to_lowercase()).unwrap();
+        assert_eq!(peer.address, desktop.address());
+        (desktop, laptop)
+    }
+
+    #[test]
+    fn paired_instances_share_both_ways() {
+        let (desktop, laptop) = pair("both");
+        assert_eq!(peers(&desktop.db).len(), 1);
+        assert_eq!(peers(&laptop.db).len(), 1);
+
+        let id = desktop.db.insert("from the desktop", Some("code"), None).unwrap();
+        assert_eq!(desktop.service.share_now(id), 1);
+        assert!(laptop.wait_for("from the desktop", false));
+
+        let id = laptop.db.insert("from the laptop", None, None).unwrap();
+        laptop.db.set_pinned(id, true).unwrap();
+        assert_eq!(laptop.service.share_now(id), 1);
+        assert!(desktop.wait_for("from the laptop", true));
+    }
+
+    #[test]
+    fn pairing_codes_are_single_use() {
+        let root = std::env::temp_dir().join(format!("clipstream-lan-code-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let desktop = Instance::new(&root, "desktop");
+        let laptop = Instance::new(&root, "laptop");
+
+        let code = desktop.service.begin_pairing();
+        assert!(laptop.service.pair_with(&desktop.address(), "AAAA-AAAA").is_err());
+        // The failed guess used up the real code as well, and the UI is told
+        assert!(matches!(desktop.service.pairing_status(), PairingStatus::Failed { .. }));
+        assert!(laptop.service.pair_with(&desktop.address(), &code).is_err());
+        assert!(peers(&laptop.db).is_empty());
+        assert!(peers(&desktop.db).is_empty());
+
+        let code = desktop.service.begin_pairing();
+        assert!(matches!(desktop.service.pairing_status(), PairingStatus::Waiting { .. }));
+        laptop.service.pair_with(&desktop.address(), &code).unwrap();
+        assert!(eventually(|| desktop.service.pairing_status() == PairingStatus::Paired { name: device_name() }));
+    }
+
+    /// Wait for the responder's background thread to catch up.
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    fn raw_connect(instance: &Instance) -> TcpStream {
+        let stream = TcpStream::connect(instance.address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    /// Whether the other side closed the connection without sending anything.
+    fn closed(stream: &mut TcpStream) -> bool {
+        matches!(stream.read(&mut [0u8; 1]), Ok(0) | Err(_))
+    }
+
+    #[test]
+    fn a_hello_alone_does_not_use_up_the_code() {
+        let root = std::env::temp_dir().join(format!("clipstream-lan-hello-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let desktop = Instance::new(&root, "desktop");
+        let laptop = Instance::new(&root, "laptop");
+        let code = desktop.service.begin_pairing();
+
+        // A neighbour starts a pairing handshake and gives up before the proof
+        let mut stream = raw_connect(&desktop);
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let hello = laptop.service.hello(&laptop.db, Purpose::Pair, &PublicKey::from(&secret)).unwrap();
+        write_frame(&mut stream, &hello).unwrap();
+        assert!(expect_frame(&mut stream).is_ok());
+        drop(stream);
+
+        laptop.service.pair_with(&desktop.address(), &code).unwrap();
+        assert!(eventually(|| peers(&desktop.db).len() == 1));
+    }
+
+    #[test]
+    fn oversized_handshake_frames_are_refused() {
+        let root = std::env::temp_dir().join(format!("clipstream-lan-frame-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let desktop = Instance::new(&root, "desktop");
+
+        let mut stream = raw_connect(&desktop);
+        stream.write_all(&((MAX_HANDSHAKE_FRAME_LEN + 1) as u32).to_be_bytes()).unwrap();
+        assert!(closed(&mut stream));
+    }
+
+    #[test]
+    fn connections_are_bounded() {
+        let root = std::env::temp_dir().join(format!("clipstream-lan-bound-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let desktop = Instance::new(&root, "desktop");
+
+        let active = || desktop.service.connections.load(Ordering::SeqCst);
+        let idle: Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| raw_connect(&desktop)).collect();
+        assert!(eventually(|| active() == MAX_CONNECTIONS));
+        assert!(closed(&mut raw_connect(&desktop)));
+
+        // Slots come back when connections end
+        drop(idle);
+        assert!(eventually(|| active() == 0));
+    }
+
+    #[test]
+    fn rules_limit_what_is_sent() {
+        let (desktop, laptop) = pair("rules");
+        let laptop_id = peers(&desktop.db)[0].device_id.clone();
+        let rules = PeerRules { text: true, images: false, pinned_only: true };
+        set_peer_rules(&desktop.db, &laptop_id, rules).unwrap();
+
+        let id = desktop.db.insert("not pinned", None, None).unwrap();
+        assert_eq!(desktop.service.share_now(id), 0);
+        desktop.db.set_pinned(id, true).unwrap();
+        assert_eq!(desktop.service.share_now(id), 1);
+        assert!(laptop.wait_for("not pinned", true));
+
+        // Removing a peer on one side stops the other from getting in
+        remove_peer(&desktop.db, &laptop_id).unwrap();
+        let id = laptop.db.insert("after unpairing", None, None).unwrap();
+        assert_eq!(laptop.service.share_now(id), 0);
+    }
+
+    #[test]
+    fn channel_rejects_tampered_and_replayed_frames() {
+        let key = [7u8; 32];
+        let mut sender = Channel::new(&key);
+        let first = sender.seal(b"first");
+        let second = sender.seal(b"second");
+
+        let mut receiver = Channel::new(&key);
+        assert_eq!(receiver.open(&first).unwrap(), b"first");
+        assert!(receiver.open(&first).is_err());
+
+        let mut tampered = first.clone();
+        tampered[0] ^= 1;
+        assert!(Channel::new(&key).open(&tampered).is_err());
+        assert!(Channel::new(&key).open(&second).is_err());
+    }
+}
@@ -1,31 +1,78 @@
+mod app_rules;
+mod archive;
+mod backup;
+mod classify;
+pub mod cli;
 mod clipboard;
+mod crypto;
 mod database;
+mod expiry;
+mod fuzzy;
+mod lan;
+mod migrations;
+mod profiles;
+mod query;
+mod retention;
+mod sensitive;
+mod snippet;
+mod sync;
 
+use app_rules::{AppRules, CompiledRules};
+use archive::{ExportFilter, ImportSummary};
+use backup::{BackupInfo, BackupPolicy};
 use clipboard::{ClipboardContent, ClipboardListener, set_clipboard_text};
-use database::{ClipboardEntry, Database};
-use once_cell::sync::OnceCell;
-use std::sync::Arc;
+use crypto::KeySource;
+use database::{ClipboardEntry, Database, DedupMode, DiffLine, EncryptionStatus, EntryRevision, SearchPage, Tag};
+use query::{SearchMode, SearchQuery, SearchSort};
+use retention::{RetentionCandidate, RetentionPolicy};
+use sensitive::{Capture, SensitiveAction, SensitivePolicy, Sensitivity};
+use once_cell::sync::{Lazy, OnceCell};
+use lan::{LanConfig, LanService, PairingStatus, Peer, PeerRules};
+use profiles::Profile;
+use sync::{SyncConfig, SyncReport};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 use tauri::{
     AppHandle, Manager, Runtime, PhysicalPosition,
-    menu::{MenuBuilder, MenuItemBuilder},
+    menu::{CheckMenuItemBuilder, Menu, MenuBuilder, MenuItemBuilder, SubmenuBuilder},
     tray::{TrayIconBuilder, TrayIconEvent, MouseButton, MouseButtonState},
     WindowEvent,
 };
 use tauri_plugin_global_shortcut::{Code, GlobalShortcutExt, Modifiers, Shortcut, ShortcutState};
 
-static DB: OnceCell<Arc<Database>> = OnceCell::new();
+/// The active profile's database, swapped out when switching profiles.
+static DB: OnceCell<RwLock<Arc<Database>>> = OnceCell::new();
 static LISTENER: OnceCell<ClipboardListener> = OnceCell::new();
+static LAN: OnceCell<Arc<LanService>> = OnceCell::new();
+/// The active profile's app rules, compiled once rather than for every clip.
+static APP_RULES: Lazy<RwLock<CompiledRules>> = Lazy::new(RwLock::default);
+/// The active profile's sensitive data policy, likewise read once.
+static SENSITIVE_POLICY: Lazy<RwLock<SensitivePolicy>> = Lazy::new(RwLock::default);
 
-fn get_db() -> &'static Arc<Database> {
-    DB.get().expect("Database not initialized")
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Tray menu item ids for profiles are this prefix plus the profile name.
+const PROFILE_MENU_PREFIX: &str = "profile:";
+
+fn get_db() -> Arc<Database> {
+    DB.get().expect("Database not initialized").read().unwrap().clone()
 }
 
 #[tauri::command]
-fn search_history(query: String) -> Result<Vec<ClipboardEntry>, String> {
+fn search_history(
+    query: String,
+    mode: Option<SearchMode>,
+    sort: Option<SearchSort>,
+    offset: Option<usize>,
+    page_size: Option<usize>,
+) -> Result<SearchPage, String> {
+    let query = SearchQuery::parse(&query).map_err(|e| e.to_string())?;
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
     get_db()
-        .search(&query, 50)
+        .search(&query, mode.unwrap_or_default(), sort.unwrap_or_default(), offset.unwrap_or(0), page_size)
         .map_err(|e| e.to_string())
 }
 
@@ -36,6 +83,24 @@ fn get_entry(id: i64) -> Result<Option<ClipboardEntry>, String> {
         .map_err(|e| e.to_string())
 }
 
+/// Full-size image for an entry as base64 PNG. Search results only carry a
+/// thumbnail, so the UI calls this when it actually needs the image.
+#[tauri::command]
+fn get_entry_image(id: i64) -> Result<Option<String>, String> {
+    let data = get_db()
+        .get_image(id)
+        .map_err(|e| e.to_string())?;
+    Ok(data.map(|bytes| base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)))
+}
+
+/// Near-duplicate images of an entry, e.g. the same screenshot retaken.
+#[tauri::command]
+fn get_similar_images(id: i64, max_distance: Option<u32>) -> Result<Vec<ClipboardEntry>, String> {
+    get_db()
+        .find_similar_images(id, max_distance.unwrap_or(database::NEAR_DUPLICATE_DISTANCE))
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn paste_entry(id: i64) -> Result<(), String> {
     let entry = get_db()
@@ -47,6 +112,7 @@ fn paste_entry(id: i64) -> Result<(), String> {
     
     thread::sleep(Duration::from_millis(50));
     simulate_paste();
+    record_use(id);
     
     Ok(())
 }
@@ -70,6 +136,7 @@ fn paste_formatted(id: i64, format: String) -> Result<(), String> {
     
     thread::sleep(Duration::from_millis(50));
     simulate_paste();
+    record_use(id);
     
     Ok(())
 }
@@ -94,14 +161,38 @@ fn copy_entry(id: i64) -> Result<(), String> {
         .map_err(|e| e.to_string())?
         .ok_or("Entry not found")?;
     
-    set_clipboard_text(&entry.content)
+    set_clipboard_text(&entry.content)?;
+    record_use(id);
+    Ok(())
+}
+
+/// Usage only feeds ranking, so a failure to record it never fails the paste.
+fn record_use(id: i64) {
+    if let Err(e) = get_db().record_use(id) {
+        eprintln!("Failed to record usage of entry {}: {}", id, e);
+    }
 }
 
 #[tauri::command]
 fn toggle_pin(id: i64) -> Result<bool, String> {
-    get_db()
+    let pinned = get_db()
         .toggle_pin(id)
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    // Peers limited to pinned entries get them once pinned
+    if pinned {
+        share_entry(id);
+    }
+    Ok(pinned)
+}
+
+/// Delete the entry `minutes` from now, or keep it again with `None`.
+#[tauri::command]
+fn set_entry_expiry(id: i64, minutes: Option<u64>) -> Result<(), String> {
+    get_db()
+        .set_expiry(id, minutes)
+        .map_err(|e| e.to_string())?;
+    expiry::wake();
+    Ok(())
 }
 
 #[tauri::command]
@@ -111,6 +202,34 @@ fn delete_entry(id: i64) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn get_trash() -> Result<Vec<ClipboardEntry>, String> {
+    get_db()
+        .get_trash()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn restore_entry(id: i64) -> Result<(), String> {
+    get_db()
+        .restore_entry(id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn undo_delete() -> Result<usize, String> {
+    get_db()
+        .undo_delete()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn empty_trash() -> Result<usize, String> {
+    get_db()
+        .empty_trash()
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn update_entry(id: i64, content: String) -> Result<(), String> {
     get_db()
@@ -118,6 +237,26 @@ fn update_entry(id: i64, content: String) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn get_revisions(id: i64) -> Result<Vec<EntryRevision>, String> {
+    get_db()
+        .get_revisions(id)
+        .map_err(|e| e.to_string())
+}
+
+/// Diff two versions of an entry; omit a revision id to use the current content.
+#[tauri::command]
+fn diff_revisions(id: i64, from: Option<i64>, to: Option<i64>) -> Result<Vec<DiffLine>, String> {
+    get_db().diff_revisions(id, from, to)
+}
+
+#[tauri::command]
+fn restore_revision(revision_id: i64) -> Result<(), String> {
+    get_db()
+        .restore_revision(revision_id)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn hide_window(app: AppHandle) -> Result<(), String> {
     if let Some(window) = app.get_webview_window("main") {
@@ -127,36 +266,127 @@ fn hide_window(app: AppHandle) -> Result<(), String> {
 }
 
 // ============================================================================
-// Ignored Apps Management
+// Tags
 // ============================================================================
 
 #[tauri::command]
-fn get_ignored_apps() -> Result<Vec<String>, String> {
+fn get_tags() -> Result<Vec<Tag>, String> {
     get_db()
-        .get_ignored_apps()
+        .get_tags()
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn add_ignored_app(app_name: String) -> Result<(), String> {
+fn create_tag(name: String) -> Result<Tag, String> {
+    get_db().create_tag(&name)
+}
+
+#[tauri::command]
+fn rename_tag(id: i64, name: String) -> Result<(), String> {
+    get_db().rename_tag(id, &name)
+}
+
+#[tauri::command]
+fn delete_tag(id: i64) -> Result<(), String> {
     get_db()
-        .add_ignored_app(&app_name)
+        .delete_tag(id)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn remove_ignored_app(app_name: String) -> Result<(), String> {
+fn tag_entries(tag_id: i64, entry_ids: Vec<i64>) -> Result<(), String> {
     get_db()
-        .remove_ignored_app(&app_name)
+        .tag_entries(tag_id, &entry_ids)
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn untag_entries(tag_id: i64, entry_ids: Vec<i64>) -> Result<(), String> {
+    get_db()
+        .untag_entries(tag_id, &entry_ids)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_entries_by_tag(tag_id: i64) -> Result<Vec<ClipboardEntry>, String> {
+    get_db()
+        .get_entries_by_tag(tag_id)
+        .map_err(|e| e.to_string())
+}
+
+// ============================================================================
+// Capture Rules
+// ============================================================================
+
+#[tauri::command]
+fn get_app_rules() -> AppRules {
+    AppRules::load(&get_db())
+}
+
+#[tauri::command]
+fn set_app_rules(rules: AppRules) -> Result<(), String> {
+    rules.save(&get_db())?;
+    *APP_RULES.write().unwrap() = rules.compile();
+    Ok(())
+}
+
+/// The ignored apps list, kept for the settings view; entries are substring
+/// ignore rules in the app rules.
+#[tauri::command]
+fn get_ignored_apps() -> Vec<String> {
+    AppRules::load(&get_db()).ignored_apps()
+}
+
+#[tauri::command]
+fn add_ignored_app(app_name: String) -> Result<(), String> {
+    let mut rules = AppRules::load(&get_db());
+    rules.add_ignored_app(&app_name);
+    set_app_rules(rules)
+}
+
+#[tauri::command]
+fn remove_ignored_app(app_name: String) -> Result<(), String> {
+    let mut rules = AppRules::load(&get_db());
+    rules.remove_ignored_app(&app_name);
+    set_app_rules(rules)
+}
+
+/// Load the active profile's app rules and sensitive data policy again,
+/// after the database behind them changed.
+fn reload_capture_settings() {
+    let db = get_db();
+    *APP_RULES.write().unwrap() = AppRules::load_compiled(&db);
+    *SENSITIVE_POLICY.write().unwrap() = SensitivePolicy::load(&db);
+}
+
+#[tauri::command]
+fn get_sensitive_policy() -> SensitivePolicy {
+    SensitivePolicy::load(&get_db())
+}
+
+#[tauri::command]
+fn set_sensitive_policy(policy: SensitivePolicy) -> Result<(), String> {
+    policy.save(&get_db())?;
+    *SENSITIVE_POLICY.write().unwrap() = policy;
+    Ok(())
+}
+
 // ============================================================================
 // Settings Management
 // ============================================================================
 
+/// Settings owned by a backend service, reachable only through its own
+/// commands.
+fn check_setting_key(key: &str) -> Result<(), String> {
+    if key.starts_with("lan_") {
+        return Err(format!("Setting '{}' cannot be accessed directly", key));
+    }
+    Ok(())
+}
+
 #[tauri::command]
 fn get_setting(key: String) -> Result<Option<String>, String> {
+    check_setting_key(&key)?;
     get_db()
         .get_setting(&key)
         .map_err(|e| e.to_string())
@@ -164,11 +394,303 @@ fn get_setting(key: String) -> Result<Option<String>, String> {
 
 #[tauri::command]
 fn set_setting(key: String, value: String) -> Result<(), String> {
+    check_setting_key(&key)?;
+    // Settings the listener keeps loaded take effect as they are saved
+    match key.as_str() {
+        app_rules::RULES_SETTING_KEY => {
+            let rules = serde_json::from_str(&value).map_err(|e| format!("Invalid app rules: {}", e))?;
+            return set_app_rules(rules);
+        }
+        sensitive::POLICY_SETTING_KEY => {
+            let policy = serde_json::from_str(&value).map_err(|e| format!("Invalid sensitive data policy: {}", e))?;
+            return set_sensitive_policy(policy);
+        }
+        _ => {}
+    }
     get_db()
         .set_setting(&key, &value)
         .map_err(|e| e.to_string())
 }
 
+// ============================================================================
+// Deduplication
+// ============================================================================
+
+#[tauri::command]
+fn get_dedup_mode() -> Result<DedupMode, String> {
+    get_db()
+        .dedup_mode()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_dedup_mode(mode: DedupMode) -> Result<(), String> {
+    get_db().set_dedup_mode(mode)
+}
+
+// ============================================================================
+// Export / Import
+// ============================================================================
+
+#[tauri::command]
+fn export_history(path: String, filter: Option<ExportFilter>) -> Result<usize, String> {
+    archive::export_to(&get_db(), Path::new(&path), &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+fn import_history(path: String) -> Result<ImportSummary, String> {
+    archive::import_from(&get_db(), Path::new(&path))
+}
+
+// ============================================================================
+// Backups
+// ============================================================================
+
+#[tauri::command]
+fn get_backup_policy() -> BackupPolicy {
+    BackupPolicy::load(&get_db())
+}
+
+#[tauri::command]
+fn set_backup_policy(policy: BackupPolicy) -> Result<(), String> {
+    policy.save(&get_db())
+}
+
+#[tauri::command]
+fn list_backups() -> Result<Vec<BackupInfo>, String> {
+    backup::list_backups(&get_db())
+}
+
+#[tauri::command]
+fn create_backup() -> Result<BackupInfo, String> {
+    let db = get_db();
+    backup::create_backup(&db, BackupPolicy::load(&db).keep)
+}
+
+/// Swap in a snapshot while the app keeps running. The listener is paused so
+/// no clip lands in the database that is about to be replaced.
+#[tauri::command]
+fn restore_backup(name: String) -> Result<(), String> {
+    let listener = LISTENER.get();
+    if let Some(listener) = listener {
+        listener.pause();
+    }
+    let result = backup::restore_backup(&get_db(), &name);
+    reload_capture_settings();
+    if let Some(listener) = listener {
+        listener.resume();
+    }
+    result
+}
+
+// ============================================================================
+// Sync
+// ============================================================================
+
+#[tauri::command]
+fn get_sync_config() -> SyncConfig {
+    SyncConfig::load(&get_db())
+}
+
+#[tauri::command]
+fn set_sync_config(config: SyncConfig) -> Result<(), String> {
+    config.save(&get_db())
+}
+
+#[tauri::command]
+fn sync_now() -> Result<SyncReport, String> {
+    sync::sync_now(&get_db())
+}
+
+// ============================================================================
+// LAN sharing
+// ============================================================================
+
+/// Start the sharing service unless it already runs.
+fn start_lan(port: u16) -> Result<Arc<LanService>, String> {
+    LAN.get_or_try_init(|| LanService::start(get_db, ([0, 0, 0, 0], port).into()))
+        .cloned()
+}
+
+fn share_entry(id: i64) {
+    if let Some(lan) = LAN.get() {
+        lan.share(id);
+    }
+}
+
+fn running_lan() -> Result<Arc<LanService>, String> {
+    LAN.get()
+        .filter(|lan| lan.is_enabled())
+        .cloned()
+        .ok_or_else(|| "LAN sharing is disabled".to_string())
+}
+
+#[tauri::command]
+fn get_lan_config() -> LanConfig {
+    LanConfig::load(&get_db())
+}
+
+#[tauri::command]
+fn set_lan_config(config: LanConfig) -> Result<(), String> {
+    config.save(&get_db())?;
+    if config.enabled {
+        start_lan(config.port)?.set_enabled(true);
+    } else if let Some(lan) = LAN.get() {
+        lan.set_enabled(false);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn begin_pairing() -> Result<String, String> {
+    Ok(running_lan()?.begin_pairing())
+}
+
+/// Poll while a pairing code is shown; `failed` means it was used up by a
+/// wrong attempt or expired, and a new code is needed.
+#[tauri::command]
+fn get_pairing_status() -> PairingStatus {
+    LAN.get().map(|lan| lan.pairing_status()).unwrap_or(PairingStatus::Idle)
+}
+
+#[tauri::command]
+fn pair_with_peer(address: String, code: String) -> Result<Peer, String> {
+    running_lan()?.pair_with(&address, &code)
+}
+
+#[tauri::command]
+fn get_lan_peers() -> Vec<Peer> {
+    lan::peers(&get_db())
+}
+
+#[tauri::command]
+fn set_peer_rules(device_id: String, rules: PeerRules) -> Result<(), String> {
+    lan::set_peer_rules(&get_db(), &device_id, rules)
+}
+
+#[tauri::command]
+fn remove_peer(device_id: String) -> Result<(), String> {
+    lan::remove_peer(&get_db(), &device_id)
+}
+
+// ============================================================================
+// Profiles
+// ============================================================================
+
+#[tauri::command]
+fn get_profiles() -> Vec<Profile> {
+    profiles::list()
+}
+
+#[tauri::command]
+fn create_profile(app: AppHandle, name: String) -> Result<String, String> {
+    let name = profiles::create(&name)?;
+    refresh_tray_menu(&app);
+    Ok(name)
+}
+
+#[tauri::command]
+fn delete_profile(app: AppHandle, name: String) -> Result<(), String> {
+    profiles::delete(&name)?;
+    refresh_tray_menu(&app);
+    Ok(())
+}
+
+#[tauri::command]
+fn switch_profile(app: AppHandle, name: String) -> Result<(), String> {
+    activate_profile(&app, &name)
+}
+
+/// Open profile `name` and make it the one the listener and commands use.
+fn activate_profile<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<(), String> {
+    if !profiles::exists(name) {
+        return Err(format!("Profile \"{}\" not found", name));
+    }
+    if name == profiles::active() {
+        return Ok(());
+    }
+    
+    let db = Database::open(&profiles::database_path(name)).map_err(|e| e.to_string())?;
+    
+    let listener = LISTENER.get();
+    if let Some(listener) = listener {
+        listener.pause();
+    }
+    *DB.get().expect("Database not initialized").write().unwrap() = Arc::new(db);
+    reload_capture_settings();
+    let result = profiles::set_active(name);
+    if let Some(listener) = listener {
+        listener.resume();
+    }
+    
+    refresh_tray_menu(app);
+    result
+}
+
+// ============================================================================
+// Retention
+// ============================================================================
+
+#[tauri::command]
+fn get_retention_policy() -> RetentionPolicy {
+    RetentionPolicy::load(&get_db())
+}
+
+#[tauri::command]
+fn set_retention_policy(policy: RetentionPolicy) -> Result<(), String> {
+    policy.save(&get_db())
+}
+
+/// Dry run: report what the current policy would delete.
+#[tauri::command]
+fn preview_cleanup() -> Result<Vec<RetentionCandidate>, String> {
+    let db = get_db();
+    db.cleanup_preview(&RetentionPolicy::load(&db))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn run_cleanup() -> Result<usize, String> {
+    let db = get_db();
+    db.cleanup(&RetentionPolicy::load(&db))
+        .map_err(|e| e.to_string())
+}
+
+// ============================================================================
+// Encryption
+// ============================================================================
+
+#[tauri::command]
+fn get_encryption_status() -> EncryptionStatus {
+    get_db().encryption_status()
+}
+
+/// Encrypt the history with either a passphrase or a key file path.
+#[tauri::command]
+fn enable_encryption(passphrase: Option<String>, key_file: Option<String>) -> Result<(), String> {
+    let source = match (passphrase, key_file) {
+        (Some(passphrase), None) => KeySource::Passphrase(passphrase),
+        (None, Some(path)) => KeySource::KeyFile(path),
+        _ => return Err("Provide either a passphrase or a key file".to_string()),
+    };
+    get_db().enable_encryption(&source)
+}
+
+#[tauri::command]
+fn disable_encryption() -> Result<(), String> {
+    get_db().disable_encryption()
+}
+
+#[tauri::command]
+fn lock_history() -> Result<(), String> {
+    get_db().lock()
+}
+
+#[tauri::command]
+fn unlock_history(passphrase: Option<String>) -> Result<(), String> {
+    get_db().unlock(passphrase.as_deref())
+}
+
 // ============================================================================
 // Paste Simulation (cross-platform)
 // ============================================================================
@@ -291,15 +813,45 @@ fn toggle_window<R: Runtime>(app: &AppHandle<R>) {
     }
 }
 
-fn setup_tray<R: Runtime>(app: &AppHandle<R>) -> Result<(), Box<dyn std::error::Error>> {
+fn build_tray_menu<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<Menu<R>> {
     let quit = MenuItemBuilder::with_id("quit", "Quit ClipStream").build(app)?;
     let show = MenuItemBuilder::with_id("show", "Open (Ctrl+Shift+V)").build(app)?;
+    let lock = MenuItemBuilder::with_id("lock", "Lock History").build(app)?;
+    
+    let mut profile_menu = SubmenuBuilder::new(app, "Profile");
+    for profile in profiles::list() {
+        let item = CheckMenuItemBuilder::with_id(format!("{}{}", PROFILE_MENU_PREFIX, profile.name), &profile.name)
+            .checked(profile.active)
+            .build(app)?;
+        profile_menu = profile_menu.item(&item);
+    }
     
-    let menu = MenuBuilder::new(app)
+    MenuBuilder::new(app)
         .item(&show)
+        .item(&lock)
+        .item(&profile_menu.build()?)
         .separator()
         .item(&quit)
-        .build()?;
+        .build()
+}
+
+/// Rebuild the tray menu after the profile list or active profile changed.
+fn refresh_tray_menu<R: Runtime>(app: &AppHandle<R>) {
+    let Some(tray) = app.tray_by_id("main-tray") else {
+        return;
+    };
+    match build_tray_menu(app) {
+        Ok(menu) => {
+            if let Err(e) = tray.set_menu(Some(menu)) {
+                eprintln!("Failed to update tray menu: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to build tray menu: {}", e),
+    }
+}
+
+fn setup_tray<R: Runtime>(app: &AppHandle<R>) -> Result<(), Box<dyn std::error::Error>> {
+    let menu = build_tray_menu(app)?;
     
     let _ = TrayIconBuilder::with_id("main-tray")
         .icon(app.default_window_icon().unwrap().clone())
@@ -309,7 +861,20 @@ fn setup_tray<R: Runtime>(app: &AppHandle<R>) -> Result<(), Box<dyn std::error::
             match event.id().as_ref() {
                 "quit" => app.exit(0),
                 "show" => toggle_window(app),
-                _ => {}
+                "lock" => {
+                    if let Err(e) = get_db().lock() {
+                        eprintln!("Failed to lock history: {}", e);
+                    }
+                }
+                id => {
+                    if let Some(name) = id.strip_prefix(PROFILE_MENU_PREFIX) {
+                        if let Err(e) = activate_profile(app, name) {
+                            eprintln!("Failed to switch profile: {}", e);
+                        }
+                        // Check items toggle themselves on click; redraw from the real state
+                        refresh_tray_menu(app);
+                    }
+                }
             }
         })
         .on_tray_icon_event(|tray, event| {
@@ -325,39 +890,87 @@ fn setup_tray<R: Runtime>(app: &AppHandle<R>) -> Result<(), Box<dyn std::error::
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let db = Arc::new(Database::new().expect("Failed to initialize database"));
-    DB.set(db.clone()).expect("Failed to set database");
+    DB.set(RwLock::new(db)).expect("Failed to set database");
+    reload_capture_settings();
     
-    if let Err(e) = db.cleanup(7, 500) {
-        eprintln!("Cleanup error: {}", e);
-    }
+    retention::start_worker(get_db);
+    expiry::start_reaper(get_db);
+    backup::start_worker(get_db);
+    sync::start_worker(get_db);
     
-    // Get ignored apps for filtering
-    let ignored_apps = db.get_ignored_apps().unwrap_or_default();
+    let lan_config = LanConfig::load(&get_db());
+    if lan_config.enabled {
+        if let Err(e) = start_lan(lan_config.port) {
+            eprintln!("Failed to start LAN sharing: {}", e);
+        }
+    }
     
     let listener = ClipboardListener::new();
-    let db_clone = db.clone();
-    listener.start(move |content, source_app| {
-        // Check if source app is ignored
-        if let Some(ref app) = source_app {
-            let app_lower = app.to_lowercase();
-            if ignored_apps.iter().any(|ignored| app_lower.contains(&ignored.to_lowercase())) {
-                return; // Skip ignored apps
-            }
+    listener.start(move |content, window, hints| {
+        // Clips go to whichever profile is active when they arrive
+        let db = get_db();
+        
+        // Nothing can be sealed while the history is locked, so capture is
+        // paused until it is unlocked; the unlock view says so
+        if db.is_locked() {
+            return;
+        }
+        
+        if !APP_RULES.read().unwrap().allows(&window) {
+            return;
         }
+        let source_app = window.app_name().map(str::to_string);
         
         match content {
             ClipboardContent::Text(text) => {
-                if let Err(e) = db_clone.insert(&text, source_app.as_deref(), None) {
-                    eprintln!("Failed to save clipboard entry: {}", e);
+                let capture = SENSITIVE_POLICY.read().unwrap().screen(&text, source_app.as_deref(), hints.concealed);
+                let Capture::Store { content, sensitivity, expire_minutes } = capture else {
+                    return; // Secret the policy says not to keep
+                };
+                match db.insert(&content, source_app.as_deref(), None) {
+                    Ok(id) => {
+                        if let Some(sensitivity) = sensitivity {
+                            if let Err(e) = db.mark_sensitive(id, sensitivity, expire_minutes) {
+                                eprintln!("Failed to flag sensitive entry: {}", e);
+                            }
+                            if expire_minutes.is_some() {
+                                expiry::wake();
+                            }
+                        }
+                        share_entry(id);
+                    }
+                    Err(e) => eprintln!("Failed to save clipboard entry: {}", e),
                 }
             }
             ClipboardContent::Image { data, width, height } => {
-                // Encode image as base64 for storage
+                // Concealed images are kept only if the policy keeps them as
+                // they are, since they cannot be masked
+                let concealed = if hints.concealed {
+                    let action = SENSITIVE_POLICY.read().unwrap().concealed_action;
+                    match action {
+                        SensitiveAction::Skip | SensitiveAction::Mask => return,
+                        action => Some(action.expire_minutes()),
+                    }
+                } else {
+                    None
+                };
+                
+                // Store the PNG bytes in the content-addressed blob table
                 if let Ok(png_data) = encode_rgba_to_png(&data, width, height) {
-                    let base64_str = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &png_data);
                     let preview = format!("[Image {}x{}]", width, height);
-                    if let Err(e) = db_clone.insert(&preview, source_app.as_deref(), Some(&base64_str)) {
-                        eprintln!("Failed to save clipboard image: {}", e);
+                    match db.insert(&preview, source_app.as_deref(), Some(&png_data)) {
+                        Ok(id) => {
+                            if let Some(expire_minutes) = concealed {
+                                if let Err(e) = db.mark_sensitive(id, Sensitivity::Password, expire_minutes) {
+                                    eprintln!("Failed to flag sensitive entry: {}", e);
+                                }
+                                if expire_minutes.is_some() {
+                                    expiry::wake();
+                                }
+                            }
+                            share_entry(id);
+                        }
+                        Err(e) => eprintln!("Failed to save clipboard image: {}", e),
                     }
                 }
             }
@@ -379,18 +992,72 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             search_history,
             get_entry,
+            get_entry_image,
+            get_similar_images,
             paste_entry,
             paste_formatted,
             copy_entry,
             toggle_pin,
+            set_entry_expiry,
             delete_entry,
+            get_trash,
+            restore_entry,
+            undo_delete,
+            empty_trash,
             update_entry,
+            get_revisions,
+            diff_revisions,
+            restore_revision,
             hide_window,
+            get_tags,
+            create_tag,
+            rename_tag,
+            delete_tag,
+            tag_entries,
+            untag_entries,
+            get_entries_by_tag,
+            get_app_rules,
+            set_app_rules,
             get_ignored_apps,
             add_ignored_app,
             remove_ignored_app,
+            get_sensitive_policy,
+            set_sensitive_policy,
             get_setting,
             set_setting,
+            get_dedup_mode,
+            set_dedup_mode,
+            export_history,
+            import_history,
+            get_backup_policy,
+            set_backup_policy,
+            list_backups,
+            create_backup,
+            restore_backup,
+            get_sync_config,
+            set_sync_config,
+            sync_now,
+            get_lan_config,
+            set_lan_config,
+            begin_pairing,
+            pair_with_peer,
+            get_pairing_status,
+            get_lan_peers,
+            set_peer_rules,
+            remove_peer,
+            get_profiles,
+            create_profile,
+            delete_profile,
+            switch_profile,
+            get_retention_policy,
+            set_retention_policy,
+            preview_cleanup,
+            run_cleanup,
+            get_encryption_status,
+            enable_encryption,
+            disable_encryption,
+            lock_history,
+            unlock_history,
         ])
         .setup(|app| {
             setup_tray(app.handle())?;
@@ -0,0 +1,976 @@
+use crate::classify;
+use crate::database::{backfill_perceptual_hashes, blob_hash, content_uuid, make_thumbnail, random_uuid};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rusqlite::{Connection, params};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A single forward-only schema change.
+///
+/// Migrations are applied in order, each inside its own transaction, and the
+/// database's `PRAGMA user_version` is bumped to `version` when it commits.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// Every schema change ever shipped, oldest first. Never edit or reorder an
+/// entry once it has been released; append a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        up: v1_initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "content-addressed image blobs",
+        up: v2_image_blobs,
+    },
+    Migration {
+        version: 3,
+        description: "image thumbnails",
+        up: v3_image_thumbnails,
+    },
+    Migration {
+        version: 4,
+        description: "encryption at rest",
+        up: v4_encryption_flags,
+    },
+    Migration {
+        version: 5,
+        description: "tags",
+        up: v5_tags,
+    },
+    Migration {
+        version: 6,
+        description: "entry revisions",
+        up: v6_entry_revisions,
+    },
+    Migration {
+        version: 7,
+        description: "trigram index for fuzzy search",
+        up: v7_trigram_index,
+    },
+    Migration {
+        version: 8,
+        description: "usage tracking",
+        up: v8_usage_tracking,
+    },
+    Migration {
+        version: 9,
+        description: "content hash dedup",
+        up: v9_content_hash,
+    },
+    Migration {
+        version: 10,
+        description: "image dedup and perceptual hashes",
+        up: v10_image_hashes,
+    },
+    Migration {
+        version: 11,
+        description: "entry uuids and sync state",
+        up: v11_sync,
+    },
+    Migration {
+        version: 12,
+        description: "trash",
+        up: v12_trash,
+    },
+    Migration {
+        version: 13,
+        description: "sensitivity flags and expiry",
+        up: v13_sensitivity,
+    },
+    Migration {
+        version: 14,
+        description: "app rules",
+        up: v14_app_rules,
+    },
+    Migration {
+        version: 15,
+        description: "content classification",
+        up: v15_classification,
+    },
+    Migration {
+        version: 16,
+        description: "sealed secrets",
+        up: v16_secrets,
+    },
+    Migration {
+        version: 17,
+        description: "keyed blob addresses",
+        up: v17_keyed_blob_addresses,
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    /// The database was written by a newer build than this one.
+    TooNew { found: i64, supported: i64 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "{}", e),
+            MigrationError::TooNew { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the latest supported version {}. Please update ClipStream.",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Bring the database up to the latest schema version.
+pub fn run(conn: &mut Connection) -> Result<(), MigrationError> {
+    apply(conn, MIGRATIONS, latest_version())
+}
+
+fn apply(conn: &mut Connection, migrations: &[Migration], target: i64) -> Result<(), MigrationError> {
+    let current = current_version(conn)?;
+    let supported = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    if current > supported {
+        return Err(MigrationError::TooNew { found: current, supported });
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current && m.version <= target) {
+        let tx = conn.transaction()?;
+        if let Err(e) = (migration.up)(&tx) {
+            eprintln!("Migration {} ({}) failed: {}", migration.version, migration.description, e);
+            return Err(e.into());
+        }
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// ============================================================================
+// Migrations
+// ============================================================================
+
+/// Baseline schema. Databases created before versioning existed report
+/// `user_version = 0` and may already contain these tables, with or without
+/// the `content_blob` column, so everything here must be idempotent.
+fn v1_initial_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS clipboard_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            content TEXT NOT NULL,
+            source_app TEXT,
+            content_type TEXT DEFAULT 'text',
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            is_pinned BOOLEAN DEFAULT 0
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_created_at ON clipboard_history(created_at DESC);
+        CREATE INDEX IF NOT EXISTS idx_source_app ON clipboard_history(source_app);
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS history_fts USING fts5(
+            content,
+            source_app,
+            content='clipboard_history',
+            content_rowid='id'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS history_ai AFTER INSERT ON clipboard_history BEGIN
+            INSERT INTO history_fts(rowid, content, source_app) VALUES (new.id, new.content, new.source_app);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS history_ad AFTER DELETE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app) VALUES('delete', old.id, old.content, old.source_app);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS history_au AFTER UPDATE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app) VALUES('delete', old.id, old.content, old.source_app);
+            INSERT INTO history_fts(rowid, content, source_app) VALUES (new.id, new.content, new.source_app);
+        END;
+
+        CREATE TABLE IF NOT EXISTS ignored_apps (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            app_name TEXT UNIQUE NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );"
+    )?;
+
+    if !has_column(conn, "clipboard_history", "content_blob")? {
+        conn.execute("ALTER TABLE clipboard_history ADD COLUMN content_blob TEXT", [])?;
+    }
+
+    Ok(())
+}
+
+/// Move images out of the base64 `content_blob` column into the `blobs`
+/// table, keyed by the SHA-256 of the PNG bytes so identical images share a
+/// single row. Blobs are garbage-collected when their last entry is deleted.
+fn v2_image_blobs(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE blobs (
+            hash TEXT PRIMARY KEY,
+            data BLOB NOT NULL,
+            size INTEGER NOT NULL
+        );
+
+        ALTER TABLE clipboard_history ADD COLUMN blob_hash TEXT;
+        CREATE INDEX idx_blob_hash ON clipboard_history(blob_hash);"
+    )?;
+
+    let rows = {
+        let mut stmt = conn.prepare("SELECT id, content_blob FROM clipboard_history WHERE content_blob IS NOT NULL")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+
+    for (id, encoded) in rows {
+        let data = match STANDARD.decode(encoded.trim()) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Skipping undecodable image for entry {}: {}", id, e);
+                continue;
+            }
+        };
+        let hash = blob_hash(&data);
+        conn.execute(
+            "INSERT OR IGNORE INTO blobs (hash, data, size) VALUES (?1, ?2, ?3)",
+            params![hash, data, data.len() as i64]
+        )?;
+        conn.execute(
+            "UPDATE clipboard_history SET blob_hash = ?1 WHERE id = ?2",
+            params![hash, id]
+        )?;
+    }
+
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history DROP COLUMN content_blob;
+
+        CREATE TRIGGER history_blob_gc AFTER DELETE ON clipboard_history
+        WHEN old.blob_hash IS NOT NULL BEGIN
+            DELETE FROM blobs WHERE hash = old.blob_hash
+            AND NOT EXISTS (SELECT 1 FROM clipboard_history WHERE blob_hash = old.blob_hash);
+        END;"
+    )
+}
+
+/// Small per-blob thumbnails kept apart from `blobs` so list queries never
+/// touch the full image pages.
+fn v3_image_thumbnails(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE blob_thumbnails (
+            hash TEXT PRIMARY KEY,
+            data BLOB NOT NULL
+        );
+
+        CREATE TRIGGER blobs_thumbnail_gc AFTER DELETE ON blobs BEGIN
+            DELETE FROM blob_thumbnails WHERE hash = old.hash;
+        END;"
+    )?;
+
+    let hashes = {
+        let mut stmt = conn.prepare("SELECT hash FROM blobs")?;
+        let hashes = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        hashes
+    };
+
+    for hash in hashes {
+        let data: Vec<u8> = conn.query_row("SELECT data FROM blobs WHERE hash = ?1", params![hash], |row| row.get(0))?;
+        if let Some(thumb) = make_thumbnail(&data) {
+            conn.execute(
+                "INSERT INTO blob_thumbnails (hash, data) VALUES (?1, ?2)",
+                params![hash, thumb]
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Per-row `encrypted` flags. Ciphertext must never reach the persistent FTS
+/// index, so the sync triggers now skip encrypted rows; an unlocked encrypted
+/// history is searched through a TEMP index instead.
+fn v4_encryption_flags(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE blobs ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE blob_thumbnails ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT 0;
+
+        DROP TRIGGER IF EXISTS history_ai;
+        DROP TRIGGER IF EXISTS history_ad;
+        DROP TRIGGER IF EXISTS history_au;
+
+        CREATE TRIGGER history_ai AFTER INSERT ON clipboard_history BEGIN
+            INSERT INTO history_fts(rowid, content, source_app)
+                SELECT new.id, new.content, new.source_app WHERE new.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_ad AFTER DELETE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app)
+                SELECT 'delete', old.id, old.content, old.source_app WHERE old.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_au AFTER UPDATE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app)
+                SELECT 'delete', old.id, old.content, old.source_app WHERE old.encrypted = 0;
+            INSERT INTO history_fts(rowid, content, source_app)
+                SELECT new.id, new.content, new.source_app WHERE new.encrypted = 0;
+        END;"
+    )
+}
+
+/// Tags live in `tags`/`entry_tags`; triggers keep a newline-separated copy
+/// of each entry's tag names in `clipboard_history.tags` so the FTS index can
+/// search them alongside the content.
+fn v5_tags(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT UNIQUE NOT NULL COLLATE NOCASE,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE entry_tags (
+            entry_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (entry_id, tag_id)
+        );
+
+        CREATE INDEX idx_entry_tags_tag ON entry_tags(tag_id);
+
+        ALTER TABLE clipboard_history ADD COLUMN tags TEXT;
+
+        CREATE TRIGGER entry_tags_ai AFTER INSERT ON entry_tags BEGIN
+            UPDATE clipboard_history SET tags = (
+                SELECT group_concat(name, char(10)) FROM (
+                    SELECT t.name FROM entry_tags et JOIN tags t ON t.id = et.tag_id
+                    WHERE et.entry_id = new.entry_id ORDER BY t.name
+                )
+            ) WHERE id = new.entry_id;
+        END;
+
+        CREATE TRIGGER entry_tags_ad AFTER DELETE ON entry_tags BEGIN
+            UPDATE clipboard_history SET tags = (
+                SELECT group_concat(name, char(10)) FROM (
+                    SELECT t.name FROM entry_tags et JOIN tags t ON t.id = et.tag_id
+                    WHERE et.entry_id = old.entry_id ORDER BY t.name
+                )
+            ) WHERE id = old.entry_id;
+        END;
+
+        CREATE TRIGGER tags_au AFTER UPDATE OF name ON tags BEGIN
+            UPDATE clipboard_history SET tags = (
+                SELECT group_concat(name, char(10)) FROM (
+                    SELECT t.name FROM entry_tags et JOIN tags t ON t.id = et.tag_id
+                    WHERE et.entry_id = clipboard_history.id ORDER BY t.name
+                )
+            ) WHERE id IN (SELECT entry_id FROM entry_tags WHERE tag_id = new.id);
+        END;
+
+        CREATE TRIGGER tags_ad AFTER DELETE ON tags BEGIN
+            DELETE FROM entry_tags WHERE tag_id = old.id;
+        END;
+
+        CREATE TRIGGER history_tags_ad AFTER DELETE ON clipboard_history BEGIN
+            DELETE FROM entry_tags WHERE entry_id = old.id;
+        END;
+
+        DROP TRIGGER history_ai;
+        DROP TRIGGER history_ad;
+        DROP TRIGGER history_au;
+        DROP TABLE history_fts;
+
+        CREATE VIRTUAL TABLE history_fts USING fts5(
+            content,
+            source_app,
+            tags,
+            content='clipboard_history',
+            content_rowid='id'
+        );
+
+        INSERT INTO history_fts(rowid, content, source_app, tags)
+            SELECT id, content, source_app, tags FROM clipboard_history WHERE encrypted = 0;
+
+        CREATE TRIGGER history_ai AFTER INSERT ON clipboard_history BEGIN
+            INSERT INTO history_fts(rowid, content, source_app, tags)
+                SELECT new.id, new.content, new.source_app, new.tags WHERE new.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_ad AFTER DELETE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app, tags)
+                SELECT 'delete', old.id, old.content, old.source_app, old.tags WHERE old.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_au AFTER UPDATE ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app, tags)
+                SELECT 'delete', old.id, old.content, old.source_app, old.tags WHERE old.encrypted = 0;
+            INSERT INTO history_fts(rowid, content, source_app, tags)
+                SELECT new.id, new.content, new.source_app, new.tags WHERE new.encrypted = 0;
+        END;"
+    )
+}
+
+fn v6_entry_revisions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE entry_revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            entry_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            encrypted BOOLEAN NOT NULL DEFAULT 0,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX idx_entry_revisions_entry ON entry_revisions(entry_id);
+
+        CREATE TRIGGER history_revisions_ad AFTER DELETE ON clipboard_history BEGIN
+            DELETE FROM entry_revisions WHERE entry_id = old.id;
+        END;"
+    )
+}
+
+fn v7_trigram_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE history_trigram USING fts5(
+            content,
+            content='clipboard_history',
+            content_rowid='id',
+            tokenize='trigram'
+        );
+
+        INSERT INTO history_trigram(rowid, content)
+            SELECT id, content FROM clipboard_history WHERE encrypted = 0;
+
+        CREATE TRIGGER history_trigram_ai AFTER INSERT ON clipboard_history BEGIN
+            INSERT INTO history_trigram(rowid, content)
+                SELECT new.id, new.content WHERE new.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_trigram_ad AFTER DELETE ON clipboard_history BEGIN
+            INSERT INTO history_trigram(history_trigram, rowid, content)
+                SELECT 'delete', old.id, old.content WHERE old.encrypted = 0;
+        END;
+
+        CREATE TRIGGER history_trigram_au AFTER UPDATE OF content, encrypted ON clipboard_history BEGIN
+            INSERT INTO history_trigram(history_trigram, rowid, content)
+                SELECT 'delete', old.id, old.content WHERE old.encrypted = 0;
+            INSERT INTO history_trigram(rowid, content)
+                SELECT new.id, new.content WHERE new.encrypted = 0;
+        END;"
+    )
+}
+
+fn v8_usage_tracking(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE clipboard_history ADD COLUMN last_used_at DATETIME;
+
+        -- Usage bumps and pin toggles should not reindex the entry
+        DROP TRIGGER history_au;
+
+        CREATE TRIGGER history_au AFTER UPDATE OF content, source_app, tags, encrypted ON clipboard_history BEGIN
+            INSERT INTO history_fts(history_fts, rowid, content, source_app, tags)
+                SELECT 'delete', old.id, old.content, old.source_app, old.tags WHERE old.encrypted = 0;
+            INSERT INTO history_fts(rowid, content, source_app, tags)
+                SELECT new.id, new.content, new.source_app, new.tags WHERE new.encrypted = 0;
+        END;"
+    )
+}
+
+/// Settings key set when encrypted rows still need their keyed content hash
+/// or blob address, which can only be computed once the history is unlocked.
+pub const CONTENT_HASH_PENDING_KEY: &str = "content_hash_pending";
+
+fn v9_content_hash(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN content_hash TEXT;
+
+        CREATE UNIQUE INDEX idx_content_hash ON clipboard_history(content_hash)
+            WHERE content_hash IS NOT NULL;"
+    )?;
+
+    // Older duplicates keep a NULL hash; the newest copy owns it
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, content FROM clipboard_history
+             WHERE encrypted = 0 AND content_type != 'image'
+             ORDER BY created_at DESC, id DESC"
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+
+    let mut seen = HashSet::new();
+    for (id, content) in rows {
+        let hash = blob_hash(content.as_bytes());
+        if seen.insert(hash.clone()) {
+            conn.execute(
+                "UPDATE clipboard_history SET content_hash = ?1 WHERE id = ?2",
+                params![hash, id]
+            )?;
+        }
+    }
+
+    let has_encrypted: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM clipboard_history WHERE encrypted = 1)",
+        [],
+        |row| row.get(0)
+    )?;
+    if has_encrypted {
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, '1')",
+            params![CONTENT_HASH_PENDING_KEY]
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Images join the content hash index under their blob address, and plaintext
+/// images get a perceptual hash for near-duplicate lookups.
+fn v10_image_hashes(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("ALTER TABLE clipboard_history ADD COLUMN phash INTEGER;")?;
+
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, blob_hash FROM clipboard_history
+             WHERE encrypted = 0 AND content_type = 'image' AND blob_hash IS NOT NULL
+             ORDER BY created_at DESC, id DESC"
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+
+    let mut seen = HashSet::new();
+    for (id, hash) in rows {
+        if seen.insert(hash.clone()) {
+            conn.execute(
+                "UPDATE clipboard_history SET content_hash = ?1 WHERE id = ?2",
+                params![hash, id]
+            )?;
+        }
+    }
+
+    backfill_perceptual_hashes(conn)?;
+
+    let has_encrypted_images: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM clipboard_history WHERE encrypted = 1 AND content_type = 'image')",
+        [],
+        |row| row.get(0)
+    )?;
+    if has_encrypted_images {
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, '1')",
+            params![CONTENT_HASH_PENDING_KEY]
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Stable entry UUIDs for sync, plus the per-entry fingerprints recorded at
+/// the end of each sync pass so local changes can be told apart.
+fn v11_sync(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN uuid TEXT;
+
+        CREATE UNIQUE INDEX idx_uuid ON clipboard_history(uuid);
+
+        CREATE TABLE sync_state (
+            uuid TEXT PRIMARY KEY,
+            content_hash TEXT NOT NULL,
+            is_pinned BOOLEAN NOT NULL,
+            tags TEXT NOT NULL
+        );"
+    )?;
+
+    let rows: Vec<(i64, String, Option<String>, bool)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, content, blob_hash, encrypted FROM clipboard_history
+             ORDER BY created_at DESC, id DESC"
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+
+    let mut seen = HashSet::new();
+    for (id, content, blob, encrypted) in rows {
+        let uuid = if encrypted {
+            random_uuid()
+        } else {
+            let hash = blob.unwrap_or_else(|| blob_hash(content.as_bytes()));
+            Some(content_uuid(&hash)).filter(|uuid| !seen.contains(uuid)).unwrap_or_else(random_uuid)
+        };
+        seen.insert(uuid.clone());
+        conn.execute("UPDATE clipboard_history SET uuid = ?1 WHERE id = ?2", params![uuid, id])?;
+    }
+
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0)
+    )
+}
+
+/// Deleted entries stay in the table with `deleted_at` set until the trash
+/// is emptied. User deletes share a `delete_batch` number so the most recent
+/// one can be undone as a unit.
+fn v12_trash(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN deleted_at DATETIME;
+        ALTER TABLE clipboard_history ADD COLUMN delete_batch INTEGER;
+        CREATE INDEX idx_deleted_at ON clipboard_history(deleted_at);"
+    )
+}
+
+/// Entries flagged by the sensitive data detector, and the time after which
+/// an entry is deleted outright.
+fn v13_sensitivity(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN sensitivity TEXT;
+        ALTER TABLE clipboard_history ADD COLUMN expires_at DATETIME;
+        CREATE INDEX idx_expires_at ON clipboard_history(expires_at) WHERE expires_at IS NOT NULL;"
+    )
+}
+
+/// Ignored apps become substring ignore rules on the process name or the
+/// window title, stored with the other app rules in settings.
+fn v14_app_rules(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "INSERT INTO settings (key, value)
+        SELECT 'app_rules', json_object('mode', 'all', 'rules', json_group_array(json_object(
+            'pattern', app_name, 'kind', 'substring', 'field', 'either', 'list', 'ignore'
+        )))
+        FROM ignored_apps HAVING COUNT(*) > 0;
+        DROP TABLE ignored_apps;"
+    )
+}
+
+/// Language of code entries, and existing text entries classified again
+/// with the new content types. Encrypted entries keep their old type, since
+/// migrations run before the history is unlocked.
+fn v15_classification(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clipboard_history ADD COLUMN language TEXT;
+        CREATE INDEX idx_content_type ON clipboard_history(content_type, language);"
+    )?;
+
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, content FROM clipboard_history WHERE encrypted = 0 AND content_type != 'image'"
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+
+    let mut update = conn.prepare("UPDATE clipboard_history SET content_type = ?1, language = ?2 WHERE id = ?3")?;
+    for (id, content) in rows {
+        let kind = classify::classify(&content);
+        update.execute(params![kind.content_type.as_str(), kind.language.map(|l| l.as_str()), id])?;
+    }
+    Ok(())
+}
+
+/// Values that must not be readable through the settings commands and are
+/// sealed with the history while encryption is on. The LAN peer list with
+/// its pairing keys moves here; in an encrypted history it stays plaintext
+/// until first read after unlocking.
+fn v16_secrets(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE secrets (
+            key TEXT PRIMARY KEY,
+            value BLOB NOT NULL,
+            encrypted INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO secrets (key, value) SELECT key, value FROM settings WHERE key = 'lan_peers';
+        DELETE FROM settings WHERE key = 'lan_peers';"
+    )
+}
+
+/// Blobs of an encrypted history are addressed by a keyed hash instead of
+/// the plain SHA-256 of the image. Existing ones move on the next unlock.
+fn v17_keyed_blob_addresses(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value)
+         SELECT ?1, '1' WHERE EXISTS (SELECT 1 FROM blobs WHERE encrypted = 1)",
+        params![CONTENT_HASH_PENDING_KEY]
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Schema shipped in 0.1.0 before `content_blob` was added.
+    const LEGACY_NO_BLOB: &str = "
+        CREATE TABLE clipboard_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            content TEXT NOT NULL,
+            source_app TEXT,
+            content_type TEXT DEFAULT 'text',
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            is_pinned BOOLEAN DEFAULT 0
+        );
+        CREATE VIRTUAL TABLE history_fts USING fts5(
+            content, source_app, content='clipboard_history', content_rowid='id'
+        );
+        CREATE TRIGGER history_ai AFTER INSERT ON clipboard_history BEGIN
+            INSERT INTO history_fts(rowid, content, source_app) VALUES (new.id, new.content, new.source_app);
+        END;
+        CREATE TABLE ignored_apps (id INTEGER PRIMARY KEY AUTOINCREMENT, app_name TEXT UNIQUE NOT NULL);
+        CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+        INSERT INTO clipboard_history (content, source_app, is_pinned) VALUES ('hello world', 'firefox', 1);
+        INSERT INTO ignored_apps (app_name) VALUES ('KeePassXC');
+    ";
+
+    /// Same as above, after the ad-hoc `content_blob` column migration ran.
+    const LEGACY_WITH_BLOB: &str = "
+        ALTER TABLE clipboard_history ADD COLUMN content_blob TEXT;
+        INSERT INTO clipboard_history (content, content_type, content_blob) VALUES ('[Image 1x1]', 'image', 'iVBORw0KGgo=');
+    ";
+
+    fn assert_latest(conn: &Connection) {
+        assert_eq!(current_version(conn).unwrap(), latest_version());
+        assert!(has_column(conn, "clipboard_history", "blob_hash").unwrap());
+        assert!(!has_column(conn, "clipboard_history", "content_blob").unwrap());
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn).unwrap();
+        assert_latest(&conn);
+    }
+
+    #[test]
+    fn upgrades_legacy_database_without_blob_column() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_NO_BLOB).unwrap();
+
+        run(&mut conn).unwrap();
+
+        assert_latest(&conn);
+        let (content, pinned): (String, bool) = conn
+            .query_row("SELECT content, is_pinned FROM clipboard_history", [], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap();
+        assert_eq!(content, "hello world");
+        assert!(pinned);
+        let content_type: String = conn
+            .query_row("SELECT content_type FROM clipboard_history", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(content_type, "text");
+        let rules: String = conn
+            .query_row("SELECT value FROM settings WHERE key = 'app_rules'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(
+            rules,
+            r#"{"mode":"all","rules":[{"pattern":"KeePassXC","kind":"substring","field":"either","list":"ignore"}]}"#
+        );
+    }
+
+    #[test]
+    fn upgrades_legacy_database_with_blob_column() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_NO_BLOB).unwrap();
+        conn.execute_batch(LEGACY_WITH_BLOB).unwrap();
+
+        run(&mut conn).unwrap();
+
+        assert_latest(&conn);
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM clipboard_history", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let data: Vec<u8> = conn
+            .query_row(
+                "SELECT b.data FROM clipboard_history h JOIN blobs b ON b.hash = h.blob_hash",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(data, STANDARD.decode("iVBORw0KGgo=").unwrap());
+    }
+
+    #[test]
+    fn identical_legacy_images_share_one_blob() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, MIGRATIONS, 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO clipboard_history (content, content_type, content_blob) VALUES ('[Image 1x1]', 'image', 'iVBORw0KGgo=');
+             INSERT INTO clipboard_history (content, content_type, content_blob) VALUES ('[Image 1x1]', 'image', 'iVBORw0KGgo=');
+             INSERT INTO clipboard_history (content, content_type, content_blob) VALUES ('[Image 2x2]', 'image', 'AAAA');"
+        )
+        .unwrap();
+
+        run(&mut conn).unwrap();
+
+        let blobs: i64 = conn.query_row("SELECT COUNT(*) FROM blobs", [], |r| r.get(0)).unwrap();
+        assert_eq!(blobs, 2);
+
+        // The shared blob survives until its last referencing entry is gone.
+        conn.execute("DELETE FROM clipboard_history WHERE id = 1", []).unwrap();
+        let blobs: i64 = conn.query_row("SELECT COUNT(*) FROM blobs", [], |r| r.get(0)).unwrap();
+        assert_eq!(blobs, 2);
+        conn.execute("DELETE FROM clipboard_history WHERE id = 2", []).unwrap();
+        let blobs: i64 = conn.query_row("SELECT COUNT(*) FROM blobs", [], |r| r.get(0)).unwrap();
+        assert_eq!(blobs, 1);
+    }
+
+    #[test]
+    fn lan_peers_move_out_of_settings() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, MIGRATIONS, 15).unwrap();
+        conn.execute_batch(
+            "INSERT INTO settings (key, value) VALUES ('lan_peers', '[]'), ('lan_config', '{}');"
+        )
+        .unwrap();
+
+        run(&mut conn).unwrap();
+
+        let keys: Vec<String> = conn
+            .prepare("SELECT key FROM settings WHERE key LIKE 'lan_%'")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(keys, vec!["lan_config".to_string()]);
+        let peers: String = conn
+            .query_row("SELECT value FROM secrets WHERE key = 'lan_peers' AND encrypted = 0", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(peers, "[]");
+    }
+
+    #[test]
+    fn encrypted_blobs_wait_for_the_key_to_move() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, MIGRATIONS, 16).unwrap();
+        conn.execute_batch("INSERT INTO blobs (hash, data, size, encrypted) VALUES ('plain', x'00', 1, 1);").unwrap();
+
+        run(&mut conn).unwrap();
+
+        let pending: i64 = conn
+            .query_row("SELECT COUNT(*) FROM settings WHERE key = ?1", params![CONTENT_HASH_PENDING_KEY], |r| r.get(0))
+            .unwrap();
+        assert_eq!(pending, 1);
+    }
+
+    #[test]
+    fn upgrades_from_every_released_version() {
+        for migration in MIGRATIONS {
+            let mut conn = Connection::open_in_memory().unwrap();
+            apply(&mut conn, MIGRATIONS, migration.version).unwrap();
+            assert_eq!(current_version(&conn).unwrap(), migration.version);
+
+            conn.execute(
+                "INSERT INTO clipboard_history (content, source_app) VALUES ('fixture', 'terminal')",
+                [],
+            )
+            .unwrap();
+
+            run(&mut conn).unwrap();
+            assert_latest(&conn);
+            let count: i64 = conn
+                .query_row("SELECT COUNT(*) FROM clipboard_history WHERE content = 'fixture'", [], |r| r.get(0))
+                .unwrap();
+            assert_eq!(count, 1, "data lost upgrading from v{}", migration.version);
+        }
+    }
+
+    #[test]
+    fn backfills_thumbnails_for_existing_blobs() {
+        let mut png = Vec::new();
+        image::RgbaImage::new(800, 600)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, MIGRATIONS, 2).unwrap();
+        conn.execute(
+            "INSERT INTO blobs (hash, data, size) VALUES (?1, ?2, ?3)",
+            params![blob_hash(&png), png, png.len() as i64],
+        )
+        .unwrap();
+
+        run(&mut conn).unwrap();
+
+        let thumb: Vec<u8> = conn
+            .query_row("SELECT data FROM blob_thumbnails WHERE hash = ?1", [blob_hash(&png)], |r| r.get(0))
+            .unwrap();
+        let thumb = image::load_from_memory(&thumb).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (160, 120));
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+
+        match run(&mut conn) {
+            Err(MigrationError::TooNew { found, supported }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(supported, latest_version());
+            }
+            other => panic!("expected TooNew, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        fn broken(conn: &Connection) -> rusqlite::Result<()> {
+            conn.execute_batch("CREATE TABLE half_done (id INTEGER); SELECT * FROM missing_table;")
+        }
+        let migrations = [
+            Migration { version: 1, description: "initial schema", up: v1_initial_schema },
+            Migration { version: 2, description: "broken", up: broken },
+        ];
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(apply(&mut conn, &migrations, 2).is_err());
+
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        let leftover: bool = conn
+            .query_row("SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'half_done'", [], |r| r.get(0))
+            .unwrap();
+        assert!(!leftover);
+    }
+}
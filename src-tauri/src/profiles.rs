@@ -0,0 +1,207 @@
+//! Named profiles, each a separate history database with its own settings
+//! and app rules.
+//!
+//! The default profile keeps the original `ClipStream/clipboard.db`, so
+//! existing installs carry on unchanged; other profiles live under
+//! `ClipStream/profiles/<name>/`. The active profile is recorded in a small
+//! file next to them, since settings themselves are per profile.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILES_DIR: &str = "profiles";
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+const DATABASE_FILE: &str = "clipboard.db";
+const MAX_NAME_LEN: usize = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub active: bool,
+}
+
+fn data_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(dir) = tests::DATA_DIR.with(|dir| dir.borrow().clone()) {
+        return dir;
+    }
+    let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("ClipStream");
+    path
+}
+
+pub fn database_path(name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        data_dir().join(DATABASE_FILE)
+    } else {
+        data_dir().join(PROFILES_DIR).join(name).join(DATABASE_FILE)
+    }
+}
+
+/// Profile names, the default one first.
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(data_dir().join(PROFILES_DIR))
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| validate_name(name).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    names
+}
+
+pub fn list() -> Vec<Profile> {
+    let active = active();
+    names()
+        .into_iter()
+        .map(|name| Profile { active: name == active, name })
+        .collect()
+}
+
+pub fn exists(name: &str) -> bool {
+    names().iter().any(|n| n == name)
+}
+
+/// The active profile, falling back to the default one if the recorded
+/// profile was removed.
+pub fn active() -> String {
+    fs::read_to_string(data_dir().join(ACTIVE_PROFILE_FILE))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| exists(name))
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+pub fn set_active(name: &str) -> Result<(), String> {
+    let dir = data_dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    fs::write(dir.join(ACTIVE_PROFILE_FILE), name).map_err(|e| e.to_string())
+}
+
+pub fn create(name: &str) -> Result<String, String> {
+    let name = validate_name(name)?;
+    // Compared case-insensitively since profile directories may be too
+    if names().iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+        return Err(format!("Profile \"{}\" already exists", name));
+    }
+    fs::create_dir_all(data_dir().join(PROFILES_DIR).join(&name)).map_err(|e| e.to_string())?;
+    Ok(name)
+}
+
+/// Delete a profile and its history, including its backups.
+pub fn delete(name: &str) -> Result<(), String> {
+    if name == DEFAULT_PROFILE {
+        return Err("The default profile cannot be deleted".to_string());
+    }
+    if !exists(name) {
+        return Err(format!("Profile \"{}\" not found", name));
+    }
+    if name == active() {
+        return Err("Switch to another profile before deleting this one".to_string());
+    }
+    fs::remove_dir_all(data_dir().join(PROFILES_DIR).join(name)).map_err(|e| e.to_string())
+}
+
+fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("Profile name must be at most {} characters", MAX_NAME_LEN));
+    }
+    // Names become directory names, so keep them portable
+    if !name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') {
+        return Err("Profile name may only contain letters, digits, spaces, '-' and '_'".to_string());
+    }
+    if name.eq_ignore_ascii_case(DEFAULT_PROFILE) {
+        return Err(format!("\"{}\" is reserved", DEFAULT_PROFILE));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// Stands in for the real data directory on the test's thread.
+        pub static DATA_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+    }
+
+    /// Point this thread's profiles at an empty temporary directory.
+    fn temp_data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clipstream-profiles-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        DATA_DIR.with(|data_dir| *data_dir.borrow_mut() = Some(dir.clone()));
+        dir
+    }
+
+    #[test]
+    fn names_must_be_portable_directory_names() {
+        assert_eq!(validate_name("  Work stuff_2-b ").unwrap(), "Work stuff_2-b");
+        assert_eq!(validate_name("Café Ärger").unwrap(), "Café Ärger");
+        assert!(validate_name(&"é".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+        for name in ["", "   ", "a/b", "a\\b", "..", "a.b", "a:b", "Default", "DEFAULT"] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn names_are_unique_ignoring_case() {
+        let dir = temp_data_dir("names");
+        assert_eq!(names(), vec![DEFAULT_PROFILE]);
+
+        assert_eq!(create(" work ").unwrap(), "work");
+        assert_eq!(create("Home").unwrap(), "Home");
+        assert_eq!(create("WORK").unwrap_err(), "Profile \"WORK\" already exists");
+        assert!(create("default").is_err());
+        // Directories that are not valid profile names are not profiles
+        fs::create_dir_all(dir.join(PROFILES_DIR).join("not.a.profile")).unwrap();
+
+        assert_eq!(names(), vec![DEFAULT_PROFILE, "Home", "work"]);
+        assert!(exists("work") && !exists("Work"));
+        assert_eq!(database_path("work"), dir.join(PROFILES_DIR).join("work").join(DATABASE_FILE));
+        assert_eq!(database_path(DEFAULT_PROFILE), dir.join(DATABASE_FILE));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn active_and_default_profiles_are_not_deleted() {
+        let dir = temp_data_dir("delete");
+        create("work").unwrap();
+        create("home").unwrap();
+        assert_eq!(active(), DEFAULT_PROFILE);
+
+        set_active("work").unwrap();
+        assert_eq!(active(), "work");
+        let active_flags: Vec<(String, bool)> = list().into_iter().map(|p| (p.name, p.active)).collect();
+        assert_eq!(active_flags, vec![
+            (DEFAULT_PROFILE.to_string(), false),
+            ("home".to_string(), false),
+            ("work".to_string(), true),
+        ]);
+
+        assert!(delete(DEFAULT_PROFILE).is_err());
+        assert!(delete("work").is_err());
+        assert_eq!(delete("gone").unwrap_err(), "Profile \"gone\" not found");
+        delete("home").unwrap();
+        assert_eq!(names(), vec![DEFAULT_PROFILE, "work"]);
+
+        // A recorded profile removed behind our back falls back to the default
+        fs::remove_dir_all(dir.join(PROFILES_DIR).join("work")).unwrap();
+        assert_eq!(active(), DEFAULT_PROFILE);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
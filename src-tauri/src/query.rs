@@ -0,0 +1,482 @@
+use crate::classify::{ContentType, Language};
+use chrono::{Duration, Local, NaiveDate, Utc};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A parsed search query.
+///
+/// Syntax: bare words match as prefixes, `"exact phrase"` matches literally,
+/// `-word` excludes, `a OR b` matches either, and `field:value` filters on
+/// `app`, `type`, `lang`, `pinned`, `after`, `before` and `tag`. Filters can be
+/// negated with `-` as well, e.g. `-app:slack`. Any other `name:value` is
+/// rejected as a mistyped filter; quote it to search for the text.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    /// Terms that must match, as AND-ed groups of OR-ed alternatives.
+    groups: Vec<Vec<Term>>,
+    excluded: Vec<Term>,
+    filters: Vec<(Filter, bool)>,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Prefix(String),
+    Phrase(String),
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    App(String),
+    Type(ContentType),
+    Language(Language),
+    Pinned(bool),
+    After(String),
+    Before(String),
+    Tag(String),
+}
+
+/// How free-text terms are matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Prefix FTS, topped up with fuzzy matches when it finds too few rows.
+    #[default]
+    Auto,
+    /// Prefix FTS only.
+    Exact,
+    /// Typo-tolerant trigram matching only.
+    Fuzzy,
+}
+
+/// How results are ordered. Pinned entries always come first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchSort {
+    /// Best text match first, or newest first without search terms.
+    #[default]
+    Relevance,
+    /// Blend of how often and how recently an entry was used, weighted by
+    /// text relevance when there are search terms.
+    Frecency,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError(pub String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid search: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// SQL pieces for `Database::search`. `fts_match` goes in a `MATCH` against
+/// the joined FTS table; `conditions` are AND-ed with `?` placeholders bound
+/// to `params` in order.
+#[derive(Debug, Clone, Default)]
+pub struct CompiledQuery {
+    pub fts_match: Option<String>,
+    pub conditions: Vec<String>,
+    pub params: Vec<Value>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    Field(String, String),
+    Or,
+    And,
+    Not,
+    Minus,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let tokens = tokenize(input)?;
+        let mut query = SearchQuery::default();
+        let mut iter = tokens.into_iter().peekable();
+        let mut pending_or = false;
+
+        while let Some(token) = iter.next() {
+            match token {
+                Token::Or | Token::And => {
+                    let op = if token == Token::Or { "OR" } else { "AND" };
+                    if query.groups.is_empty() || pending_or {
+                        return Err(QueryError(format!("'{}' needs a search term before it", op)));
+                    }
+                    match iter.peek() {
+                        Some(Token::Word(_)) | Some(Token::Phrase(_)) => {}
+                        _ => return Err(QueryError(format!("'{}' needs a search term after it", op))),
+                    }
+                    pending_or = token == Token::Or;
+                }
+                Token::Not | Token::Minus => {
+                    let op = if token == Token::Not { "NOT" } else { "-" };
+                    match iter.next() {
+                        Some(Token::Word(word)) => query.excluded.push(Term::Prefix(word)),
+                        Some(Token::Phrase(phrase)) => query.excluded.push(Term::Phrase(phrase)),
+                        Some(Token::Field(name, value)) => query.filters.push((parse_filter(&name, &value)?, true)),
+                        _ => return Err(QueryError(format!("'{}' needs a search term after it", op))),
+                    }
+                }
+                Token::Word(word) => query.push_term(Term::Prefix(word), std::mem::take(&mut pending_or)),
+                Token::Phrase(phrase) => query.push_term(Term::Phrase(phrase), std::mem::take(&mut pending_or)),
+                Token::Field(name, value) => query.filters.push((parse_filter(&name, &value)?, false)),
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn push_term(&mut self, term: Term, or_with_previous: bool) {
+        match self.groups.last_mut() {
+            Some(group) if or_with_previous => group.push(term),
+            _ => self.groups.push(vec![term]),
+        }
+    }
+
+    /// The terms that must match, as plain text for fuzzy scoring.
+    pub fn text(&self) -> String {
+        self.groups
+            .iter()
+            .flatten()
+            .map(|term| match term {
+                Term::Prefix(text) | Term::Phrase(text) => text.as_str(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Whether the query quotes an exact phrase, which opts out of the fuzzy
+    /// fallback in `SearchMode::Auto`.
+    pub fn has_phrase(&self) -> bool {
+        self.groups.iter().flatten().any(|term| matches!(term, Term::Phrase(_)))
+    }
+
+    /// Build SQL against `fts_table` (the FTS5 table joined as `fts`).
+    pub fn compile(&self, fts_table: &str) -> CompiledQuery {
+        let mut compiled = CompiledQuery::default();
+
+        if !self.groups.is_empty() {
+            let expr = self
+                .groups
+                .iter()
+                .map(|group| {
+                    let alternatives: Vec<String> = group.iter().map(Term::to_fts).collect();
+                    if alternatives.len() == 1 {
+                        alternatives.into_iter().next().unwrap()
+                    } else {
+                        format!("({})", alternatives.join(" OR "))
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            compiled.fts_match = Some(expr);
+        }
+
+        if !self.excluded.is_empty() {
+            let expr = self.excluded.iter().map(Term::to_fts).collect::<Vec<_>>().join(" OR ");
+            compiled.conditions.push(format!(
+                "h.id NOT IN (SELECT rowid FROM {fts} WHERE {fts} MATCH ?)",
+                fts = fts_table
+            ));
+            compiled.params.push(Value::Text(expr));
+        }
+
+        for (filter, negated) in &self.filters {
+            let (condition, param) = filter.to_sql();
+            compiled.conditions.push(if *negated {
+                format!("NOT ({})", condition)
+            } else {
+                condition.to_string()
+            });
+            compiled.params.push(param);
+        }
+
+        compiled
+    }
+}
+
+impl Term {
+    fn to_fts(&self) -> String {
+        match self {
+            Term::Prefix(word) => format!("\"{}\"*", word.replace('"', "\"\"")),
+            Term::Phrase(phrase) => format!("\"{}\"", phrase.replace('"', "\"\"")),
+        }
+    }
+}
+
+impl Filter {
+    fn to_sql(&self) -> (&'static str, Value) {
+        match self {
+            Filter::App(app) => ("COALESCE(h.source_app, '') LIKE ? ESCAPE '\\'", Value::Text(format!("%{}%", escape_like(app)))),
+            Filter::Type(content_type) => ("h.content_type = ?", Value::Text(content_type.as_str().to_string())),
+            Filter::Language(language) => ("h.language = ?", Value::Text(language.as_str().to_string())),
+            Filter::Pinned(pinned) => ("h.is_pinned = ?", Value::Integer(*pinned as i64)),
+            Filter::After(timestamp) => ("h.created_at >= ?", Value::Text(timestamp.clone())),
+            Filter::Before(timestamp) => ("h.created_at < ?", Value::Text(timestamp.clone())),
+            Filter::Tag(tag) => (
+                "EXISTS (SELECT 1 FROM entry_tags et JOIN tags t ON t.id = et.tag_id WHERE et.entry_id = h.id AND t.name = ?)",
+                Value::Text(tag.clone()),
+            ),
+        }
+    }
+}
+
+fn parse_filter(name: &str, value: &str) -> Result<Filter, QueryError> {
+    if value.is_empty() {
+        return Err(QueryError(format!("'{}:' needs a value", name)));
+    }
+
+    match name {
+        "app" => Ok(Filter::App(value.to_string())),
+        "type" => match ContentType::from_name(&value.to_lowercase()) {
+            Some(content_type) => Ok(Filter::Type(content_type)),
+            None => Err(QueryError(format!(
+                "unknown type '{}', expected one of: {}",
+                value,
+                ContentType::ALL.map(ContentType::as_str).join(", ")
+            ))),
+        },
+        "lang" => match Language::from_name(&value.to_lowercase()) {
+            Some(language) => Ok(Filter::Language(language)),
+            None => Err(QueryError(format!(
+                "unknown language '{}', expected one of: {}",
+                value,
+                Language::ALL.map(Language::as_str).join(", ")
+            ))),
+        },
+        "pinned" => match value.to_lowercase().as_str() {
+            "yes" | "true" | "1" => Ok(Filter::Pinned(true)),
+            "no" | "false" | "0" => Ok(Filter::Pinned(false)),
+            _ => Err(QueryError(format!("'pinned:' expects yes or no, got '{}'", value))),
+        },
+        "after" => Ok(Filter::After(parse_date(value)?)),
+        "before" => Ok(Filter::Before(parse_date(value)?)),
+        "tag" => Ok(Filter::Tag(value.to_string())),
+        _ => unreachable!("tokenizer only emits known fields"),
+    }
+}
+
+/// Parse `YYYY-MM-DD`, `today` or `yesterday` as local midnight, returned in
+/// the UTC `YYYY-MM-DD HH:MM:SS` format SQLite stores in `created_at`.
+pub(crate) fn parse_date(value: &str) -> Result<String, QueryError> {
+    let today = Local::now().date_naive();
+    let date = match value.to_lowercase().as_str() {
+        "today" => today,
+        "yesterday" => today - Duration::days(1),
+        _ => NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+            QueryError(format!("'{}' is not a date, use YYYY-MM-DD, today or yesterday", value))
+        })?,
+    };
+
+    let midnight = date
+        .and_hms_opt(0, 0, 0)
+        .and_then(|dt| dt.and_local_timezone(Local).earliest())
+        .ok_or_else(|| QueryError(format!("'{}' is not a valid local date", value)))?;
+
+    Ok(midnight.with_timezone(&Utc).format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+pub(crate) fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+const FIELDS: &[&str] = &["app", "type", "lang", "pinned", "after", "before", "tag"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c == '"' {
+            chars.next();
+            tokens.push(Token::Phrase(read_quoted(&mut chars)?));
+            continue;
+        }
+
+        if c == '-' {
+            chars.next();
+            match chars.peek() {
+                Some(next) if !next.is_whitespace() => tokens.push(Token::Minus),
+                _ => return Err(QueryError("'-' needs a search term after it".to_string())),
+            }
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            word.push(c);
+
+            // `field:"quoted value"`
+            if c == ':' && chars.peek() == Some(&'"') {
+                let field = word.trim_end_matches(':').to_lowercase();
+                if FIELDS.contains(&field.as_str()) {
+                    chars.next();
+                    let value = read_quoted(&mut chars)?;
+                    tokens.push(Token::Field(field, value));
+                    word.clear();
+                    break;
+                }
+            }
+        }
+        if word.is_empty() {
+            continue;
+        }
+
+        let token = match word.as_str() {
+            "OR" => Token::Or,
+            "AND" => Token::And,
+            "NOT" => Token::Not,
+            _ => match word.split_once(':') {
+                Some((field, value)) if FIELDS.contains(&field.to_lowercase().as_str()) => {
+                    Token::Field(field.to_lowercase(), value.to_string())
+                }
+                // A mistyped filter would otherwise silently search for its text;
+                // URLs and times like `12:30` are not fields
+                Some((field, value))
+                    if field.chars().all(|c| c.is_ascii_alphabetic()) && !value.is_empty() && !value.starts_with('/') =>
+                {
+                    return Err(QueryError(format!(
+                        "unknown filter '{}:', expected one of: {} (quote it to search for the text)",
+                        field,
+                        FIELDS.join(", ")
+                    )));
+                }
+                _ => Token::Word(word),
+            },
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, QueryError> {
+    let mut value = String::new();
+    for c in chars.by_ref() {
+        if c == '"' {
+            return if value.trim().is_empty() {
+                Err(QueryError("empty quotes".to_string()))
+            } else {
+                Ok(value)
+            };
+        }
+        value.push(c);
+    }
+    Err(QueryError("missing closing quote".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(input: &str) -> CompiledQuery {
+        SearchQuery::parse(input).unwrap().compile("fts")
+    }
+
+    fn error(input: &str) -> String {
+        SearchQuery::parse(input).unwrap_err().0
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    #[test]
+    fn filters_compile_to_conditions() {
+        let cases = [
+            ("app:slack", "COALESCE(h.source_app, '') LIKE ? ESCAPE '\\'", text("%slack%")),
+            ("app:my_app", "COALESCE(h.source_app, '') LIKE ? ESCAPE '\\'", text("%my\\_app%")),
+            ("type:url", "h.content_type = ?", text("url")),
+            ("TYPE:JSON", "h.content_type = ?", text("json")),
+            ("lang:ts", "h.language = ?", text("typescript")),
+            ("pinned:yes", "h.is_pinned = ?", Value::Integer(1)),
+            ("pinned:false", "h.is_pinned = ?", Value::Integer(0)),
+            ("tag:work", "EXISTS (SELECT 1 FROM entry_tags et JOIN tags t ON t.id = et.tag_id WHERE et.entry_id = h.id AND t.name = ?)", text("work")),
+            ("tag:\"to do\"", "EXISTS (SELECT 1 FROM entry_tags et JOIN tags t ON t.id = et.tag_id WHERE et.entry_id = h.id AND t.name = ?)", text("to do")),
+            ("after:2024-01-05", "h.created_at >= ?", text(&parse_date("2024-01-05").unwrap())),
+            ("before:today", "h.created_at < ?", text(&parse_date("today").unwrap())),
+        ];
+        for (input, condition, param) in cases {
+            let compiled = compile(input);
+            assert_eq!(compiled.fts_match, None, "{}", input);
+            assert_eq!(compiled.conditions, vec![condition.to_string()], "{}", input);
+            assert_eq!(compiled.params, vec![param], "{}", input);
+        }
+
+        let negated = compile("-app:slack");
+        assert_eq!(negated.conditions, vec!["NOT (COALESCE(h.source_app, '') LIKE ? ESCAPE '\\')".to_string()]);
+    }
+
+    #[test]
+    fn relative_dates_are_local_midnights() {
+        let today = parse_date("today").unwrap();
+        let yesterday = parse_date("yesterday").unwrap();
+        assert!(yesterday < today);
+        assert_eq!(parse_date("Yesterday").unwrap(), yesterday);
+
+        let compiled = compile("after:yesterday before:today");
+        assert_eq!(compiled.conditions, vec!["h.created_at >= ?".to_string(), "h.created_at < ?".to_string()]);
+        assert_eq!(compiled.params, vec![text(&yesterday), text(&today)]);
+    }
+
+    #[test]
+    fn terms_compile_to_fts() {
+        assert_eq!(compile("foo bar").fts_match.as_deref(), Some("\"foo\"* AND \"bar\"*"));
+        assert_eq!(compile("foo AND bar").fts_match.as_deref(), Some("\"foo\"* AND \"bar\"*"));
+        assert_eq!(compile("foo OR bar baz").fts_match.as_deref(), Some("(\"foo\"* OR \"bar\"*) AND \"baz\"*"));
+
+        let phrase = SearchQuery::parse("\"exact phrase\" word").unwrap();
+        assert!(phrase.has_phrase());
+        assert_eq!(phrase.text(), "exact phrase word");
+        assert_eq!(phrase.compile("fts").fts_match.as_deref(), Some("\"exact phrase\" AND \"word\"*"));
+
+        let excluded = compile("foo -bar NOT \"two words\"");
+        assert_eq!(excluded.fts_match.as_deref(), Some("\"foo\"*"));
+        assert_eq!(excluded.conditions, vec!["h.id NOT IN (SELECT rowid FROM fts WHERE fts MATCH ?)".to_string()]);
+        assert_eq!(excluded.params, vec![text("\"bar\"* OR \"two words\"")]);
+
+        // Quotes inside terms are escaped, URLs and times are plain words
+        assert_eq!(compile("say\"hi").fts_match.as_deref(), Some("\"say\"\"hi\"*"));
+        assert_eq!(compile("https://example.com").fts_match.as_deref(), Some("\"https://example.com\"*"));
+        assert_eq!(compile("12:30").fts_match.as_deref(), Some("\"12:30\"*"));
+        assert_eq!(compile("\"foo:bar\"").fts_match.as_deref(), Some("\"foo:bar\""));
+    }
+
+    #[test]
+    fn invalid_queries_explain_why() {
+        let cases = [
+            ("foo AND", "'AND' needs a search term after it"),
+            ("OR foo", "'OR' needs a search term before it"),
+            ("foo OR OR bar", "'OR' needs a search term after it"),
+            ("foo -", "'-' needs a search term after it"),
+            ("-", "'-' needs a search term after it"),
+            ("NOT", "'NOT' needs a search term after it"),
+            ("\"open", "missing closing quote"),
+            ("\"  \"", "empty quotes"),
+            ("app:", "'app:' needs a value"),
+            ("pinned:maybe", "'pinned:' expects yes or no, got 'maybe'"),
+            ("after:2024-13-01", "'2024-13-01' is not a date, use YYYY-MM-DD, today or yesterday"),
+            ("before:tomorrow", "'tomorrow' is not a date, use YYYY-MM-DD, today or yesterday"),
+        ];
+        for (input, message) in cases {
+            assert_eq!(error(input), message, "{}", input);
+        }
+        assert!(error("type:movie").starts_with("unknown type 'movie', expected one of: text, url"));
+        assert!(error("lang:cobol").starts_with("unknown language 'cobol'"));
+        assert!(error("color:red").starts_with("unknown filter 'color:', expected one of: app, type"));
+    }
+}
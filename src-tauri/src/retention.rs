@@ -0,0 +1,173 @@
+use crate::classify::ContentType;
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Settings key holding the JSON-encoded retention policy.
+pub const POLICY_SETTING_KEY: &str = "retention_policy";
+
+/// Rules deciding which unpinned entries `Database::cleanup` moves to the
+/// trash, and how long the trash keeps them.
+///
+/// Age limits are resolved most-specific first: a matching source app rule
+/// wins over a content type rule, which wins over `max_age_days`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub max_age_days: Option<f64>,
+    pub max_entries: Option<i64>,
+    pub max_storage_mb: Option<f64>,
+    /// Per content type age limit, e.g. `{"image": 1, "text": 30}`. Types
+    /// without their own key fall back to the category they had before
+    /// content was classified in detail, see `legacy_category`.
+    pub content_type_max_age_days: BTreeMap<String, f64>,
+    /// Per source app age limit, matched case-insensitively as a substring.
+    pub app_max_age_days: BTreeMap<String, f64>,
+    /// How often the background worker applies the policy.
+    pub interval_minutes: u64,
+    /// Days deleted entries stay in the trash before they are removed for good.
+    pub trash_days: f64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_days: Some(7.0),
+            max_entries: Some(500),
+            max_storage_mb: None,
+            content_type_max_age_days: BTreeMap::new(),
+            app_max_age_days: BTreeMap::new(),
+            interval_minutes: 60,
+            trash_days: 30.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionReason {
+    Age,
+    Count,
+    Storage,
+}
+
+/// An entry the policy would delete, as reported by the dry run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionCandidate {
+    pub id: i64,
+    pub preview: String,
+    pub content_type: String,
+    pub source_app: Option<String>,
+    pub created_at: String,
+    pub reason: RetentionReason,
+}
+
+impl RetentionPolicy {
+    /// Load the policy from settings, falling back to the defaults.
+    pub fn load(db: &Database) -> Self {
+        match db.get_setting(POLICY_SETTING_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Invalid retention policy, using defaults: {}", e);
+                Self::default()
+            }),
+            Ok(None) => Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read retention policy: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        db.set_setting(POLICY_SETTING_KEY, &json).map_err(|e| e.to_string())
+    }
+
+    /// Age limit in days that applies to an entry, if any.
+    pub fn max_age_for(&self, content_type: &str, source_app: Option<&str>) -> Option<f64> {
+        if let Some(app) = source_app {
+            let app_lower = app.to_lowercase();
+            let app_rule = self
+                .app_max_age_days
+                .iter()
+                .find(|(name, _)| app_lower.contains(&name.to_lowercase()));
+            if let Some((_, days)) = app_rule {
+                return Some(*days);
+            }
+        }
+
+        self.content_type_max_age_days
+            .get(content_type)
+            .or_else(|| self.content_type_max_age_days.get(legacy_category(content_type)))
+            .copied()
+            .or(self.max_age_days)
+    }
+
+    pub fn max_storage_bytes(&self) -> Option<i64> {
+        self.max_storage_mb.map(|mb| (mb * 1024.0 * 1024.0) as i64)
+    }
+}
+
+/// The `text`/`url`/`code`/`image` type an entry had before detailed
+/// classification, so rules saved back then keep covering the same entries:
+/// a `text` rule still applies to JSON, paths, emails and so on.
+fn legacy_category(content_type: &str) -> &'static str {
+    match ContentType::from_name(content_type) {
+        Some(ContentType::Url) => "url",
+        Some(ContentType::Code) => "code",
+        Some(ContentType::Image) => "image",
+        _ => "text",
+    }
+}
+
+/// Apply the retention policy now and then every `interval_minutes`. The
+/// policy is re-read from settings on every pass so edits take effect
+/// without a restart, and `db` is called each time so the pass follows the
+/// active profile.
+pub fn start_worker<F>(db: F)
+where
+    F: Fn() -> Arc<Database> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        let db = db();
+        let policy = RetentionPolicy::load(&db);
+
+        if let Err(e) = db.cleanup(&policy) {
+            eprintln!("Cleanup error: {}", e);
+        }
+        drop(db);
+
+        thread::sleep(Duration::from_secs(policy.interval_minutes.max(1) * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detailed_types_fall_back_to_their_legacy_rule() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(7.0),
+            content_type_max_age_days: BTreeMap::from([
+                ("text".to_string(), 30.0),
+                ("code".to_string(), 90.0),
+                ("json".to_string(), 2.0),
+            ]),
+            app_max_age_days: BTreeMap::from([("keepass".to_string(), 0.5)]),
+            ..RetentionPolicy::default()
+        };
+
+        assert_eq!(policy.max_age_for("text", None), Some(30.0));
+        assert_eq!(policy.max_age_for("yaml", None), Some(30.0));
+        assert_eq!(policy.max_age_for("email", None), Some(30.0));
+        assert_eq!(policy.max_age_for("shell", None), Some(30.0));
+        assert_eq!(policy.max_age_for("json", None), Some(2.0));
+        assert_eq!(policy.max_age_for("code", None), Some(90.0));
+        assert_eq!(policy.max_age_for("url", None), Some(7.0));
+        assert_eq!(policy.max_age_for("yaml", Some("KeePassXC")), Some(0.5));
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+/// Marks FTS5 `snippet()` wraps around matched tokens. Control characters
+/// cannot be typed into a search and are vanishingly rare in clipboard text.
+pub const MATCH_START: char = '\u{2}';
+pub const MATCH_END: char = '\u{3}';
+pub const ELLIPSIS: &str = "…";
+
+/// Characters of context kept on each side of a fuzzy match.
+const CONTEXT_CHARS: usize = 40;
+
+/// Excerpt of an entry around where a search matched.
+///
+/// `highlights` are `[start, end)` ranges into `text` in UTF-16 code units,
+/// so the frontend can pass them straight to `String.prototype.slice`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snippet {
+    pub text: String,
+    pub highlights: Vec<[usize; 2]>,
+}
+
+impl Snippet {
+    /// Parse the output of `snippet(fts, 0, MATCH_START, MATCH_END, ...)`.
+    pub fn from_marked(marked: &str) -> Self {
+        let mut snippet = Snippet::default();
+        let mut offset = 0;
+        let mut start = None;
+
+        for c in marked.chars() {
+            match c {
+                MATCH_START => start = Some(offset),
+                MATCH_END => {
+                    if let Some(start) = start.take() {
+                        snippet.highlights.push([start, offset]);
+                    }
+                }
+                _ => {
+                    snippet.text.push(c);
+                    offset += c.len_utf16();
+                }
+            }
+        }
+
+        snippet
+    }
+
+    /// Build an excerpt of `text` around the matched character `positions`,
+    /// merging adjacent positions into single highlights.
+    pub fn around(text: &str, positions: &[usize]) -> Option<Self> {
+        let chars: Vec<char> = text.chars().collect();
+        let first = *positions.iter().min()?;
+        let last = *positions.iter().max()?;
+
+        let from = first.saturating_sub(CONTEXT_CHARS);
+        let to = (last + 1 + CONTEXT_CHARS).min(chars.len());
+
+        let mut snippet = Snippet::default();
+        if from > 0 {
+            snippet.text.push_str(ELLIPSIS);
+        }
+        let mut offset: usize = snippet.text.encode_utf16().count();
+
+        let mut sorted: Vec<usize> = positions.iter().copied().filter(|&p| p < chars.len()).collect();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let mut marks = sorted.into_iter().peekable();
+
+        for (i, &c) in chars.iter().enumerate().take(to).skip(from) {
+            let width = c.len_utf16();
+            if marks.peek() == Some(&i) {
+                marks.next();
+                match snippet.highlights.last_mut() {
+                    Some(range) if range[1] == offset => range[1] += width,
+                    _ => snippet.highlights.push([offset, offset + width]),
+                }
+            }
+            snippet.text.push(c);
+            offset += width;
+        }
+
+        if to < chars.len() {
+            snippet.text.push_str(ELLIPSIS);
+        }
+        Some(snippet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The highlighted parts of `snippet`, sliced the way the frontend does.
+    fn highlighted(snippet: &Snippet) -> Vec<String> {
+        let units: Vec<u16> = snippet.text.encode_utf16().collect();
+        snippet.highlights.iter().map(|&[start, end]| String::from_utf16(&units[start..end]).unwrap()).collect()
+    }
+
+    #[test]
+    fn marked_snippets_count_utf16_units() {
+        let snippet = Snippet::from_marked("say \u{2}héllo\u{3} to 🐎\u{2}horse\u{3}\u{3} now");
+        assert_eq!(snippet.text, "say héllo to 🐎horse now");
+        assert_eq!(snippet.highlights, vec![[4, 9], [15, 20]]);
+        assert_eq!(highlighted(&snippet), vec!["héllo", "horse"]);
+
+        let snippet = Snippet::from_marked("…\u{2}𝔘𝔫𝔦\u{3}code");
+        assert_eq!(snippet.highlights, vec![[1, 7]]);
+        assert_eq!(highlighted(&snippet), vec!["𝔘𝔫𝔦"]);
+    }
+
+    #[test]
+    fn adjacent_positions_merge_into_one_highlight() {
+        let snippet = Snippet::around("hello world", &[6, 0, 1, 2, 1, 99]).unwrap();
+        assert_eq!(snippet.text, "hello world");
+        assert_eq!(snippet.highlights, vec![[0, 3], [6, 7]]);
+        assert!(Snippet::around("hello world", &[]).is_none());
+    }
+
+    #[test]
+    fn long_entries_are_cut_with_ellipses() {
+        let text = format!("{}🐎X{}", "a".repeat(50), "b".repeat(50));
+        let snippet = Snippet::around(&text, &[50, 51]).unwrap();
+        assert_eq!(snippet.text, format!("{ELLIPSIS}{}🐎X{}{ELLIPSIS}", "a".repeat(40), "b".repeat(40)));
+        assert_eq!(snippet.highlights, vec![[41, 44]]);
+        assert_eq!(highlighted(&snippet), vec!["🐎X"]);
+
+        let start = Snippet::around(&text, &[3]).unwrap();
+        assert!(start.text.starts_with("aaa") && start.text.ends_with(ELLIPSIS));
+        assert_eq!(start.highlights, vec![[3, 4]]);
+        let end = Snippet::around(&text, &[101]).unwrap();
+        assert!(end.text.starts_with(ELLIPSIS) && end.text.ends_with('b'));
+        assert_eq!(highlighted(&end), vec!["b"]);
+    }
+}
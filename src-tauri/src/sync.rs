@@ -0,0 +1,613 @@
+//! Folder-based sync between devices.
+//!
+//! Each device appends operations to its own `<device id>.ndjson` log in a
+//! shared directory (a network share, Syncthing or Dropbox folder, ...), so
+//! no file is ever written by two machines. Every line is one operation:
+//!
+//! ```json
+//! {"time":1714470000000,"device":"3f0c...","seq":7,"entry":"9b2e...","op":"update","pinned":true}
+//! ```
+//!
+//! `op` is `create` (with `content`, `content_type`, `language`, `source_app`,
+//! `created_at`, `pinned`, `tags` and, for images, base64 `image`), `update`
+//! (any of `content`, `pinned`, `tags`) or `delete`. `entry` is the entry
+//! UUID. `time` is a hybrid logical clock in milliseconds that never runs
+//! behind any operation the device has seen.
+//!
+//! A pass first turns local changes since the previous pass into operations,
+//! then replays every log in `(time, device, seq)` order and makes the local
+//! history match. The outcome only depends on the set of operations, so all
+//! devices converge no matter in which order logs arrive: each field is
+//! last-writer-wins, and a `create` for a live entry changes nothing.
+//! Deletions propagate, including ones made by retention, and land in the
+//! other devices' trash.
+//!
+//! Logs hold entries in plaintext, so sync is refused while the history is
+//! encrypted.
+
+use crate::database::{blob_hash, Database};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Settings key holding the JSON-encoded sync configuration.
+pub const CONFIG_SETTING_KEY: &str = "sync_config";
+const DEVICE_ID_KEY: &str = "sync_device_id";
+const LOG_SUFFIX: &str = ".ndjson";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncScope {
+    /// Pinned entries only.
+    #[default]
+    Pinned,
+    All,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// Shared directory holding the logs; sync is off while unset.
+    pub directory: Option<String>,
+    pub scope: SyncScope,
+    pub interval_minutes: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            scope: SyncScope::Pinned,
+            interval_minutes: 5,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Load the configuration from settings, falling back to the defaults.
+    pub fn load(db: &Database) -> Self {
+        match db.get_setting(CONFIG_SETTING_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Invalid sync config, using defaults: {}", e);
+                Self::default()
+            }),
+            Ok(None) => Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read sync config: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        if let Some(dir) = &self.directory {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+        }
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        db.set_setting(CONFIG_SETTING_KEY, &json).map_err(|e| e.to_string())
+    }
+}
+
+/// Position of an operation in the global order. Field order matters: the
+/// derived `Ord` compares time, then device, then sequence number.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Clock {
+    pub time: u64,
+    pub device: String,
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Op {
+    #[serde(flatten)]
+    pub clock: Clock,
+    pub entry: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Change {
+    Create {
+        content: String,
+        content_type: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        source_app: Option<String>,
+        created_at: String,
+        pinned: bool,
+        tags: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image: Option<String>,
+    },
+    Update {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pinned: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<String>>,
+    },
+    Delete,
+}
+
+/// An entry as the local history holds it.
+#[derive(Debug, Clone)]
+pub struct LocalEntry {
+    pub uuid: String,
+    pub content: String,
+    pub content_type: String,
+    pub language: Option<String>,
+    pub source_app: Option<String>,
+    pub created_at: String,
+    pub is_pinned: bool,
+    pub tags: Vec<String>,
+    pub blob_hash: Option<String>,
+}
+
+/// The synced fields of an entry at the end of the last pass, kept in
+/// `sync_state` so the next pass can tell local edits from remote ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub content_hash: String,
+    pub is_pinned: bool,
+    pub tags: String,
+}
+
+impl Fingerprint {
+    pub fn new(content: &str, is_pinned: bool, tags: &[String]) -> Self {
+        Self {
+            content_hash: blob_hash(content.as_bytes()),
+            is_pinned,
+            tags: tag_key(tags),
+        }
+    }
+}
+
+/// Tag names in a canonical order, for comparing tag sets.
+pub fn tag_key(tags: &[String]) -> String {
+    let mut tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+    tags.sort_by_key(|tag| tag.to_lowercase());
+    tags.join("\n")
+}
+
+/// An entry as all logs together say it should be.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncedEntry {
+    pub uuid: String,
+    pub alive: bool,
+    pub content: String,
+    pub content_type: String,
+    pub language: Option<String>,
+    pub source_app: Option<String>,
+    pub created_at: String,
+    pub is_pinned: bool,
+    pub tags: Vec<String>,
+    /// Base64 encoded PNG for image entries.
+    pub image: Option<String>,
+}
+
+impl SyncedEntry {
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::new(&self.content, self.is_pinned, &self.tags)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// Operations this device appended to its log.
+    pub sent: usize,
+    /// Local entries created, changed or deleted to match the other devices.
+    pub applied: usize,
+}
+
+/// Run one sync pass against the configured directory.
+pub fn sync_now(db: &Database) -> Result<SyncReport, String> {
+    let config = SyncConfig::load(db);
+    let dir = config.directory.as_deref().ok_or("Sync is not configured")?;
+    sync_with(db, Path::new(dir), config.scope)
+}
+
+pub fn sync_with(db: &Database, dir: &Path, scope: SyncScope) -> Result<SyncReport, String> {
+    if db.encryption_status().enabled {
+        return Err("Sync writes entries in plaintext and is unavailable while encryption is enabled".to_string());
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let device = device_id(db)?;
+    let mut ops = read_logs(dir)?;
+    let mut seq = ops.iter().filter(|op| op.clock.device == device).map(|op| op.clock.seq).max().unwrap_or(0);
+    let mut time = ops.iter().map(|op| op.clock.time).max().unwrap_or(0);
+    let mut tick = || {
+        seq += 1;
+        time = time.max(Utc::now().timestamp_millis().max(0) as u64).saturating_add(1);
+        Clock { time, device: device.clone(), seq }
+    };
+
+    // Turn local changes since the last pass into operations
+    let local = db.sync_local_entries(scope == SyncScope::All).map_err(|e| e.to_string())?;
+    let states = db.sync_fingerprints().map_err(|e| e.to_string())?;
+    let mut outgoing = Vec::new();
+    for entry in &local {
+        let current = Fingerprint::new(&entry.content, entry.is_pinned, &entry.tags);
+        let change = match states.get(&entry.uuid) {
+            None => {
+                let image = match &entry.blob_hash {
+                    Some(hash) => db.get_blob(hash).map_err(|e| e.to_string())?.map(|data| base64_encode(&data)),
+                    None => None,
+                };
+                Change::Create {
+                    content: entry.content.clone(),
+                    content_type: entry.content_type.clone(),
+                    language: entry.language.clone(),
+                    source_app: entry.source_app.clone(),
+                    created_at: entry.created_at.clone(),
+                    pinned: entry.is_pinned,
+                    tags: entry.tags.clone(),
+                    image,
+                }
+            }
+            Some(previous) if *previous != current => Change::Update {
+                content: (previous.content_hash != current.content_hash).then(|| entry.content.clone()),
+                pinned: (previous.is_pinned != current.is_pinned).then_some(entry.is_pinned),
+                tags: (previous.tags != current.tags).then(|| entry.tags.clone()),
+            },
+            Some(_) => continue,
+        };
+        outgoing.push(Op { clock: tick(), entry: entry.uuid.clone(), change });
+    }
+    let present: HashSet<&str> = local.iter().map(|entry| entry.uuid.as_str()).collect();
+    let mut deleted: Vec<&String> = states.keys().filter(|uuid| !present.contains(uuid.as_str())).collect();
+    deleted.sort();
+    for uuid in deleted {
+        outgoing.push(Op { clock: tick(), entry: uuid.clone(), change: Change::Delete });
+    }
+
+    append_log(dir, &device, &outgoing)?;
+    let sent = outgoing.len();
+    ops.extend(outgoing);
+
+    let merged: Vec<SyncedEntry> = merge(ops).into_values().collect();
+    let applied = db.apply_synced(&merged)?;
+    Ok(SyncReport { sent, applied })
+}
+
+/// Replay operations in clock order into the state of every entry.
+pub fn merge(mut ops: Vec<Op>) -> BTreeMap<String, SyncedEntry> {
+    ops.sort_by(|a, b| a.clock.cmp(&b.clock));
+
+    let mut entries: BTreeMap<String, SyncedEntry> = BTreeMap::new();
+    for op in ops {
+        match op.change {
+            Change::Create { content, content_type, language, source_app, created_at, pinned, tags, image } => {
+                // The same clip copied on two devices shares a UUID; only the
+                // first create counts while the entry is alive
+                if entries.get(&op.entry).is_some_and(|entry| entry.alive) {
+                    continue;
+                }
+                entries.insert(op.entry.clone(), SyncedEntry {
+                    uuid: op.entry,
+                    alive: true,
+                    content,
+                    content_type,
+                    language,
+                    source_app,
+                    created_at,
+                    is_pinned: pinned,
+                    tags,
+                    image,
+                });
+            }
+            Change::Update { content, pinned, tags } => {
+                let Some(entry) = entries.get_mut(&op.entry) else {
+                    continue;
+                };
+                if let Some(content) = content {
+                    entry.content = content;
+                }
+                if let Some(pinned) = pinned {
+                    entry.is_pinned = pinned;
+                }
+                if let Some(tags) = tags {
+                    entry.tags = tags;
+                }
+            }
+            Change::Delete => {
+                if let Some(entry) = entries.get_mut(&op.entry) {
+                    entry.alive = false;
+                }
+            }
+        }
+    }
+    entries
+}
+
+fn device_id(db: &Database) -> Result<String, String> {
+    if let Some(id) = db.get_setting(DEVICE_ID_KEY).map_err(|e| e.to_string())? {
+        return Ok(id);
+    }
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    db.set_setting(DEVICE_ID_KEY, &id).map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+fn log_path(dir: &Path, device: &str) -> PathBuf {
+    dir.join(format!("{}{}", device, LOG_SUFFIX))
+}
+
+/// All operations in every log in `dir`. A torn last line, as left by a
+/// file still being copied in, is skipped and read on a later pass.
+fn read_logs(dir: &Path) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.to_string_lossy().ends_with(LOG_SUFFIX) {
+            continue;
+        }
+        let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<Op>(line) {
+                Ok(op) => ops.push(op),
+                Err(e) => eprintln!("Skipping unreadable sync operation in {}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(ops)
+}
+
+fn append_log(dir: &Path, device: &str, ops: &[Op]) -> Result<(), String> {
+    if ops.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for op in ops {
+        lines.push_str(&serde_json::to_string(op).map_err(|e| e.to_string())?);
+        lines.push('\n');
+    }
+    let path = log_path(dir, device);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    file.write_all(lines.as_bytes()).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    STANDARD.encode(data)
+}
+
+/// Sync every `interval_minutes` while a directory is configured. `db` is
+/// called each time so the pass follows the active profile.
+pub fn start_worker<F>(db: F)
+where
+    F: Fn() -> Arc<Database> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        let db = db();
+        let config = SyncConfig::load(&db);
+
+        if config.directory.is_some() && !db.encryption_status().enabled {
+            if let Err(e) = sync_now(&db) {
+                eprintln!("Sync error: {}", e);
+            }
+        }
+        drop(db);
+
+        thread::sleep(Duration::from_secs(config.interval_minutes.max(1) * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Device {
+        db: Database,
+        dir: PathBuf,
+    }
+
+    impl Device {
+        fn new(root: &Path, name: &str) -> Self {
+            let dir = root.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            let db = Database::open(&dir.join("clipboard.db")).unwrap();
+            Self { db, dir: dir.join("sync") }
+        }
+
+        fn sync(&self) -> SyncReport {
+            sync_with(&self.db, &self.dir, SyncScope::Pinned).unwrap()
+        }
+
+        fn pinned(&self) -> Vec<(String, Vec<String>)> {
+            let mut pinned: Vec<(String, Vec<String>)> = self
+                .db
+                .sync_local_entries(false)
+                .unwrap()
+                .into_iter()
+                .filter(|entry| entry.is_pinned)
+                .map(|entry| (entry.content, entry.tags))
+                .collect();
+            pinned.sort();
+            pinned
+        }
+
+        fn id_of(&self, content: &str) -> i64 {
+            self.db.insert(content, None, None).unwrap()
+        }
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("clipstream-sync-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    /// Copy every log from one device's sync directory to the other's, as a
+    /// file sync tool would.
+    fn replicate(from: &Device, to: &Device) {
+        fs::create_dir_all(&to.dir).unwrap();
+        for entry in fs::read_dir(&from.dir).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, to.dir.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+
+    fn exchange(a: &Device, b: &Device) {
+        a.sync();
+        b.sync();
+        replicate(a, b);
+        replicate(b, a);
+        a.sync();
+        b.sync();
+    }
+
+    #[test]
+    fn pinned_entries_follow_across_devices() {
+        let root = temp_root("follow");
+        let desktop = Device::new(&root, "desktop");
+        let laptop = Device::new(&root, "laptop");
+
+        let id = desktop.id_of("ssh deploy@prod");
+        desktop.db.toggle_pin(id).unwrap();
+        desktop.db.insert("not pinned", None, None).unwrap();
+        exchange(&desktop, &laptop);
+        assert_eq!(laptop.pinned(), vec![("ssh deploy@prod".to_string(), vec![])]);
+
+        // Edits and tags made on the laptop come back
+        let laptop_id = laptop.id_of("ssh deploy@prod");
+        laptop.db.update_content(laptop_id, "ssh deploy@staging").unwrap();
+        let tag = laptop.db.create_tag("ops").unwrap();
+        laptop.db.tag_entries(tag.id, &[laptop_id]).unwrap();
+        exchange(&laptop, &desktop);
+        assert_eq!(desktop.pinned(), vec![("ssh deploy@staging".to_string(), vec!["ops".to_string()])]);
+
+        // Deleting on one side moves it to the trash on the other
+        desktop.db.delete(id).unwrap();
+        exchange(&desktop, &laptop);
+        assert!(laptop.pinned().is_empty());
+        assert!(laptop.db.get_by_id(laptop_id).unwrap().unwrap().deleted_at.is_some());
+    }
+
+    #[test]
+    fn concurrent_changes_converge() {
+        let root = temp_root("converge");
+        let a = Device::new(&root, "a");
+        let b = Device::new(&root, "b");
+
+        let id = a.id_of("shared");
+        a.db.toggle_pin(id).unwrap();
+        exchange(&a, &b);
+
+        // Both edit the same entry before seeing each other's change; one
+        // also unpins it. Field-wise last writer wins on both sides.
+        let b_id = b.id_of("shared");
+        a.db.update_content(id, "edited on a").unwrap();
+        b.db.update_content(b_id, "edited on b").unwrap();
+        b.db.toggle_pin(b_id).unwrap();
+        a.sync();
+        b.sync();
+        replicate(&b, &a);
+        replicate(&a, &b);
+        a.sync();
+        b.sync();
+
+        let on_a = a.db.get_by_id(id).unwrap().unwrap();
+        let on_b = b.db.get_by_id(b_id).unwrap().unwrap();
+        assert_eq!(on_a.content, on_b.content);
+        assert_eq!(on_a.is_pinned, on_b.is_pinned);
+        assert!(!on_a.is_pinned);
+
+        // A further pass with nothing new changes nothing
+        assert_eq!(a.sync().sent, 0);
+        assert_eq!(b.sync().applied, 0);
+    }
+
+    #[test]
+    fn the_same_clip_on_two_devices_is_one_entry() {
+        let root = temp_root("same");
+        let a = Device::new(&root, "a");
+        let b = Device::new(&root, "b");
+
+        let a_id = a.id_of("copied everywhere");
+        let b_id = b.id_of("copied everywhere");
+        a.db.toggle_pin(a_id).unwrap();
+        b.db.toggle_pin(b_id).unwrap();
+        exchange(&a, &b);
+
+        assert_eq!(a.pinned().len(), 1);
+        assert_eq!(b.pinned().len(), 1);
+    }
+
+    #[test]
+    fn merge_does_not_depend_on_log_order() {
+        let clock = |time, device: &str, seq| Clock { time, device: device.to_string(), seq };
+        let create = Change::Create {
+            content: "x".to_string(),
+            content_type: "text".to_string(),
+            language: None,
+            source_app: None,
+            created_at: "2024-01-01 00:00:00".to_string(),
+            pinned: true,
+            tags: vec![],
+            image: None,
+        };
+        let ops = vec![
+            Op { clock: clock(1, "a", 1), entry: "e".to_string(), change: create },
+            Op { clock: clock(5, "b", 1), entry: "e".to_string(), change: Change::Update { content: Some("from b".to_string()), pinned: None, tags: None } },
+            Op { clock: clock(5, "a", 2), entry: "e".to_string(), change: Change::Update { content: Some("from a".to_string()), pinned: Some(false), tags: None } },
+        ];
+        let mut reversed = ops.clone();
+        reversed.reverse();
+
+        let forward = merge(ops);
+        assert_eq!(forward, merge(reversed));
+        let entry = &forward["e"];
+        assert_eq!(entry.content, "from b");
+        assert!(!entry.is_pinned);
+    }
+
+    #[test]
+    fn synced_entries_keep_their_type() {
+        let root = temp_root("type");
+        let laptop = Device::new(&root, "laptop");
+        let synced = |uuid: &str, content: &str, content_type: &str, language: Option<&str>| SyncedEntry {
+            uuid: uuid.to_string(),
+            alive: true,
+            content: content.to_string(),
+            content_type: content_type.to_string(),
+            language: language.map(str::to_string),
+            source_app: None,
+            created_at: "2024-01-01 00:00:00".to_string(),
+            is_pinned: true,
+            tags: vec![],
+            image: None,
+        };
+        laptop.db.apply_synced(&[
+            synced("a", "fn main() {}", "text", None),
+            synced("b", "SELECT 1 FROM t", "code", Some("sql")),
+        ]).unwrap();
+
+        let mut kinds: Vec<(String, String, Option<String>)> = laptop
+            .db
+            .sync_local_entries(true)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.content, entry.content_type, entry.language))
+            .collect();
+        kinds.sort();
+        assert_eq!(kinds, vec![
+            ("SELECT 1 FROM t".to_string(), "code".to_string(), Some("sql".to_string())),
+            ("fn main() {}".to_string(), "text".to_string(), None),
+        ]);
+    }
+}
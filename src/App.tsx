@@ -7,9 +7,34 @@ interface ClipboardEntry {
   content: string;
   source_app: string | null;
   content_type: string;
+  language: string | null;
   created_at: string;
   is_pinned: boolean;
-  content_blob: string | null;
+  thumbnail: string | null;
+  tags: string[];
+  sensitivity: string | null;
+  expires_at: string | null;
+  snippet: Snippet | null;
+}
+
+interface SearchPage {
+  entries: ClipboardEntry[];
+  total: number;
+  next_offset: number | null;
+}
+
+const PAGE_SIZE = 50;
+const EXPIRY_MINUTES = 5;
+
+interface Snippet {
+  text: string;
+  highlights: [number, number][];
+}
+
+interface EncryptionStatus {
+  enabled: boolean;
+  locked: boolean;
+  mode: string | null;
 }
 
 type ViewMode = 'list' | 'settings';
@@ -54,6 +79,12 @@ const Icons = {
       <path d="M9.828.722a.5.5 0 0 1 .354.146l4.95 4.95a.5.5 0 0 1 0 .707c-.48.48-1.072.588-1.503.588-.177 0-.335-.018-.46-.039l-3.134 3.134a5.927 5.927 0 0 1 .16 1.013c.046.702-.032 1.687-.72 2.375a.5.5 0 0 1-.707 0l-2.829-2.828-3.182 3.182c-.195.195-1.219.902-1.414.707-.195-.195.512-1.22.707-1.414l3.182-3.182-2.828-2.829a.5.5 0 0 1 0-.707c.688-.688 1.673-.767 2.375-.72a5.922 5.922 0 0 1 1.013.16l3.134-3.133a2.772 2.772 0 0 1-.04-.461c0-.43.108-1.022.589-1.503a.5.5 0 0 1 .353-.146z" />
     </svg>
   ),
+  clock: (
+    <svg width="12" height="12" viewBox="0 0 16 16" fill="currentColor">
+      <path d="M8 3.5a.5.5 0 0 0-1 0V9a.5.5 0 0 0 .252.434l3.5 2a.5.5 0 0 0 .496-.868L8 8.71V3.5z" />
+      <path d="M8 16A8 8 0 1 0 8 0a8 8 0 0 0 0 16zm7-8A7 7 0 1 1 1 8a7 7 0 0 1 14 0z" />
+    </svg>
+  ),
   pinOutline: (
     <svg width="12" height="12" viewBox="0 0 16 16" fill="currentColor">
       <path d="M9.828.722a.5.5 0 0 1 .354.146l4.95 4.95a.5.5 0 0 1 0 .707c-.48.48-1.072.588-1.503.588-.177 0-.335-.018-.46-.039l-3.134 3.134a5.927 5.927 0 0 1 .16 1.013c.046.702-.032 1.687-.72 2.375a.5.5 0 0 1-.707 0l-2.829-2.828-3.182 3.182c-.195.195-1.219.902-1.414.707-.195-.195.512-1.22.707-1.414l3.182-3.182-2.828-2.829a.5.5 0 0 1 0-.707c.688-.688 1.673-.767 2.375-.72a5.922 5.922 0 0 1 1.013.16l3.134-3.133a2.772 2.772 0 0 1-.04-.461c0-.43.108-1.022.589-1.503a.5.5 0 0 1 .353-.146zm.122 2.112v-.002.002zm0-.002v.002a.5.5 0 0 1-.122.51L6.293 6.878a.5.5 0 0 1-.511.12H5.78l-.014-.004a4.507 4.507 0 0 0-.288-.076 4.922 4.922 0 0 0-.765-.116c-.422-.028-.836.008-1.175.15l5.51 5.509c.141-.34.177-.753.149-1.175a4.924 4.924 0 0 0-.192-1.054l-.004-.013v-.001a.5.5 0 0 1 .12-.512l3.536-3.535a.5.5 0 0 1 .532-.115l.096.022c.087.017.208.034.344.034.114 0 .23-.011.343-.04L9.927 2.028c-.029.113-.04.23-.04.343a1.779 1.779 0 0 0 .062.46z" />
@@ -129,7 +160,9 @@ function getAppIcon(app: string | null, contentType: string) {
   }
 
   if (contentType === 'url') return Icons.link;
-  if (contentType === 'code') return Icons.code;
+  if (contentType === 'shell') return Icons.terminal;
+  if (contentType === 'path') return Icons.file;
+  if (['code', 'json', 'yaml', 'xml', 'html'].includes(contentType)) return Icons.code;
 
   return Icons.text;
 }
@@ -145,49 +178,113 @@ function formatTime(dateStr: string): string {
   return `${Math.floor(diff / 86400)}d`;
 }
 
+function formatExpiry(dateStr: string): string {
+  const date = new Date(dateStr + 'Z');
+  const remaining = Math.max(0, Math.floor((date.getTime() - Date.now()) / 1000));
+
+  if (remaining < 60) return 'expires now';
+  if (remaining < 3600) return `expires in ${Math.ceil(remaining / 60)}m`;
+  if (remaining < 86400) return `expires in ${Math.round(remaining / 3600)}h`;
+  return `expires in ${Math.round(remaining / 86400)}d`;
+}
+
 function getAppDisplayName(app: string | null): string {
   if (!app) return 'Unknown';
   return app.replace('.exe', '').replace('.EXE', '').replace('.app', '');
 }
 
+function renderSnippet(snippet: Snippet) {
+  const parts = [];
+  let pos = 0;
+  snippet.highlights.forEach(([start, end], i) => {
+    if (start > pos) parts.push(snippet.text.slice(pos, start));
+    parts.push(<mark key={i} className="match">{snippet.text.slice(start, end)}</mark>);
+    pos = end;
+  });
+  if (pos < snippet.text.length) parts.push(snippet.text.slice(pos));
+  return parts;
+}
+
+type PatternKind = 'substring' | 'glob' | 'regex';
+type MatchField = 'process' | 'title' | 'either';
+type RuleList = 'ignore' | 'allow';
+
+interface AppRule {
+  pattern: string;
+  kind: PatternKind;
+  field: MatchField;
+  list: RuleList;
+}
+
+interface AppRules {
+  mode: 'all' | 'allowlist';
+  rules: AppRule[];
+}
+
+const FIELD_LABELS: Record<MatchField, string> = {
+  process: 'app',
+  title: 'window title',
+  either: 'app or title',
+};
+
 // Settings View Component
 function SettingsView({ onBack }: { onBack: () => void }) {
-  const [ignoredApps, setIgnoredApps] = useState<string[]>([]);
-  const [newApp, setNewApp] = useState('');
+  const [appRules, setAppRules] = useState<AppRules>({ mode: 'all', rules: [] });
+  const [newRule, setNewRule] = useState<AppRule>({ pattern: '', kind: 'substring', field: 'either', list: 'ignore' });
+  const [ruleError, setRuleError] = useState('');
+  const [ignoreWhitespace, setIgnoreWhitespace] = useState(false);
 
   useEffect(() => {
-    loadIgnoredApps();
+    loadAppRules();
+    invoke<string>('get_dedup_mode')
+      .then(mode => setIgnoreWhitespace(mode === 'whitespace'))
+      .catch(e => console.error('Failed to load dedup mode:', e));
   }, []);
 
-  const loadIgnoredApps = async () => {
+  const toggleIgnoreWhitespace = async () => {
+    const next = !ignoreWhitespace;
     try {
-      const apps = await invoke<string[]>('get_ignored_apps');
-      setIgnoredApps(apps);
+      await invoke('set_dedup_mode', { mode: next ? 'whitespace' : 'exact' });
+      setIgnoreWhitespace(next);
     } catch (e) {
-      console.error('Failed to load ignored apps:', e);
+      console.error('Failed to set dedup mode:', e);
     }
   };
 
-  const addApp = async () => {
-    if (!newApp.trim()) return;
+  const loadAppRules = async () => {
     try {
-      await invoke('add_ignored_app', { appName: newApp.trim() });
-      setNewApp('');
-      await loadIgnoredApps();
+      setAppRules(await invoke<AppRules>('get_app_rules'));
     } catch (e) {
-      console.error('Failed to add app:', e);
+      console.error('Failed to load app rules:', e);
     }
   };
 
-  const removeApp = async (appName: string) => {
+  const saveAppRules = async (next: AppRules) => {
     try {
-      await invoke('remove_ignored_app', { appName });
-      await loadIgnoredApps();
+      await invoke('set_app_rules', { rules: next });
+      setAppRules(next);
+      setRuleError('');
+      return true;
     } catch (e) {
-      console.error('Failed to remove app:', e);
+      setRuleError(String(e));
+      return false;
+    }
+  };
+
+  const addRule = async () => {
+    if (!newRule.pattern.trim()) return;
+    const rule = { ...newRule, pattern: newRule.pattern.trim() };
+    if (await saveAppRules({ ...appRules, rules: [...appRules.rules, rule] })) {
+      setNewRule({ ...newRule, pattern: '' });
     }
   };
 
+  const removeRule = (index: number) =>
+    saveAppRules({ ...appRules, rules: appRules.rules.filter((_, i) => i !== index) });
+
+  const toggleAllowlist = () =>
+    saveAppRules({ ...appRules, mode: appRules.mode === 'allowlist' ? 'all' : 'allowlist' });
+
   return (
     <div className="settings-view">
       <div className="settings-header">
@@ -196,34 +293,130 @@ function SettingsView({ onBack }: { onBack: () => void }) {
       </div>
 
       <div className="settings-section">
-        <h3>Ignored Apps</h3>
-        <p className="settings-desc">Clipboard from these apps won't be saved</p>
+        <h3>App Rules</h3>
+        <p className="settings-desc">Clipboard from ignored apps or windows won't be saved</p>
+
+        <label className="settings-desc">
+          <input type="checkbox" checked={appRules.mode === 'allowlist'} onChange={toggleAllowlist} />
+          {' '}Only save clipboard from allowed apps
+        </label>
 
         <div className="add-app-row">
           <input
             type="text"
-            value={newApp}
-            onInput={(e) => setNewApp((e.target as HTMLInputElement).value)}
-            onKeyDown={(e) => e.key === 'Enter' && addApp()}
-            placeholder="App name (e.g., 1Password)"
+            value={newRule.pattern}
+            onInput={(e) => setNewRule({ ...newRule, pattern: (e.target as HTMLInputElement).value })}
+            onKeyDown={(e) => e.key === 'Enter' && addRule()}
+            placeholder="Pattern (e.g., 1Password, *Private Browsing*)"
             className="app-input"
           />
-          <button onClick={addApp} className="add-btn">{Icons.plus}</button>
+          <button onClick={addRule} className="add-btn">{Icons.plus}</button>
         </div>
+        <div className="add-app-row">
+          <select
+            value={newRule.list}
+            onChange={(e) => setNewRule({ ...newRule, list: (e.target as HTMLSelectElement).value as RuleList })}
+            className="app-input"
+          >
+            <option value="ignore">Ignore</option>
+            <option value="allow">Allow</option>
+          </select>
+          <select
+            value={newRule.field}
+            onChange={(e) => setNewRule({ ...newRule, field: (e.target as HTMLSelectElement).value as MatchField })}
+            className="app-input"
+          >
+            <option value="either">App or title</option>
+            <option value="process">App</option>
+            <option value="title">Window title</option>
+          </select>
+          <select
+            value={newRule.kind}
+            onChange={(e) => setNewRule({ ...newRule, kind: (e.target as HTMLSelectElement).value as PatternKind })}
+            className="app-input"
+          >
+            <option value="substring">Contains</option>
+            <option value="glob">Glob</option>
+            <option value="regex">Regex</option>
+          </select>
+        </div>
+        {ruleError && <p className="settings-desc">{ruleError}</p>}
 
         <div className="ignored-apps-list">
-          {ignoredApps.length === 0 ? (
-            <div className="empty-apps">No ignored apps</div>
+          {appRules.rules.length === 0 ? (
+            <div className="empty-apps">No app rules</div>
           ) : (
-            ignoredApps.map((app) => (
-              <div key={app} className="ignored-app-item">
-                <span>{app}</span>
-                <button onClick={() => removeApp(app)} className="remove-btn">{Icons.trash}</button>
+            appRules.rules.map((rule, index) => (
+              <div key={index} className="ignored-app-item">
+                <span>{rule.pattern}</span>
+                <span className="rule-meta">{rule.list} · {FIELD_LABELS[rule.field]} · {rule.kind}</span>
+                <button onClick={() => removeRule(index)} className="remove-btn">{Icons.trash}</button>
               </div>
             ))
           )}
         </div>
       </div>
+
+      <div className="settings-section">
+        <h3>Duplicates</h3>
+        <label className="settings-desc">
+          <input type="checkbox" checked={ignoreWhitespace} onChange={toggleIgnoreWhitespace} />
+          {' '}Treat clips that differ only in whitespace as the same
+        </label>
+      </div>
+    </div>
+  );
+}
+
+// Unlock View Component
+function UnlockView({ status, onUnlocked }: { status: EncryptionStatus; onUnlocked: () => void }) {
+  const [passphrase, setPassphrase] = useState('');
+  const [error, setError] = useState('');
+  const needsPassphrase = status.mode === 'passphrase';
+
+  const unlock = async () => {
+    try {
+      await invoke('unlock_history', { passphrase: needsPassphrase ? passphrase : null });
+      setPassphrase('');
+      setError('');
+      onUnlocked();
+    } catch (e) {
+      setError(String(e));
+    }
+  };
+
+  return (
+    <div className="settings-view">
+      <div className="settings-header">
+        <span>History Locked</span>
+      </div>
+
+      <div className="settings-section">
+        <p className="settings-desc">
+          {needsPassphrase ? 'Enter your passphrase to unlock' : 'Key file could not be read'}
+        </p>
+
+        <div className="add-app-row">
+          {needsPassphrase && (
+            <input
+              type="password"
+              value={passphrase}
+              onInput={(e) => setPassphrase((e.target as HTMLInputElement).value)}
+              onKeyDown={(e) => e.key === 'Enter' && unlock()}
+              placeholder="Passphrase"
+              className="app-input"
+              autoFocus
+            />
+          )}
+          <button onClick={unlock} className="add-btn">{Icons.back}</button>
+        </div>
+
+        {error && <p className="settings-desc">{error}</p>}
+      </div>
+
+      <div className="settings-section">
+        <p className="settings-desc">Clipboard capture is paused until the history is unlocked</p>
+      </div>
     </div>
   );
 }
@@ -237,21 +430,85 @@ export function App() {
   const [editingId, setEditingId] = useState<number | null>(null);
   const [editText, setEditText] = useState('');
   const [showPasteMenu, setShowPasteMenu] = useState(false);
+  const [lockStatus, setLockStatus] = useState<EncryptionStatus | null>(null);
+  const [queryError, setQueryError] = useState<string | null>(null);
+  const [fuzzy, setFuzzy] = useState(false);
+  const [frecency, setFrecency] = useState(false);
+  const [total, setTotal] = useState(0);
+  const [nextOffset, setNextOffset] = useState<number | null>(null);
+  // How many rows the periodic refresh reloads, grown as the list scrolls
+  const loadedCount = useRef(PAGE_SIZE);
+  const loadingMore = useRef(false);
   const searchRef = useRef<HTMLInputElement>(null);
   const listRef = useRef<HTMLDivElement>(null);
   const editRef = useRef<HTMLInputElement>(null);
 
   const loadEntries = useCallback(async () => {
     try {
-      const results = await invoke<ClipboardEntry[]>('search_history', { query });
-      setEntries(results);
-      if (selectedIndex >= results.length) {
-        setSelectedIndex(Math.max(0, results.length - 1));
+      const status = await invoke<EncryptionStatus>('get_encryption_status');
+      if (status.locked) {
+        setLockStatus(status);
+        setEntries([]);
+        return;
+      }
+      setLockStatus(null);
+
+      const page = await invoke<SearchPage>('search_history', {
+        query,
+        mode: fuzzy ? 'fuzzy' : 'auto',
+        sort: frecency ? 'frecency' : 'relevance',
+        offset: 0,
+        pageSize: loadedCount.current,
+      });
+      setQueryError(null);
+      setEntries(page.entries);
+      setTotal(page.total);
+      setNextOffset(page.next_offset);
+      if (selectedIndex >= page.entries.length) {
+        setSelectedIndex(Math.max(0, page.entries.length - 1));
       }
     } catch (e) {
+      if (typeof e === 'string' && e.startsWith('Invalid search')) {
+        setQueryError(e);
+        setEntries([]);
+        return;
+      }
       console.error('Failed to load entries:', e);
     }
-  }, [query]);
+  }, [query, fuzzy, frecency]);
+
+  const loadMore = useCallback(async () => {
+    if (nextOffset === null || loadingMore.current) return;
+    loadingMore.current = true;
+    try {
+      const page = await invoke<SearchPage>('search_history', {
+        query,
+        mode: fuzzy ? 'fuzzy' : 'auto',
+        sort: frecency ? 'frecency' : 'relevance',
+        offset: nextOffset,
+        pageSize: PAGE_SIZE,
+      });
+      setEntries(prev => [...prev.slice(0, nextOffset), ...page.entries]);
+      setTotal(page.total);
+      setNextOffset(page.next_offset);
+      loadedCount.current = nextOffset + page.entries.length;
+    } catch (e) {
+      console.error('Failed to load more entries:', e);
+    } finally {
+      loadingMore.current = false;
+    }
+  }, [query, fuzzy, frecency, nextOffset]);
+
+  const handleListScroll = () => {
+    const list = listRef.current;
+    if (list && list.scrollTop + list.clientHeight >= list.scrollHeight - 100) {
+      loadMore();
+    }
+  };
+
+  useEffect(() => {
+    loadedCount.current = PAGE_SIZE;
+  }, [query, fuzzy, frecency]);
 
   useEffect(() => {
     const mediaQuery = window.matchMedia('(prefers-color-scheme: dark)');
@@ -278,7 +535,10 @@ export function App() {
       const selectedEl = listRef.current.children[selectedIndex] as HTMLElement;
       selectedEl?.scrollIntoView({ block: 'nearest' });
     }
-  }, [selectedIndex, entries.length]);
+    if (selectedIndex >= entries.length - 5) {
+      loadMore();
+    }
+  }, [selectedIndex, entries.length, loadMore]);
 
   useEffect(() => {
     if (editingId !== null && editRef.current) {
@@ -329,6 +589,12 @@ export function App() {
           await handleDelete(entries[selectedIndex].id);
         }
         break;
+      case 'z':
+        if (e.ctrlKey) {
+          e.preventDefault();
+          await handleUndoDelete();
+        }
+        break;
       case 'F2':
         if (entries[selectedIndex] && entries[selectedIndex].content_type !== 'image') {
           e.preventDefault();
@@ -395,6 +661,17 @@ export function App() {
     }
   };
 
+  // Expire the entry after a few minutes, or keep it again
+  const handleExpiry = async (entry: ClipboardEntry, e: MouseEvent) => {
+    e.stopPropagation();
+    try {
+      await invoke('set_entry_expiry', { id: entry.id, minutes: entry.expires_at ? null : EXPIRY_MINUTES });
+      await loadEntries();
+    } catch (err) {
+      console.error('Failed to set expiry:', err);
+    }
+  };
+
   const handleDelete = async (id: number, e?: MouseEvent) => {
     e?.stopPropagation();
     try {
@@ -405,6 +682,23 @@ export function App() {
     }
   };
 
+  const handleUndoDelete = async () => {
+    try {
+      await invoke('undo_delete');
+      await loadEntries();
+    } catch (err) {
+      console.error('Failed to undo delete:', err);
+    }
+  };
+
+  if (lockStatus) {
+    return (
+      <div className={`app-container ${isDark ? 'dark' : 'light'}`}>
+        <UnlockView status={lockStatus} onUnlocked={loadEntries} />
+      </div>
+    );
+  }
+
   if (viewMode === 'settings') {
     return (
       <div className={`app-container ${isDark ? 'dark' : 'light'}`}>
@@ -425,20 +719,35 @@ export function App() {
           type="text"
           value={query}
           onInput={(e) => { setQuery((e.target as HTMLInputElement).value); setSelectedIndex(0); }}
-          placeholder="Search..."
+          placeholder="Search... (app:, type:, lang:, tag:, -word)"
+          title={queryError ?? undefined}
           className="search-input"
         />
+        <button
+          onClick={() => setFuzzy(!fuzzy)}
+          className={`settings-btn ${fuzzy ? 'active' : ''}`}
+          title="Fuzzy search"
+        >
+          ~
+        </button>
+        <button
+          onClick={() => setFrecency(!frecency)}
+          className={`settings-btn ${frecency ? 'active' : ''}`}
+          title="Sort by most used"
+        >
+          ★
+        </button>
         <button onClick={() => setViewMode('settings')} className="settings-btn" title="Settings">
           {Icons.settings}
         </button>
-        <span className="item-count">{entries.length}</span>
+        <span className="item-count">{total}</span>
       </div>
 
-      <div ref={listRef} className="entries-list">
+      <div ref={listRef} className="entries-list" onScroll={handleListScroll}>
         {entries.length === 0 ? (
           <div className="empty-state">
             <span className="empty-icon">{Icons.clipboard}</span>
-            <p>{query ? 'No results' : 'Empty'}</p>
+            <p>{queryError ?? (query ? 'No results' : 'Empty')}</p>
           </div>
         ) : (
           entries.map((entry, index) => (
@@ -462,18 +771,44 @@ export function App() {
                     onBlur={saveEdit}
                     className="edit-input"
                   />
-                ) : entry.content_type === 'image' && entry.content_blob ? (
+                ) : entry.content_type === 'image' && entry.thumbnail ? (
                   <div className="image-preview">
-                    <img src={`data:image/png;base64,${entry.content_blob}`} alt="Clipboard" />
+                    <img src={`data:image/png;base64,${entry.thumbnail}`} alt="Clipboard" />
                     <span className="image-label">{entry.content}</span>
                   </div>
                 ) : (
-                  <p className="entry-text">{entry.content}</p>
+                  <p className="entry-text">
+                    {entry.snippet ? renderSnippet(entry.snippet) : entry.content}
+                  </p>
                 )}
                 <div className="entry-meta">
                   <span>{getAppDisplayName(entry.source_app)}</span>
                   <span>·</span>
                   <span>{formatTime(entry.created_at)}</span>
+                  {entry.language && (
+                    <>
+                      <span>·</span>
+                      <span>{entry.language}</span>
+                    </>
+                  )}
+                  {entry.tags.length > 0 && (
+                    <>
+                      <span>·</span>
+                      <span>{entry.tags.map(tag => `#${tag}`).join(' ')}</span>
+                    </>
+                  )}
+                  {entry.sensitivity && (
+                    <>
+                      <span>·</span>
+                      <span className="sensitive-indicator">{entry.sensitivity.replace('_', ' ')}</span>
+                    </>
+                  )}
+                  {entry.expires_at && (
+                    <>
+                      <span>·</span>
+                      <span>{formatExpiry(entry.expires_at)}</span>
+                    </>
+                  )}
                 </div>
               </div>
 
@@ -491,6 +826,13 @@ export function App() {
                     <button onClick={(e) => handlePin(entry.id, e)} className="action-btn" title="Pin">
                       {Icons.pinOutline}
                     </button>
+                    <button
+                      onClick={(e) => handleExpiry(entry, e)}
+                      className="action-btn"
+                      title={entry.expires_at ? 'Keep' : `Expire in ${EXPIRY_MINUTES} minutes`}
+                    >
+                      {Icons.clock}
+                    </button>
                     <button onClick={(e) => handleCopy(entry.id, e)} className="action-btn" title="Copy">
                       {Icons.copy}
                     </button>